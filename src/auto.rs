@@ -0,0 +1,373 @@
+use std::cmp::Ordering;
+
+use alsa::Direction;
+
+use colored::*;
+
+use comfy_table::{
+    modifiers::UTF8_SOLID_INNER_BORDERS, presets::UTF8_FULL, Attribute, Cell, ContentArrangement,
+    Table,
+};
+
+use crate::{ValidConfiguration, FORMATS};
+
+const RANKING_TABLE_LEN: usize = 5;
+
+// How --auto decides which ValidConfiguration is "best".
+// The criteria are compared in order, a later criterion
+// only matters if all of the earlier ones are tied:
+//
+// 1. The Sampling Rate is a multiple of the preferred family (48000 or 44100).
+// 2. The Format is deeper (later in FORMATS).
+// 3. The Channel Count is the native (largest) Channel Count of the PCM,
+//    or the preferred Channel Count if one was given.
+// 4. Within the family the lowest multiple wins, outside of it the highest Rate wins.
+//
+// The Buffer Time is chosen afterwards, as close to
+// buffer_time_ms as the winning Configuration allows.
+#[derive(Debug, Clone)]
+pub struct ScoringPolicy {
+    pub rate_family: u32,
+    pub channels: Option<u32>,
+    pub buffer_time_ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Score {
+    rate_family: bool,
+    format_depth: usize,
+    native_channels: bool,
+    rate_preference: i64,
+}
+
+impl Score {
+    fn cmp_criteria(&self, other: &Self) -> (Ordering, &'static str) {
+        let criteria = [
+            (
+                self.rate_family.cmp(&other.rate_family),
+                "its Sampling Rate is in the preferred family",
+            ),
+            (
+                self.format_depth.cmp(&other.format_depth),
+                "it has a deeper Format",
+            ),
+            (
+                self.native_channels.cmp(&other.native_channels),
+                "it uses the preferred Channel Count",
+            ),
+            (
+                self.rate_preference.cmp(&other.rate_preference),
+                "its Sampling Rate is a better fit",
+            ),
+        ];
+
+        criteria
+            .into_iter()
+            .find(|(ordering, _)| *ordering != Ordering::Equal)
+            .unwrap_or((Ordering::Equal, "it was found first"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RankedConfiguration {
+    pub config: ValidConfiguration,
+    score: Score,
+}
+
+impl ScoringPolicy {
+    pub fn rank(&self, configs: Vec<ValidConfiguration>) -> Vec<RankedConfiguration> {
+        let mut ranked: Vec<RankedConfiguration> = configs
+            .iter()
+            .map(|config| {
+                // The native Channel Count is per PCM.
+                let native_channels = configs
+                    .iter()
+                    .filter(|c| c.name == config.name)
+                    .map(|c| c.channels)
+                    .max()
+                    .unwrap_or(config.channels);
+
+                let rate_family = config.rate % self.rate_family == 0;
+
+                RankedConfiguration {
+                    score: Score {
+                        rate_family,
+                        format_depth: FORMATS
+                            .iter()
+                            .position(|f| *f == config.format)
+                            .unwrap_or_default(),
+                        native_channels: config.channels
+                            == self.channels.unwrap_or(native_channels),
+                        rate_preference: if rate_family {
+                            -i64::from(config.rate)
+                        } else {
+                            i64::from(config.rate)
+                        },
+                    },
+                    config: config.clone(),
+                }
+            })
+            .collect();
+
+        // A stable sort so that ties go to the first one found.
+        ranked.sort_by(|a, b| b.score.cmp_criteria(&a.score).0);
+
+        ranked
+    }
+}
+
+pub fn show_ranking(ranked: &[RankedConfiguration], direction: Direction) {
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(vec![
+            Cell::new("RANK").add_attribute(Attribute::Bold),
+            Cell::new("PCM").add_attribute(Attribute::Bold),
+            Cell::new("FORMAT").add_attribute(Attribute::Bold),
+            Cell::new("RATE").add_attribute(Attribute::Bold),
+            Cell::new("CHANNELS").add_attribute(Attribute::Bold),
+            Cell::new("RATE FAMILY").add_attribute(Attribute::Bold),
+            Cell::new("PREFERRED CHANNELS").add_attribute(Attribute::Bold),
+        ]);
+
+    for (i, ranked) in ranked.iter().take(RANKING_TABLE_LEN).enumerate() {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        table.add_row(vec![
+            Cell::new(i + 1),
+            Cell::new(&ranked.config.name),
+            Cell::new(ranked.config.format),
+            Cell::new(ranked.config.rate),
+            Cell::new(ranked.config.channels),
+            Cell::new(yes_no(ranked.score.rate_family)),
+            Cell::new(yes_no(ranked.score.native_channels)),
+        ]);
+    }
+
+    println!(
+        "{}",
+        format!(
+            "\nThe top ranked {direction:?} Configurations ({} in total):",
+            ranked.len()
+        )
+        .cyan()
+    );
+
+    println!("\n{table}");
+
+    if let Some(choice) = explain_choice(ranked) {
+        println!("{}", choice.cyan());
+    }
+}
+
+// Why the first of ranked was chosen, over the runner-up if there is one.
+fn explain_choice(ranked: &[RankedConfiguration]) -> Option<String> {
+    let winner = ranked.first()?;
+
+    let summary = format!(
+        "{} {} {} {}ch",
+        winner.config.name, winner.config.format, winner.config.rate, winner.config.channels
+    );
+
+    Some(match ranked.get(1) {
+        None => format!("\n{summary} was chosen because it is the only valid Configuration."),
+        Some(runner_up) => {
+            let (_, reason) = winner.score.cmp_criteria(&runner_up.score);
+
+            format!("\n{summary} was chosen over the runner-up because {reason}.")
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use alsa::pcm::Format;
+
+    use super::*;
+    use crate::backend::{Backend, SimulatedBackend};
+
+    const DEVICES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/devices.toml");
+
+    fn config(name: &str, format: Format, rate: u32, channels: u32) -> ValidConfiguration {
+        let backend: Arc<dyn Backend> =
+            Arc::new(SimulatedBackend::load(Path::new(DEVICES)).unwrap());
+
+        ValidConfiguration {
+            name: name.to_string(),
+            description: String::new(),
+            direction: Direction::Playback,
+            card_name: String::new(),
+            device_number: 0,
+            sub_device_number: 0,
+            format,
+            rate,
+            channels,
+            buffer_time_ms: 0,
+            buffer_time_range: 0..=0,
+            buffer_times_cache: Arc::new(Mutex::new(None)),
+            backend,
+        }
+    }
+
+    fn policy(rate_family: u32, channels: Option<u32>) -> ScoringPolicy {
+        ScoringPolicy {
+            rate_family,
+            channels,
+            buffer_time_ms: 100,
+        }
+    }
+
+    // The winner and why.
+    fn choose(policy: &ScoringPolicy, configs: Vec<ValidConfiguration>) -> (String, String) {
+        let ranked = policy.rank(configs);
+        let winner = &ranked[0].config;
+
+        (
+            format!(
+                "{} {} {} {}",
+                winner.name, winner.format, winner.rate, winner.channels
+            ),
+            explain_choice(&ranked).unwrap(),
+        )
+    }
+
+    #[test]
+    fn rate_family_beats_format_depth() {
+        let (winner, reason) = choose(
+            &policy(48000, None),
+            vec![
+                config("hw:A", Format::s32(), 44100, 2),
+                config("hw:A", Format::s16(), 48000, 2),
+            ],
+        );
+
+        assert_eq!(winner, "hw:A S16_LE 48000 2");
+        assert!(reason.ends_with("because its Sampling Rate is in the preferred family."));
+    }
+
+    #[test]
+    fn format_depth_beats_native_channels() {
+        let (winner, reason) = choose(
+            &policy(48000, None),
+            vec![
+                config("hw:A", Format::s16(), 48000, 8),
+                config("hw:A", Format::s32(), 48000, 2),
+            ],
+        );
+
+        assert_eq!(winner, "hw:A S32_LE 48000 2");
+        assert!(reason.ends_with("because it has a deeper Format."));
+    }
+
+    #[test]
+    fn native_channels_beat_rate_preference() {
+        let (winner, reason) = choose(
+            &policy(48000, None),
+            vec![
+                config("hw:A", Format::s32(), 48000, 2),
+                config("hw:A", Format::s32(), 96000, 8),
+            ],
+        );
+
+        assert_eq!(winner, "hw:A S32_LE 96000 8");
+        assert!(reason.ends_with("because it uses the preferred Channel Count."));
+
+        // Unless another Channel Count is preferred.
+        let (winner, _) = choose(
+            &policy(48000, Some(2)),
+            vec![
+                config("hw:A", Format::s32(), 96000, 8),
+                config("hw:A", Format::s32(), 48000, 2),
+            ],
+        );
+
+        assert_eq!(winner, "hw:A S32_LE 48000 2");
+    }
+
+    #[test]
+    fn native_channels_are_per_pcm() {
+        let ranked = policy(48000, None).rank(vec![
+            config("hw:A", Format::s32(), 48000, 8),
+            config("hw:B", Format::s32(), 48000, 2),
+        ]);
+
+        assert!(ranked.iter().all(|ranked| ranked.score.native_channels));
+    }
+
+    #[test]
+    fn rate_preference() {
+        // The lowest multiple within the family.
+        let (winner, reason) = choose(
+            &policy(48000, None),
+            vec![
+                config("hw:A", Format::s32(), 192000, 2),
+                config("hw:A", Format::s32(), 48000, 2),
+                config("hw:A", Format::s32(), 96000, 2),
+            ],
+        );
+
+        assert_eq!(winner, "hw:A S32_LE 48000 2");
+        assert!(reason.ends_with("because its Sampling Rate is a better fit."));
+
+        // The highest outside of it.
+        let (winner, _) = choose(
+            &policy(44100, None),
+            vec![
+                config("hw:A", Format::s32(), 48000, 2),
+                config("hw:A", Format::s32(), 96000, 2),
+                config("hw:A", Format::s32(), 32000, 2),
+            ],
+        );
+
+        assert_eq!(winner, "hw:A S32_LE 96000 2");
+    }
+
+    #[test]
+    fn ties_go_to_the_first_one_found() {
+        let names = |configs: Vec<ValidConfiguration>| {
+            policy(48000, None)
+                .rank(configs)
+                .into_iter()
+                .map(|ranked| ranked.config.name)
+                .collect::<Vec<String>>()
+        };
+
+        let a = config("hw:A", Format::s32(), 48000, 2);
+        let b = config("hw:B", Format::s32(), 48000, 2);
+        let c = config("hw:C", Format::s16(), 48000, 2);
+
+        assert_eq!(
+            names(vec![a.clone(), c.clone(), b.clone()]),
+            ["hw:A", "hw:B", "hw:C"]
+        );
+        assert_eq!(
+            names(vec![b.clone(), c, a.clone()]),
+            ["hw:B", "hw:A", "hw:C"]
+        );
+
+        let (winner, reason) = choose(&policy(48000, None), vec![b, a]);
+
+        assert_eq!(winner, "hw:B S32_LE 48000 2");
+        assert!(reason.ends_with("because it was found first."));
+    }
+
+    #[test]
+    fn the_only_choice() {
+        let (_, reason) = choose(
+            &policy(48000, None),
+            vec![config("hw:A", Format::s16(), 44100, 1)],
+        );
+
+        assert!(reason.ends_with("because it is the only valid Configuration."));
+        assert!(explain_choice(&[]).is_none());
+    }
+}
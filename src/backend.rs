@@ -0,0 +1,118 @@
+mod hardware;
+mod session;
+mod simulated;
+
+use std::{fmt, io};
+
+use alsa::{pcm::Format, Direction, ValueOr};
+
+use serde::{Deserialize, Serialize};
+
+pub use hardware::HardwareBackend;
+pub use session::SessionBackend;
+pub use simulated::SimulatedBackend;
+
+// Everything the probing code needs from alsa-lib, and nothing more.
+// The method names and signatures mirror alsa::PCM and alsa::pcm::HwParams
+// so that the probing code reads the same no matter what is behind it.
+pub trait Backend: fmt::Debug + Send + Sync {
+    // False if the PCMs are not real sound cards on this machine.
+    fn is_hardware(&self) -> bool;
+
+    fn hints(&self) -> Vec<Hint>;
+
+    fn open(&self, name: &str, direction: Direction) -> BackendResult<Box<dyn Device + '_>>;
+
+    // The processes that have the PCM open, when open fails with EBUSY.
+    fn holders(&self, name: &str, direction: Direction) -> Vec<Holder>;
+}
+
+pub trait Device {
+    fn info(&self) -> BackendResult<DeviceInfo>;
+
+    fn hw_params_any(&self) -> BackendResult<Box<dyn HwSpace + '_>>;
+}
+
+pub trait HwSpace {
+    // A copy of the configuration space as it is right now,
+    // so that it can be narrowed down in more than one way.
+    fn try_clone(&self) -> BackendResult<Box<dyn HwSpace + '_>>;
+
+    fn set_rate_resample(&self, resample: bool) -> BackendResult<()>;
+    fn get_rate_resample(&self) -> BackendResult<bool>;
+
+    fn test_format(&self, format: Format) -> BackendResult<()>;
+    fn set_format(&self, format: Format) -> BackendResult<()>;
+    fn get_format(&self) -> BackendResult<Format>;
+
+    fn get_rate_min(&self) -> BackendResult<u32>;
+    fn get_rate_max(&self) -> BackendResult<u32>;
+    fn test_rate(&self, rate: u32) -> BackendResult<()>;
+    fn set_rate(&self, rate: u32, dir: ValueOr) -> BackendResult<()>;
+    fn get_rate(&self) -> BackendResult<u32>;
+
+    fn get_channels_min(&self) -> BackendResult<u32>;
+    fn get_channels_max(&self) -> BackendResult<u32>;
+    fn test_channels(&self, channels: u32) -> BackendResult<()>;
+    fn set_channels(&self, channels: u32) -> BackendResult<()>;
+    fn get_channels(&self) -> BackendResult<u32>;
+
+    fn get_buffer_time_min(&self) -> BackendResult<u32>;
+    fn get_buffer_time_max(&self) -> BackendResult<u32>;
+    fn set_buffer_time_near(&self, buffer_time: u32, dir: ValueOr) -> BackendResult<u32>;
+    fn set_period_time_near(&self, period_time: u32, dir: ValueOr) -> BackendResult<u32>;
+
+    // The equivalent of pcm.hw_params(&hwp), which is
+    // the point where the device says yes or no for real.
+    fn install(&self) -> BackendResult<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct Hint {
+    pub name: Option<String>,
+    pub direction: Option<Direction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub name: Option<String>,
+    pub device: u32,
+    pub subdevice: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holder {
+    pub pid: u32,
+    pub command: String,
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (PID {})", self.command, self.pid)
+    }
+}
+
+pub type BackendResult<T> = Result<T, BackendError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendError {
+    pub errno: i32,
+}
+
+impl BackendError {
+    pub fn new(errno: i32) -> Self {
+        Self { errno }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", io::Error::from_raw_os_error(self.errno))
+    }
+}
+
+impl From<alsa::Error> for BackendError {
+    fn from(e: alsa::Error) -> Self {
+        Self::new(e.errno() as i32)
+    }
+}
@@ -0,0 +1,242 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use alsa::{
+    device_name::HintIter,
+    pcm::{Format, HwParams, PCM},
+    Direction, ValueOr,
+};
+
+use glob::glob;
+
+use super::{Backend, BackendResult, Device, DeviceInfo, Hint, Holder, HwSpace};
+
+// The real thing, a thin wrapper around alsa-lib.
+#[derive(Debug, Default)]
+pub struct HardwareBackend;
+
+impl Backend for HardwareBackend {
+    fn is_hardware(&self) -> bool {
+        true
+    }
+
+    fn hints(&self) -> Vec<Hint> {
+        match HintIter::new_str(None, "pcm") {
+            Err(_) => vec![],
+            Ok(hints) => hints
+                .map(|hint| Hint {
+                    name: hint.name,
+                    direction: hint.direction,
+                })
+                .collect(),
+        }
+    }
+
+    fn open(&self, name: &str, direction: Direction) -> BackendResult<Box<dyn Device + '_>> {
+        Ok(Box::new(HardwareDevice {
+            pcm: PCM::new(name, direction, false)?,
+        }))
+    }
+
+    fn holders(&self, name: &str, direction: Direction) -> Vec<Holder> {
+        let (card, device) = match parse_hw_name(name) {
+            None => return vec![],
+            Some(parsed) => parsed,
+        };
+
+        // /proc/asound/<card id> is a link to /proc/asound/card<index>.
+        let card = match fs::read_link(format!("/proc/asound/{card}"))
+            .ok()
+            .and_then(|link| link.to_str()?.strip_prefix("card")?.parse::<u32>().ok())
+        {
+            None => return vec![],
+            Some(card) => card,
+        };
+
+        let stream = match direction {
+            Direction::Playback => 'p',
+            Direction::Capture => 'c',
+        };
+
+        let mut pids = BTreeSet::new();
+
+        // Whoever the driver says owns a substream. Substreams
+        // that are not open just say "closed".
+        for status in glob(&format!(
+            "/proc/asound/card{card}/pcm{device}{stream}/sub*/status"
+        ))
+        .into_iter()
+        .flatten()
+        .flatten()
+        {
+            if let Ok(status) = fs::read_to_string(status) {
+                pids.extend(status.lines().filter_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+
+                    (key.trim() == "owner_pid")
+                        .then(|| value.trim().parse::<u32>().ok())
+                        .flatten()
+                }));
+            }
+        }
+
+        // Whoever has the device node open, which also catches
+        // processes that opened it but haven't configured it yet.
+        let node = Path::new("/dev/snd").join(format!("pcmC{card}D{device}{stream}"));
+
+        for fd in glob("/proc/[0-9]*/fd/*").into_iter().flatten().flatten() {
+            if fs::read_link(&fd).map_or(false, |target| target == node) {
+                if let Some(pid) = fd
+                    .iter()
+                    .nth(2)
+                    .and_then(|pid| pid.to_str()?.parse::<u32>().ok())
+                {
+                    pids.insert(pid);
+                }
+            }
+        }
+
+        pids.into_iter()
+            .map(|pid| Holder {
+                pid,
+                command: fs::read_to_string(format!("/proc/{pid}/comm"))
+                    .map(|comm| comm.trim().to_string())
+                    .unwrap_or_else(|_| "unknown".to_string()),
+            })
+            .collect()
+    }
+}
+
+// hw:CARD=<card id>,DEV=<device> into its card id and device.
+fn parse_hw_name(name: &str) -> Option<(&str, u32)> {
+    let mut card = None;
+    let mut device = None;
+
+    for part in name.strip_prefix("hw:")?.split(',') {
+        match part.split_once('=')? {
+            ("CARD", value) => card = Some(value),
+            ("DEV", value) => device = value.parse::<u32>().ok(),
+            _ => (),
+        }
+    }
+
+    Some((card?, device.unwrap_or(0)))
+}
+
+struct HardwareDevice {
+    pcm: PCM,
+}
+
+impl Device for HardwareDevice {
+    fn info(&self) -> BackendResult<DeviceInfo> {
+        let info = self.pcm.info()?;
+
+        Ok(DeviceInfo {
+            name: info.get_name().ok().map(|name| name.to_string()),
+            device: info.get_device(),
+            subdevice: info.get_subdevice(),
+        })
+    }
+
+    fn hw_params_any(&self) -> BackendResult<Box<dyn HwSpace + '_>> {
+        Ok(Box::new(HardwareHwSpace {
+            pcm: &self.pcm,
+            hwp: HwParams::any(&self.pcm)?,
+        }))
+    }
+}
+
+struct HardwareHwSpace<'a> {
+    pcm: &'a PCM,
+    hwp: HwParams<'a>,
+}
+
+impl<'a> HwSpace for HardwareHwSpace<'a> {
+    fn try_clone(&self) -> BackendResult<Box<dyn HwSpace + '_>> {
+        // HwParams::clone unwraps the allocation.
+        let mut hwp = HwParams::any(self.pcm)?;
+
+        hwp.copy_from(&self.hwp);
+
+        Ok(Box::new(HardwareHwSpace { pcm: self.pcm, hwp }))
+    }
+
+    fn set_rate_resample(&self, resample: bool) -> BackendResult<()> {
+        Ok(self.hwp.set_rate_resample(resample)?)
+    }
+
+    fn get_rate_resample(&self) -> BackendResult<bool> {
+        Ok(self.hwp.get_rate_resample()?)
+    }
+
+    fn test_format(&self, format: Format) -> BackendResult<()> {
+        Ok(self.hwp.test_format(format)?)
+    }
+
+    fn set_format(&self, format: Format) -> BackendResult<()> {
+        Ok(self.hwp.set_format(format)?)
+    }
+
+    fn get_format(&self) -> BackendResult<Format> {
+        Ok(self.hwp.get_format()?)
+    }
+
+    fn get_rate_min(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_rate_min()?)
+    }
+
+    fn get_rate_max(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_rate_max()?)
+    }
+
+    fn test_rate(&self, rate: u32) -> BackendResult<()> {
+        Ok(self.hwp.test_rate(rate)?)
+    }
+
+    fn set_rate(&self, rate: u32, dir: ValueOr) -> BackendResult<()> {
+        Ok(self.hwp.set_rate(rate, dir)?)
+    }
+
+    fn get_rate(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_rate()?)
+    }
+
+    fn get_channels_min(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_channels_min()?)
+    }
+
+    fn get_channels_max(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_channels_max()?)
+    }
+
+    fn test_channels(&self, channels: u32) -> BackendResult<()> {
+        Ok(self.hwp.test_channels(channels)?)
+    }
+
+    fn set_channels(&self, channels: u32) -> BackendResult<()> {
+        Ok(self.hwp.set_channels(channels)?)
+    }
+
+    fn get_channels(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_channels()?)
+    }
+
+    fn get_buffer_time_min(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_buffer_time_min()?)
+    }
+
+    fn get_buffer_time_max(&self) -> BackendResult<u32> {
+        Ok(self.hwp.get_buffer_time_max()?)
+    }
+
+    fn set_buffer_time_near(&self, buffer_time: u32, dir: ValueOr) -> BackendResult<u32> {
+        Ok(self.hwp.set_buffer_time_near(buffer_time, dir)?)
+    }
+
+    fn set_period_time_near(&self, period_time: u32, dir: ValueOr) -> BackendResult<u32> {
+        Ok(self.hwp.set_period_time_near(period_time, dir)?)
+    }
+
+    fn install(&self) -> BackendResult<()> {
+        Ok(self.pcm.hw_params(&self.hwp)?)
+    }
+}
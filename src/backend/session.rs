@@ -0,0 +1,613 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use alsa::{pcm::Format, Direction, ValueOr};
+
+use colored::*;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Backend, BackendError, BackendResult, Device, DeviceInfo, Hint, Holder, HwSpace};
+
+use crate::report::{direction_name, parse_direction};
+
+// Bump this whenever the meaning of a recording changes
+// in a way that would make old recordings replay wrong.
+pub const SESSION_SCHEMA_VERSION: u32 = 1;
+
+// Records every query the probing code makes through another Backend,
+// or replays a recording as if the recorded hardware was plugged in.
+//
+// Every answer is keyed by the PCM, the direction, the ops that changed
+// the configuration space before it (its history) and the op itself.
+// The probing code always asks the same questions in the same order,
+// so the same keys come up again on replay. Anything that was not
+// recorded fails with ENODATA.
+#[derive(Debug)]
+pub struct SessionBackend {
+    // None when replaying.
+    inner: Option<Arc<dyn Backend>>,
+    hints: Mutex<Vec<HintRecord>>,
+    answers: Mutex<HashMap<QueryKey, Outcome>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Session {
+    schema_version: u32,
+    awiz_version: String,
+    hints: Vec<HintRecord>,
+    queries: Vec<QueryRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HintRecord {
+    name: Option<String>,
+    direction: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueryRecord {
+    pcm: String,
+    direction: String,
+    history: Vec<Op>,
+    op: Op,
+    outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct QueryKey {
+    pcm: String,
+    direction: String,
+    history: Vec<Op>,
+    op: Op,
+}
+
+// Every call the probing code can make. The ValueOr of the *_near
+// calls is not recorded, the probing code only ever uses Nearest.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "op", content = "arg", rename_all = "snake_case")]
+enum Op {
+    Open,
+    Holders,
+    Info,
+    HwParamsAny,
+    SetRateResample(bool),
+    GetRateResample,
+    TestFormat(String),
+    SetFormat(String),
+    GetFormat,
+    GetRateMin,
+    GetRateMax,
+    TestRate(u32),
+    SetRate(u32),
+    GetRate,
+    GetChannelsMin,
+    GetChannelsMax,
+    TestChannels(u32),
+    SetChannels(u32),
+    GetChannels,
+    GetBufferTimeMin,
+    GetBufferTimeMax,
+    SetBufferTimeNear(u32),
+    SetPeriodTimeNear(u32),
+    Install,
+}
+
+impl Op {
+    fn changes_state(&self) -> bool {
+        matches!(
+            self,
+            Self::SetRateResample(_)
+                | Self::SetFormat(_)
+                | Self::SetRate(_)
+                | Self::SetChannels(_)
+                | Self::SetBufferTimeNear(_)
+                | Self::SetPeriodTimeNear(_)
+                | Self::Install
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Ok(Value),
+    Errno(i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Value {
+    None,
+    Bool(bool),
+    Number(u32),
+    Text(String),
+    Info(DeviceInfo),
+    Holders(Vec<Holder>),
+}
+
+// The types an op can answer with.
+trait Recordable: Sized {
+    fn to_value(&self) -> Value;
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+impl Recordable for () {
+    fn to_value(&self) -> Value {
+        Value::None
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        matches!(value, Value::None).then_some(())
+    }
+}
+
+impl Recordable for bool {
+    fn to_value(&self) -> Value {
+        Value::Bool(*self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl Recordable for u32 {
+    fn to_value(&self) -> Value {
+        Value::Number(*self)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+impl Recordable for Format {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Text(s) => Format::all().iter().find(|f| f.to_string() == s).copied(),
+            _ => None,
+        }
+    }
+}
+
+impl Recordable for DeviceInfo {
+    fn to_value(&self) -> Value {
+        Value::Info(self.clone())
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Info(info) => Some(info),
+            _ => None,
+        }
+    }
+}
+
+impl Recordable for Vec<Holder> {
+    fn to_value(&self) -> Value {
+        Value::Holders(self.clone())
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Holders(holders) => Some(holders),
+            _ => None,
+        }
+    }
+}
+
+impl SessionBackend {
+    pub fn record(inner: Arc<dyn Backend>) -> Self {
+        Self {
+            inner: Some(inner),
+            hints: Mutex::new(vec![]),
+            answers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn replay(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+        let session: Session = serde_json::from_str(&contents)
+            .map_err(|e| format!("{} is not a valid recording: {e}", path.display()))?;
+
+        if session.schema_version != SESSION_SCHEMA_VERSION {
+            return Err(format!(
+                "{} was recorded with an incompatible version of awiz ({}).",
+                path.display(),
+                session.awiz_version
+            ));
+        }
+
+        if session.awiz_version != env!("CARGO_PKG_VERSION") {
+            // A different version may ask different questions.
+            eprintln!(
+                "{}",
+                format!(
+                    "\n{} was recorded with awiz {}, the replay may not match the recording.",
+                    path.display(),
+                    session.awiz_version
+                )
+                .bold()
+                .yellow()
+            );
+        }
+
+        let answers = session
+            .queries
+            .into_iter()
+            .map(|query| {
+                (
+                    QueryKey {
+                        pcm: query.pcm,
+                        direction: query.direction,
+                        history: query.history,
+                        op: query.op,
+                    },
+                    query.outcome,
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            inner: None,
+            hints: Mutex::new(session.hints),
+            answers: Mutex::new(answers),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut queries: Vec<QueryRecord> = self
+            .answers
+            .lock()
+            .map_err(|_| "The recording is corrupted.".to_string())?
+            .iter()
+            .map(|(key, outcome)| QueryRecord {
+                pcm: key.pcm.clone(),
+                direction: key.direction.clone(),
+                history: key.history.clone(),
+                op: key.op.clone(),
+                outcome: outcome.clone(),
+            })
+            .collect();
+
+        // Keep the file stable, the same hardware should
+        // always result in the same recording.
+        queries.sort_by_cached_key(|query| {
+            (
+                query.pcm.clone(),
+                query.direction.clone(),
+                serde_json::to_string(&(&query.history, &query.op)).unwrap_or_default(),
+            )
+        });
+
+        let session = Session {
+            schema_version: SESSION_SCHEMA_VERSION,
+            awiz_version: env!("CARGO_PKG_VERSION").to_string(),
+            hints: self
+                .hints
+                .lock()
+                .map_err(|_| "The recording is corrupted.".to_string())?
+                .clone(),
+            queries,
+        };
+
+        let contents = serde_json::to_string(&session)
+            .map_err(|e| format!("Could not serialize the recording: {e}"))?;
+
+        fs::write(path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    fn answer<T: Recordable>(
+        &self,
+        key: QueryKey,
+        live: impl FnOnce() -> Option<BackendResult<T>>,
+    ) -> BackendResult<T> {
+        let no_data = BackendError::new(libc::ENODATA);
+
+        match live() {
+            // Replaying.
+            None => match self.answers.lock().ok().and_then(|a| a.get(&key).cloned()) {
+                Some(Outcome::Ok(value)) => T::from_value(value).ok_or(no_data),
+                Some(Outcome::Errno(errno)) => Err(BackendError::new(errno)),
+                None => Err(no_data),
+            },
+            // Recording.
+            Some(result) => {
+                let outcome = match &result {
+                    Ok(value) => Outcome::Ok(value.to_value()),
+                    Err(e) => Outcome::Errno(e.errno),
+                };
+
+                if let Ok(mut answers) = self.answers.lock() {
+                    answers.insert(key, outcome);
+                }
+
+                result
+            }
+        }
+    }
+}
+
+impl Backend for SessionBackend {
+    fn is_hardware(&self) -> bool {
+        self.inner
+            .as_ref()
+            .map_or(false, |inner| inner.is_hardware())
+    }
+
+    fn hints(&self) -> Vec<Hint> {
+        match &self.inner {
+            Some(inner) => {
+                let hints = inner.hints();
+
+                if let Ok(mut recorded) = self.hints.lock() {
+                    *recorded = hints
+                        .iter()
+                        .map(|hint| HintRecord {
+                            name: hint.name.clone(),
+                            direction: hint.direction.map(|d| direction_name(d).to_string()),
+                        })
+                        .collect();
+                }
+
+                hints
+            }
+            None => self
+                .hints
+                .lock()
+                .map(|hints| {
+                    hints
+                        .iter()
+                        .map(|hint| Hint {
+                            name: hint.name.clone(),
+                            direction: hint.direction.as_deref().and_then(parse_direction),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn open(&self, name: &str, direction: Direction) -> BackendResult<Box<dyn Device + '_>> {
+        let mut device = None;
+
+        let key = QueryKey {
+            pcm: name.to_string(),
+            direction: direction_name(direction).to_string(),
+            history: vec![],
+            op: Op::Open,
+        };
+
+        self.answer(key, || {
+            self.inner.as_ref().map(|inner| {
+                inner.open(name, direction).map(|inner| {
+                    device = Some(inner);
+                })
+            })
+        })?;
+
+        Ok(Box::new(SessionDevice {
+            backend: self,
+            inner: device,
+            pcm: name.to_string(),
+            direction: direction_name(direction).to_string(),
+        }))
+    }
+
+    fn holders(&self, name: &str, direction: Direction) -> Vec<Holder> {
+        let key = QueryKey {
+            pcm: name.to_string(),
+            direction: direction_name(direction).to_string(),
+            history: vec![],
+            op: Op::Holders,
+        };
+
+        self.answer(key, || {
+            self.inner
+                .as_ref()
+                .map(|inner| Ok(inner.holders(name, direction)))
+        })
+        .unwrap_or_default()
+    }
+}
+
+struct SessionDevice<'a> {
+    backend: &'a SessionBackend,
+    inner: Option<Box<dyn Device + 'a>>,
+    pcm: String,
+    direction: String,
+}
+
+impl<'a> SessionDevice<'a> {
+    fn key(&self, history: &[Op], op: Op) -> QueryKey {
+        QueryKey {
+            pcm: self.pcm.clone(),
+            direction: self.direction.clone(),
+            history: history.to_vec(),
+            op,
+        }
+    }
+}
+
+impl<'a> Device for SessionDevice<'a> {
+    fn info(&self) -> BackendResult<DeviceInfo> {
+        self.backend.answer(self.key(&[], Op::Info), || {
+            self.inner.as_ref().map(|inner| inner.info())
+        })
+    }
+
+    fn hw_params_any(&self) -> BackendResult<Box<dyn HwSpace + '_>> {
+        let mut hwp = None;
+
+        self.backend.answer(self.key(&[], Op::HwParamsAny), || {
+            self.inner.as_ref().map(|inner| {
+                inner.hw_params_any().map(|inner| {
+                    hwp = Some(inner);
+                })
+            })
+        })?;
+
+        Ok(Box::new(SessionHwSpace {
+            device: self,
+            inner: hwp,
+            history: RefCell::new(vec![]),
+        }))
+    }
+}
+
+struct SessionHwSpace<'a> {
+    device: &'a SessionDevice<'a>,
+    inner: Option<Box<dyn HwSpace + 'a>>,
+    history: RefCell<Vec<Op>>,
+}
+
+impl<'a> SessionHwSpace<'a> {
+    fn call<T: Recordable>(
+        &self,
+        op: Op,
+        live: impl FnOnce(&dyn HwSpace) -> BackendResult<T>,
+    ) -> BackendResult<T> {
+        let key = self.device.key(&self.history.borrow(), op.clone());
+
+        let result = self
+            .device
+            .backend
+            .answer(key, || self.inner.as_deref().map(live));
+
+        if op.changes_state() {
+            self.history.borrow_mut().push(op);
+        }
+
+        result
+    }
+}
+
+impl<'a> HwSpace for SessionHwSpace<'a> {
+    fn try_clone(&self) -> BackendResult<Box<dyn HwSpace + '_>> {
+        // Not a query, a copy has the same history
+        // and so gets the same answers.
+        let inner = match &self.inner {
+            None => None,
+            Some(inner) => Some(inner.try_clone()?),
+        };
+
+        Ok(Box::new(SessionHwSpace {
+            device: self.device,
+            inner,
+            history: RefCell::new(self.history.borrow().clone()),
+        }))
+    }
+
+    fn set_rate_resample(&self, resample: bool) -> BackendResult<()> {
+        self.call(Op::SetRateResample(resample), |hwp| {
+            hwp.set_rate_resample(resample)
+        })
+    }
+
+    fn get_rate_resample(&self) -> BackendResult<bool> {
+        self.call(Op::GetRateResample, |hwp| hwp.get_rate_resample())
+    }
+
+    fn test_format(&self, format: Format) -> BackendResult<()> {
+        self.call(Op::TestFormat(format.to_string()), |hwp| {
+            hwp.test_format(format)
+        })
+    }
+
+    fn set_format(&self, format: Format) -> BackendResult<()> {
+        self.call(Op::SetFormat(format.to_string()), |hwp| {
+            hwp.set_format(format)
+        })
+    }
+
+    fn get_format(&self) -> BackendResult<Format> {
+        self.call(Op::GetFormat, |hwp| hwp.get_format())
+    }
+
+    fn get_rate_min(&self) -> BackendResult<u32> {
+        self.call(Op::GetRateMin, |hwp| hwp.get_rate_min())
+    }
+
+    fn get_rate_max(&self) -> BackendResult<u32> {
+        self.call(Op::GetRateMax, |hwp| hwp.get_rate_max())
+    }
+
+    fn test_rate(&self, rate: u32) -> BackendResult<()> {
+        self.call(Op::TestRate(rate), |hwp| hwp.test_rate(rate))
+    }
+
+    fn set_rate(&self, rate: u32, dir: ValueOr) -> BackendResult<()> {
+        self.call(Op::SetRate(rate), |hwp| hwp.set_rate(rate, dir))
+    }
+
+    fn get_rate(&self) -> BackendResult<u32> {
+        self.call(Op::GetRate, |hwp| hwp.get_rate())
+    }
+
+    fn get_channels_min(&self) -> BackendResult<u32> {
+        self.call(Op::GetChannelsMin, |hwp| hwp.get_channels_min())
+    }
+
+    fn get_channels_max(&self) -> BackendResult<u32> {
+        self.call(Op::GetChannelsMax, |hwp| hwp.get_channels_max())
+    }
+
+    fn test_channels(&self, channels: u32) -> BackendResult<()> {
+        self.call(Op::TestChannels(channels), |hwp| {
+            hwp.test_channels(channels)
+        })
+    }
+
+    fn set_channels(&self, channels: u32) -> BackendResult<()> {
+        self.call(Op::SetChannels(channels), |hwp| hwp.set_channels(channels))
+    }
+
+    fn get_channels(&self) -> BackendResult<u32> {
+        self.call(Op::GetChannels, |hwp| hwp.get_channels())
+    }
+
+    fn get_buffer_time_min(&self) -> BackendResult<u32> {
+        self.call(Op::GetBufferTimeMin, |hwp| hwp.get_buffer_time_min())
+    }
+
+    fn get_buffer_time_max(&self) -> BackendResult<u32> {
+        self.call(Op::GetBufferTimeMax, |hwp| hwp.get_buffer_time_max())
+    }
+
+    fn set_buffer_time_near(&self, buffer_time: u32, dir: ValueOr) -> BackendResult<u32> {
+        self.call(Op::SetBufferTimeNear(buffer_time), |hwp| {
+            hwp.set_buffer_time_near(buffer_time, dir)
+        })
+    }
+
+    fn set_period_time_near(&self, period_time: u32, dir: ValueOr) -> BackendResult<u32> {
+        self.call(Op::SetPeriodTimeNear(period_time), |hwp| {
+            hwp.set_period_time_near(period_time, dir)
+        })
+    }
+
+    fn install(&self) -> BackendResult<()> {
+        self.call(Op::Install, |hwp| hwp.install())
+    }
+}
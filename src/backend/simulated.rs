@@ -0,0 +1,552 @@
+use std::{cell::RefCell, collections::BTreeMap, fs, path::Path, thread, time::Duration};
+
+use alsa::{pcm::Format, Direction, ValueOr};
+
+use serde::Deserialize;
+
+use super::{Backend, BackendError, BackendResult, Device, DeviceInfo, Hint, Holder, HwSpace};
+
+// A simulated backend driven by a device description file, so that
+// the whole wizard can be run without any sound cards at all.
+// See examples/devices.toml for what a description looks like.
+#[derive(Debug)]
+pub struct SimulatedBackend {
+    pcms: Vec<SimulatedPcm>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Description {
+    #[serde(default, rename = "pcm")]
+    pcms: Vec<PcmDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PcmDescription {
+    // Has to look like hw:CARD=<card>,DEV=<device> like a real hint.
+    name: String,
+    direction: DirectionDescription,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    device: Option<u32>,
+    #[serde(default)]
+    subdevice: u32,
+    formats: Vec<String>,
+    rates: Values,
+    channels: Values,
+    #[serde(default)]
+    buffer_time: BufferTimeDescription,
+    // The device can't be opened, as if something else is using it.
+    #[serde(default)]
+    busy: bool,
+    // Who is using it, if it's busy.
+    #[serde(default)]
+    holders: Vec<Holder>,
+    // The device insists on resampling, like a hw device behind a plug.
+    #[serde(default)]
+    rate_resample: bool,
+    // Opening the device and installing params takes this long,
+    // like a slow driver. A big enough number is a hung one.
+    #[serde(default)]
+    delay_ms: u64,
+    // Combinations that are advertised but refused when installed.
+    #[serde(default)]
+    reject: Vec<RejectDescription>,
+    // Values that are accepted but silently replaced with another.
+    #[serde(default)]
+    lies: LiesDescription,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DirectionDescription {
+    Playback,
+    Capture,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Values {
+    List(Vec<u32>),
+    Range { min: u32, max: u32 },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BufferTimeDescription {
+    min: u32,
+    max: u32,
+    // Only multiples of step (counted from min) are valid.
+    #[serde(default = "default_buffer_time_step")]
+    step: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RejectDescription {
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    rate: Option<u32>,
+    #[serde(default)]
+    channels: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LiesDescription {
+    #[serde(default)]
+    formats: BTreeMap<String, String>,
+    #[serde(default)]
+    rates: BTreeMap<String, u32>,
+    #[serde(default)]
+    channels: BTreeMap<String, u32>,
+}
+
+impl Default for BufferTimeDescription {
+    fn default() -> Self {
+        Self {
+            min: 1000,
+            max: 500000,
+            step: default_buffer_time_step(),
+        }
+    }
+}
+
+fn default_buffer_time_step() -> u32 {
+    1000
+}
+
+#[derive(Debug)]
+struct SimulatedPcm {
+    name: String,
+    direction: Direction,
+    description: String,
+    device: u32,
+    subdevice: u32,
+    formats: Vec<Format>,
+    rates: Values,
+    channels: Values,
+    buffer_time: BufferTimeDescription,
+    busy: bool,
+    holders: Vec<Holder>,
+    rate_resample: bool,
+    delay: Duration,
+    reject: Vec<Reject>,
+    format_lies: Vec<(Format, Format)>,
+    rate_lies: Vec<(u32, u32)>,
+    channel_lies: Vec<(u32, u32)>,
+}
+
+#[derive(Debug)]
+struct Reject {
+    format: Option<Format>,
+    rate: Option<u32>,
+    channels: Option<u32>,
+}
+
+impl SimulatedBackend {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+        let description: Description = toml::from_str(&contents)
+            .map_err(|e| format!("{} is not a valid device description: {e}", path.display()))?;
+
+        let pcms = description
+            .pcms
+            .into_iter()
+            .map(SimulatedPcm::try_from)
+            .collect::<Result<Vec<SimulatedPcm>, String>>()
+            .map_err(|e| format!("{} is not a valid device description: {e}", path.display()))?;
+
+        Ok(Self { pcms })
+    }
+}
+
+impl TryFrom<PcmDescription> for SimulatedPcm {
+    type Error = String;
+
+    fn try_from(pcm: PcmDescription) -> Result<Self, String> {
+        let name = pcm.name;
+
+        if !name.starts_with("hw:CARD=") {
+            return Err(format!(
+                "{name} does not look like hw:CARD=<card>,DEV=<device>"
+            ));
+        }
+
+        let parse = |s: &str| parse_format(&name, s);
+
+        let device = match pcm.device {
+            Some(device) => device,
+            None => name
+                .split(",DEV=")
+                .nth(1)
+                .and_then(|d| d.parse::<u32>().ok())
+                .unwrap_or_default(),
+        };
+
+        let formats = pcm
+            .formats
+            .iter()
+            .map(|f| parse(f))
+            .collect::<Result<Vec<Format>, String>>()?;
+
+        let reject = pcm
+            .reject
+            .iter()
+            .map(|r| {
+                Ok(Reject {
+                    format: r.format.as_deref().map(parse).transpose()?,
+                    rate: r.rate,
+                    channels: r.channels,
+                })
+            })
+            .collect::<Result<Vec<Reject>, String>>()?;
+
+        let format_lies = pcm
+            .lies
+            .formats
+            .iter()
+            .map(|(requested, actual)| Ok((parse(requested)?, parse(actual)?)))
+            .collect::<Result<Vec<(Format, Format)>, String>>()?;
+
+        let rate_lies = parse_lies(&name, "rates", &pcm.lies.rates)?;
+        let channel_lies = parse_lies(&name, "channels", &pcm.lies.channels)?;
+
+        if pcm.buffer_time.min > pcm.buffer_time.max || pcm.buffer_time.step == 0 {
+            return Err(format!("{name} has an invalid buffer_time"));
+        }
+
+        Ok(Self {
+            description: pcm.description.unwrap_or_else(|| name.clone()),
+            direction: match pcm.direction {
+                DirectionDescription::Playback => Direction::Playback,
+                DirectionDescription::Capture => Direction::Capture,
+            },
+            name,
+            device,
+            subdevice: pcm.subdevice,
+            formats,
+            rates: pcm.rates,
+            channels: pcm.channels,
+            buffer_time: pcm.buffer_time,
+            busy: pcm.busy,
+            holders: pcm.holders,
+            rate_resample: pcm.rate_resample,
+            delay: Duration::from_millis(pcm.delay_ms),
+            reject,
+            format_lies,
+            rate_lies,
+            channel_lies,
+        })
+    }
+}
+
+fn parse_format(name: &str, s: &str) -> Result<Format, String> {
+    Format::all()
+        .iter()
+        .find(|f| f.to_string() == s)
+        .copied()
+        .ok_or_else(|| format!("{name} has an unknown format {s}"))
+}
+
+fn parse_lies(
+    name: &str,
+    param: &str,
+    lies: &BTreeMap<String, u32>,
+) -> Result<Vec<(u32, u32)>, String> {
+    lies.iter()
+        .map(|(requested, actual)| {
+            requested
+                .parse::<u32>()
+                .map(|requested| (requested, *actual))
+                .map_err(|_| format!("{name} has an invalid lie in {param}: {requested}"))
+        })
+        .collect()
+}
+
+impl Values {
+    fn contains(&self, value: u32) -> bool {
+        match self {
+            Self::List(values) => values.contains(&value),
+            Self::Range { min, max } => (*min..=*max).contains(&value),
+        }
+    }
+
+    fn min(&self) -> Option<u32> {
+        match self {
+            Self::List(values) => values.iter().min().copied(),
+            Self::Range { min, .. } => Some(*min),
+        }
+    }
+
+    fn max(&self) -> Option<u32> {
+        match self {
+            Self::List(values) => values.iter().max().copied(),
+            Self::Range { max, .. } => Some(*max),
+        }
+    }
+
+    fn single(&self) -> Option<u32> {
+        match self {
+            Self::List(values) if values.len() == 1 => Some(values[0]),
+            Self::Range { min, max } if min == max => Some(*min),
+            _ => None,
+        }
+    }
+}
+
+impl Backend for SimulatedBackend {
+    fn is_hardware(&self) -> bool {
+        false
+    }
+
+    fn hints(&self) -> Vec<Hint> {
+        self.pcms
+            .iter()
+            .map(|pcm| Hint {
+                name: Some(pcm.name.clone()),
+                direction: Some(pcm.direction),
+            })
+            .collect()
+    }
+
+    fn open(&self, name: &str, direction: Direction) -> BackendResult<Box<dyn Device + '_>> {
+        let pcm = self
+            .pcms
+            .iter()
+            .find(|pcm| pcm.name == name && pcm.direction == direction)
+            .ok_or(BackendError::new(libc::ENOENT))?;
+
+        if pcm.busy {
+            return Err(BackendError::new(libc::EBUSY));
+        }
+
+        thread::sleep(pcm.delay);
+
+        Ok(Box::new(SimulatedDevice { pcm }))
+    }
+
+    fn holders(&self, name: &str, direction: Direction) -> Vec<Holder> {
+        self.pcms
+            .iter()
+            .find(|pcm| pcm.name == name && pcm.direction == direction)
+            .map(|pcm| pcm.holders.clone())
+            .unwrap_or_default()
+    }
+}
+
+struct SimulatedDevice<'a> {
+    pcm: &'a SimulatedPcm,
+}
+
+impl<'a> Device for SimulatedDevice<'a> {
+    fn info(&self) -> BackendResult<DeviceInfo> {
+        Ok(DeviceInfo {
+            name: Some(self.pcm.description.clone()),
+            device: self.pcm.device,
+            subdevice: self.pcm.subdevice,
+        })
+    }
+
+    fn hw_params_any(&self) -> BackendResult<Box<dyn HwSpace + '_>> {
+        Ok(Box::new(SimulatedHwSpace {
+            pcm: self.pcm,
+            state: RefCell::new(State::default()),
+        }))
+    }
+}
+
+// What has been set so far. Like a real configuration space
+// a parameter that has been set can't be set to anything else.
+#[derive(Debug, Default, Clone)]
+struct State {
+    format: Option<Format>,
+    rate: Option<u32>,
+    channels: Option<u32>,
+    buffer_time: Option<u32>,
+}
+
+struct SimulatedHwSpace<'a> {
+    pcm: &'a SimulatedPcm,
+    state: RefCell<State>,
+}
+
+fn einval() -> BackendError {
+    BackendError::new(libc::EINVAL)
+}
+
+fn lie<T: Copy + PartialEq>(lies: &[(T, T)], value: T) -> T {
+    lies.iter()
+        .find(|(requested, _)| *requested == value)
+        .map_or(value, |(_, actual)| *actual)
+}
+
+impl<'a> HwSpace for SimulatedHwSpace<'a> {
+    fn try_clone(&self) -> BackendResult<Box<dyn HwSpace + '_>> {
+        Ok(Box::new(SimulatedHwSpace {
+            pcm: self.pcm,
+            state: RefCell::new(self.state.borrow().clone()),
+        }))
+    }
+
+    fn set_rate_resample(&self, _resample: bool) -> BackendResult<()> {
+        Ok(())
+    }
+
+    fn get_rate_resample(&self) -> BackendResult<bool> {
+        Ok(self.pcm.rate_resample)
+    }
+
+    fn test_format(&self, format: Format) -> BackendResult<()> {
+        match self.state.borrow().format {
+            Some(set) if set != format => Err(einval()),
+            _ if self.pcm.formats.contains(&format) => Ok(()),
+            _ => Err(einval()),
+        }
+    }
+
+    fn set_format(&self, format: Format) -> BackendResult<()> {
+        self.test_format(format)?;
+
+        self.state.borrow_mut().format = Some(lie(&self.pcm.format_lies, format));
+
+        Ok(())
+    }
+
+    fn get_format(&self) -> BackendResult<Format> {
+        match (self.state.borrow().format, &self.pcm.formats[..]) {
+            (Some(format), _) | (None, &[format]) => Ok(format),
+            _ => Err(einval()),
+        }
+    }
+
+    fn get_rate_min(&self) -> BackendResult<u32> {
+        match self.state.borrow().rate {
+            Some(rate) => Ok(rate),
+            None => self.pcm.rates.min().ok_or_else(einval),
+        }
+    }
+
+    fn get_rate_max(&self) -> BackendResult<u32> {
+        match self.state.borrow().rate {
+            Some(rate) => Ok(rate),
+            None => self.pcm.rates.max().ok_or_else(einval),
+        }
+    }
+
+    fn test_rate(&self, rate: u32) -> BackendResult<()> {
+        match self.state.borrow().rate {
+            Some(set) if set != rate => Err(einval()),
+            _ if self.pcm.rates.contains(rate) => Ok(()),
+            _ => Err(einval()),
+        }
+    }
+
+    fn set_rate(&self, rate: u32, _dir: ValueOr) -> BackendResult<()> {
+        self.test_rate(rate)?;
+
+        self.state.borrow_mut().rate = Some(lie(&self.pcm.rate_lies, rate));
+
+        Ok(())
+    }
+
+    fn get_rate(&self) -> BackendResult<u32> {
+        self.state
+            .borrow()
+            .rate
+            .or_else(|| self.pcm.rates.single())
+            .ok_or_else(einval)
+    }
+
+    fn get_channels_min(&self) -> BackendResult<u32> {
+        match self.state.borrow().channels {
+            Some(channels) => Ok(channels),
+            None => self.pcm.channels.min().ok_or_else(einval),
+        }
+    }
+
+    fn get_channels_max(&self) -> BackendResult<u32> {
+        match self.state.borrow().channels {
+            Some(channels) => Ok(channels),
+            None => self.pcm.channels.max().ok_or_else(einval),
+        }
+    }
+
+    fn test_channels(&self, channels: u32) -> BackendResult<()> {
+        match self.state.borrow().channels {
+            Some(set) if set != channels => Err(einval()),
+            _ if self.pcm.channels.contains(channels) => Ok(()),
+            _ => Err(einval()),
+        }
+    }
+
+    fn set_channels(&self, channels: u32) -> BackendResult<()> {
+        self.test_channels(channels)?;
+
+        self.state.borrow_mut().channels = Some(lie(&self.pcm.channel_lies, channels));
+
+        Ok(())
+    }
+
+    fn get_channels(&self) -> BackendResult<u32> {
+        self.state
+            .borrow()
+            .channels
+            .or_else(|| self.pcm.channels.single())
+            .ok_or_else(einval)
+    }
+
+    fn get_buffer_time_min(&self) -> BackendResult<u32> {
+        Ok(self.pcm.buffer_time.min)
+    }
+
+    fn get_buffer_time_max(&self) -> BackendResult<u32> {
+        Ok(self.pcm.buffer_time.max)
+    }
+
+    fn set_buffer_time_near(&self, buffer_time: u32, _dir: ValueOr) -> BackendResult<u32> {
+        let BufferTimeDescription { min, max, step } = self.pcm.buffer_time;
+
+        let steps = (buffer_time.clamp(min, max) - min + step / 2) / step;
+        let actual = (min + steps * step).min(max);
+
+        self.state.borrow_mut().buffer_time = Some(actual);
+
+        Ok(actual)
+    }
+
+    fn set_period_time_near(&self, period_time: u32, _dir: ValueOr) -> BackendResult<u32> {
+        // At least 2 periods per buffer.
+        let max = self
+            .state
+            .borrow()
+            .buffer_time
+            .unwrap_or(self.pcm.buffer_time.max)
+            / 2;
+
+        Ok(period_time.clamp(1, max.max(1)))
+    }
+
+    fn install(&self) -> BackendResult<()> {
+        thread::sleep(self.pcm.delay);
+
+        let state = self.state.borrow();
+
+        let rejected = self.pcm.reject.iter().any(|reject| {
+            reject.format.map_or(true, |f| state.format == Some(f))
+                && reject.rate.map_or(true, |r| state.rate == Some(r))
+                && reject.channels.map_or(true, |c| state.channels == Some(c))
+        });
+
+        if rejected {
+            Err(einval())
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::{fs, path::PathBuf};
+
+use colored::*;
+
+use comfy_table::{
+    modifiers::UTF8_SOLID_INNER_BORDERS, presets::UTF8_FULL, Attribute, Cell, ContentArrangement,
+    Table,
+};
+
+use glob::{glob, Pattern};
+
+use crate::{
+    conf::{parse, Compound, Value},
+    replace_atomically, OutputTarget, BACKUP_FILE_PATH_TEMPLATE,
+};
+
+const MS_PER_SECOND: u128 = 1000;
+const SECONDS_PER_DAY: u128 = 86400;
+
+#[derive(Debug, Clone)]
+pub struct Backup {
+    // The id is the {now} part of BACKUP_FILE_PATH_TEMPLATE,
+    // milliseconds since the Unix epoch.
+    pub id: u128,
+    pub path: PathBuf,
+}
+
+impl Backup {
+    pub fn timestamp(&self) -> String {
+        format_timestamp(self.id)
+    }
+
+    pub fn summary(&self) -> String {
+        match fs::read_to_string(&self.path) {
+            Err(e) => format!("unreadable: {e}"),
+            Ok(contents) => summarize(&contents),
+        }
+    }
+}
+
+pub fn find_backups(target: &OutputTarget) -> Vec<Backup> {
+    // Everything that matches BACKUP_FILE_PATH_TEMPLATE
+    // with {now} being a number.
+    let prefix = BACKUP_FILE_PATH_TEMPLATE
+        .replace("{path}", &target.display())
+        .replace("{now}", "");
+
+    let mut backups: Vec<Backup> = match glob(&format!("{}*", Pattern::escape(&prefix))) {
+        Err(_) => vec![],
+        Ok(paths) => paths
+            .flatten()
+            .filter_map(|path| {
+                let id = path.to_str()?.strip_prefix(&prefix)?.parse::<u128>().ok()?;
+
+                Some(Backup { id, path })
+            })
+            .collect(),
+    };
+
+    // Newest first.
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.id));
+
+    backups
+}
+
+pub fn find_backup(target: &OutputTarget, id: &str) -> Result<Backup, String> {
+    let backups = find_backups(target);
+
+    let backup = if id == "latest" {
+        backups.first()
+    } else {
+        let id = id
+            .parse::<u128>()
+            .map_err(|_| format!("{id} is not a backup id, see `awiz backups list`."))?;
+
+        backups.iter().find(|backup| backup.id == id)
+    };
+
+    backup
+        .cloned()
+        .ok_or_else(|| format!("There is no backup {id} of {}.", target.display()))
+}
+
+pub fn show_backups(target: &OutputTarget, backups: &[Backup]) {
+    if backups.is_empty() {
+        println!(
+            "{}",
+            format!("\nThere are no backups of {}…", target.display()).cyan()
+        );
+
+        return;
+    }
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(vec![
+            Cell::new("ID").add_attribute(Attribute::Bold),
+            Cell::new("CREATED").add_attribute(Attribute::Bold),
+            Cell::new("CONTENTS").add_attribute(Attribute::Bold),
+        ]);
+
+    for backup in backups {
+        table.add_row(vec![
+            Cell::new(backup.id),
+            Cell::new(backup.timestamp()),
+            Cell::new(backup.summary()),
+        ]);
+    }
+
+    println!(
+        "{}",
+        format!("\nBackups of {}, newest first:", target.display()).cyan()
+    );
+
+    println!("\n{table}");
+}
+
+pub fn restore_backup(backup: &Backup, target: &OutputTarget) -> Result<(), String> {
+    // The backup is copied, not moved, so it stays
+    // available if the restored file turns out to be wrong.
+    let contents = fs::read(&backup.path)
+        .map_err(|e| format!("Could not read {}: {e}", backup.path.display()))?;
+
+    replace_atomically(&target.path, &contents)
+        .map_err(|e| format!("Could not restore {}: {e}", target.display()))
+}
+
+pub fn prune_backups(
+    backups: &[Backup],
+    keep: usize,
+    dry_run: bool,
+) -> Vec<Result<PathBuf, String>> {
+    backups
+        .iter()
+        .skip(keep)
+        .map(|backup| {
+            if !dry_run {
+                fs::remove_file(&backup.path)
+                    .map_err(|e| format!("Could not remove {}: {e}", backup.path.display()))?;
+            }
+
+            Ok(backup.path.clone())
+        })
+        .collect()
+}
+
+fn summarize(contents: &str) -> String {
+    let mut summary = format!("{} lines", contents.lines().count());
+
+    let conf = match parse(contents) {
+        Err(e) => return format!("{summary}, not a valid ALSA configuration ({e})"),
+        Ok(conf) => conf,
+    };
+
+    let definitions: Vec<String> = ["pcm", "ctl"]
+        .iter()
+        .flat_map(|namespace| {
+            conf.ids(namespace)
+                .into_iter()
+                .map(move |id| format!("{namespace}.{id}"))
+        })
+        .collect();
+
+    let mut cards = Vec::with_capacity(2);
+
+    collect_cards(&conf, &mut cards);
+
+    if !definitions.is_empty() {
+        summary.push_str(&format!(", defines {}", definitions.join(" ")));
+    }
+
+    if !cards.is_empty() {
+        summary.push_str(&format!(", cards {}", cards.join(" ")));
+    }
+
+    summary
+}
+
+fn format_timestamp(millis: u128) -> String {
+    // Civil from days, see:
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let seconds = millis / MS_PER_SECOND;
+    let days = (seconds / SECONDS_PER_DAY) as i64;
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        seconds_of_day / 3600,
+        (seconds_of_day % 3600) / 60,
+        seconds_of_day % 60
+    )
+}
+
+// Every card a config refers to, however deep down.
+fn collect_cards(compound: &Compound, cards: &mut Vec<String>) {
+    for node in compound.nodes() {
+        match &node.value {
+            Value::Compound(compound) | Value::Func { args: compound, .. } => {
+                collect_cards(compound, cards)
+            }
+            value if node.path.last().map_or(false, |id| id.name == "card") => {
+                let card = match value {
+                    Value::String(card) => card.clone(),
+                    value => value.to_string(),
+                };
+
+                if !cards.contains(&card) {
+                    cards.push(card);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    // A target in dir with backups that have ids, and a few files that only look like backups.
+    fn target_with_backups(dir: &Path, ids: &[u128]) -> OutputTarget {
+        let target = OutputTarget::new(Some(&dir.join("asound.conf")), false, false).unwrap();
+
+        fs::write(&target.path, "current").unwrap();
+
+        for id in ids {
+            fs::write(format!("{}.bak{id}", target.display()), id.to_string()).unwrap();
+        }
+
+        for not_a_backup in [".bak", ".bakfoo", ".bak12x", ".bak-1", ".orig1000"] {
+            fs::write(format!("{}{not_a_backup}", target.display()), "").unwrap();
+        }
+
+        target
+    }
+
+    fn ids(backups: &[Backup]) -> Vec<u128> {
+        backups.iter().map(|backup| backup.id).collect()
+    }
+
+    #[test]
+    fn backups_are_found_newest_first() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        // Glob characters in the path are taken literally.
+        let dir = temp_dir.path().join("etc [1]");
+
+        fs::create_dir(&dir).unwrap();
+
+        let target = target_with_backups(&dir, &[1000, 3000, 20000]);
+
+        assert_eq!(ids(&find_backups(&target)), [20000, 3000, 1000]);
+
+        assert_eq!(find_backup(&target, "latest").unwrap().id, 20000);
+        assert_eq!(find_backup(&target, "3000").unwrap().id, 3000);
+        assert!(find_backup(&target, "2000")
+            .unwrap_err()
+            .starts_with("There is no backup 2000"));
+        assert!(find_backup(&target, "foo")
+            .unwrap_err()
+            .starts_with("foo is not a backup id"));
+
+        let empty = tempfile::tempdir().unwrap();
+        let target = target_with_backups(empty.path(), &[]);
+
+        assert!(find_backups(&target).is_empty());
+        assert!(find_backup(&target, "latest").is_err());
+    }
+
+    #[test]
+    fn pruning_keeps_the_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = target_with_backups(dir.path(), &[1000, 3000, 2000, 4000]);
+        let backups = find_backups(&target);
+
+        // A dry run only says what would be removed.
+        let pruned = prune_backups(&backups, 2, true);
+
+        assert_eq!(pruned.len(), 2);
+        assert_eq!(ids(&find_backups(&target)), [4000, 3000, 2000, 1000]);
+
+        let pruned: Vec<PathBuf> = prune_backups(&backups, 2, false)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(pruned, [backups[2].path.clone(), backups[3].path.clone()]);
+        assert_eq!(ids(&find_backups(&target)), [4000, 3000]);
+
+        assert!(prune_backups(&find_backups(&target), 5, false).is_empty());
+    }
+
+    #[test]
+    fn restoring_copies_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = target_with_backups(dir.path(), &[1000, 2000]);
+        let backup = find_backup(&target, "1000").unwrap();
+
+        restore_backup(&backup, &target).unwrap();
+
+        assert_eq!(fs::read_to_string(&target.path).unwrap(), "1000");
+        assert!(backup.path.exists());
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951782400000), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1709251199999), "2024-02-29 23:59:59 UTC");
+        assert_eq!(format_timestamp(1760572800123), "2025-10-16 00:00:00 UTC");
+        // 2100 is not a leap year.
+        assert_eq!(format_timestamp(4107542399000), "2100-02-28 23:59:59 UTC");
+        assert_eq!(format_timestamp(4107542400000), "2100-03-01 00:00:00 UTC");
+    }
+}
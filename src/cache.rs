@@ -0,0 +1,244 @@
+use std::{
+    ffi::CStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glob::glob;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{replace_atomically, report::ProbeReport};
+
+pub const CACHE_DIR: &str = "/var/cache/awiz";
+const CACHE_FILE_NAME: &str = "probe.json";
+
+// Bump this whenever the layout of the cache file changes.
+// An old cache is then simply ignored and replaced.
+const CACHE_SCHEMA_VERSION: u32 = 1;
+
+// Everything that could change the result of a probe. If any of it
+// differs from when the cache was written the cache is stale.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub awiz_version: String,
+    pub alsa_lib_version: String,
+    // The contents of /proc/asound/cards.
+    pub cards: String,
+    pub card_details: Vec<CardFingerprint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CardFingerprint {
+    pub index: u32,
+    pub id: String,
+    // vendor:product, only USB cards have one.
+    pub usb_id: Option<String>,
+    pub driver: Option<String>,
+}
+
+impl Fingerprint {
+    // None if there is no /proc/asound, without it
+    // there's no telling whether anything changed.
+    pub fn current() -> Option<Self> {
+        let cards = fs::read_to_string("/proc/asound/cards").ok()?;
+
+        let mut card_details: Vec<CardFingerprint> = glob("/proc/asound/card[0-9]*")
+            .ok()?
+            .flatten()
+            .filter_map(|path| {
+                let index = path
+                    .file_name()?
+                    .to_str()?
+                    .trim_start_matches("card")
+                    .parse::<u32>()
+                    .ok()?;
+
+                Some(CardFingerprint {
+                    index,
+                    id: read_trimmed(&path.join("id"))?,
+                    usb_id: read_trimmed(&path.join("usbid")),
+                    driver: fs::read_link(format!("/sys/class/sound/card{index}/device/driver"))
+                        .ok()
+                        .and_then(|driver| Some(driver.file_name()?.to_str()?.to_string())),
+                })
+            })
+            .collect();
+
+        card_details.sort_by_key(|card| card.index);
+
+        Some(Self {
+            awiz_version: env!("CARGO_PKG_VERSION").to_string(),
+            alsa_lib_version: alsa_lib_version(),
+            cards,
+            card_details,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    schema_version: u32,
+    fingerprint: Fingerprint,
+    report: ProbeReport,
+}
+
+#[derive(Debug)]
+pub struct ProbeCache {
+    path: PathBuf,
+}
+
+impl ProbeCache {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(CACHE_FILE_NAME),
+        }
+    }
+
+    // The cached report, if there is one and it was
+    // written for hardware matching fingerprint.
+    pub fn load(&self, fingerprint: &Fingerprint) -> Option<ProbeReport> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+
+        let cache: CacheFile = serde_json::from_str(&contents).ok()?;
+
+        (cache.schema_version == CACHE_SCHEMA_VERSION && cache.fingerprint == *fingerprint)
+            .then_some(cache.report)
+    }
+
+    pub fn store(&self, fingerprint: &Fingerprint, report: &ProbeReport) -> Result<(), String> {
+        let cache = serde_json::to_string(&CacheFile {
+            schema_version: CACHE_SCHEMA_VERSION,
+            fingerprint: fingerprint.clone(),
+            report: report.clone(),
+        })
+        .map_err(|e| format!("Could not serialize the probe cache: {e}"))?;
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+        }
+
+        replace_atomically(&self.path, cache.as_bytes())
+            .map_err(|e| format!("Could not write {}: {e}", self.path.display()))
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|contents| contents.trim().to_string())
+}
+
+fn alsa_lib_version() -> String {
+    // The version of the alsa-lib that is actually loaded,
+    // not the one awiz happened to be built against.
+    unsafe { CStr::from_ptr(alsa_sys::snd_asoundlib_version()) }
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/session-report.json");
+
+    fn report() -> ProbeReport {
+        serde_json::from_str(&fs::read_to_string(REPORT).unwrap()).unwrap()
+    }
+
+    fn fingerprint() -> Fingerprint {
+        Fingerprint {
+            awiz_version: env!("CARGO_PKG_VERSION").to_string(),
+            alsa_lib_version: "1.2.8".to_string(),
+            cards: " 0 [DAC            ]: USB-Audio - USB Audio DAC\n".to_string(),
+            card_details: vec![CardFingerprint {
+                index: 0,
+                id: "DAC".to_string(),
+                usb_id: Some("1234:5678".to_string()),
+                driver: Some("snd-usb-audio".to_string()),
+            }],
+        }
+    }
+
+    fn loaded(cache: &ProbeCache, fingerprint: &Fingerprint) -> Option<String> {
+        cache
+            .load(fingerprint)
+            .map(|report| report.to_json().unwrap())
+    }
+
+    #[test]
+    fn matching_fingerprints_reuse_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        // The directory is created as needed.
+        let cache = ProbeCache::new(&dir.path().join("awiz"));
+
+        assert_eq!(loaded(&cache, &fingerprint()), None);
+
+        cache.store(&fingerprint(), &report()).unwrap();
+
+        assert_eq!(
+            loaded(&cache, &fingerprint()),
+            Some(report().to_json().unwrap())
+        );
+    }
+
+    #[test]
+    fn changed_fingerprints_invalidate_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ProbeCache::new(dir.path());
+
+        cache.store(&fingerprint(), &report()).unwrap();
+
+        let changes: [fn(&mut Fingerprint); 6] = [
+            |f| f.awiz_version = "0.0.1".to_string(),
+            |f| f.alsa_lib_version = "1.2.9".to_string(),
+            |f| {
+                f.cards
+                    .push_str(" 1 [HDMI           ]: HDA-Intel - HDA Intel HDMI\n")
+            },
+            |f| f.card_details[0].id = "DAC_1".to_string(),
+            |f| f.card_details[0].usb_id = Some("1234:5679".to_string()),
+            |f| f.card_details[0].driver = None,
+        ];
+
+        for change in changes {
+            let mut changed = fingerprint();
+
+            change(&mut changed);
+
+            assert_eq!(loaded(&cache, &changed), None, "{changed:?}");
+        }
+
+        // Storing it again for the changed hardware replaces it.
+        let mut changed = fingerprint();
+
+        changed.card_details.clear();
+
+        cache.store(&changed, &report()).unwrap();
+
+        assert!(loaded(&cache, &changed).is_some());
+        assert_eq!(loaded(&cache, &fingerprint()), None);
+    }
+
+    #[test]
+    fn other_schemas_and_garbage_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ProbeCache::new(dir.path());
+
+        let old = serde_json::json!({
+            "schema_version": CACHE_SCHEMA_VERSION - 1,
+            "fingerprint": fingerprint(),
+            "report": report(),
+        });
+
+        fs::write(&cache.path, old.to_string()).unwrap();
+
+        assert_eq!(loaded(&cache, &fingerprint()), None);
+
+        fs::write(&cache.path, "{\"schema_version\": 1,").unwrap();
+
+        assert_eq!(loaded(&cache, &fingerprint()), None);
+    }
+}
@@ -0,0 +1,421 @@
+use std::{fmt, path::PathBuf, time::Duration};
+
+use alsa::pcm::Format;
+
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+
+use crate::{auto::ScoringPolicy, AlsaPcm, ProbeOptions, FORMATS};
+
+const DEFAULT_RATE_FAMILY: u32 = 48000;
+const DEFAULT_TARGET_BUFFER_TIME_MS: u32 = 100;
+const DEFAULT_PROBE_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "awiz",
+    version,
+    about = "An interactive utility that generates a very simple /etc/asound.conf.",
+    long_about = "An interactive utility that generates a very simple /etc/asound.conf.\n\n\
+        Run without arguments to step through the wizard interactively. \
+        Passing --playback and/or --capture skips the prompts and \
+        validates the requested Configuration against the probed hardware instead. \
+        Passing --auto picks the best Configuration according to a scoring policy.",
+    args_conflicts_with_subcommands = true,
+    group(ArgGroup::new("unattended").args(["playback", "capture", "auto"]).multiple(true))
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The Playback PCM to configure, for example hw:CARD=DAC,DEV=0.
+    #[arg(long, value_name = "PCM")]
+    pub playback: Option<String>,
+
+    /// The Capture PCM to configure, for example hw:CARD=Mic,DEV=0.
+    #[arg(long, value_name = "PCM")]
+    pub capture: Option<String>,
+
+    /// Format, for example S32_LE. Also used for Capture unless --capture-format is given.
+    #[arg(long, value_name = "FORMAT", value_parser = parse_format, requires = "unattended")]
+    pub format: Option<Format>,
+
+    /// Sampling Rate. Also used for Capture unless --capture-rate is given.
+    #[arg(long, value_name = "RATE", requires = "unattended")]
+    pub rate: Option<u32>,
+
+    /// Channel Count. Also used for Capture unless --capture-channels is given.
+    #[arg(long, value_name = "CHANNELS", requires = "unattended")]
+    pub channels: Option<u32>,
+
+    /// Buffer Time in milliseconds. Also used for Capture unless --capture-buffer-time is given. Defaults to the nearest supported one to half the largest, or with --auto to --target-buffer-time.
+    #[arg(long, value_name = "MS", requires = "unattended")]
+    pub buffer_time: Option<u32>,
+
+    /// Capture Format, if it differs from --format.
+    #[arg(long, value_name = "FORMAT", value_parser = parse_format, requires = "capture")]
+    pub capture_format: Option<Format>,
+
+    /// Capture Sampling Rate, if it differs from --rate.
+    #[arg(long, value_name = "RATE", requires = "capture")]
+    pub capture_rate: Option<u32>,
+
+    /// Capture Channel Count, if it differs from --channels.
+    #[arg(long, value_name = "CHANNELS", requires = "capture")]
+    pub capture_channels: Option<u32>,
+
+    /// Capture Buffer Time in milliseconds, if it differs from --buffer-time.
+    #[arg(long, value_name = "MS", requires = "capture")]
+    pub capture_buffer_time: Option<u32>,
+
+    /// Sample Rate Converter, for example speexrate_medium.
+    #[arg(long, value_name = "CONVERTER", requires = "unattended")]
+    pub converter: Option<String>,
+
+    /// Commit the Configuration without asking for confirmation.
+    #[arg(short, long, requires = "unattended")]
+    pub yes: bool,
+
+    /// Pick the best Configuration automatically. Combined with --playback or --capture
+    /// only that PCM is considered, otherwise every PCM is.
+    #[arg(long)]
+    pub auto: bool,
+
+    /// The Sampling Rate family --auto prefers, 48000 (the default) or 44100.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate_family, requires = "auto")]
+    pub rate_family: Option<u32>,
+
+    /// The Channel Count --auto prefers. Defaults to the largest Channel Count of each PCM.
+    #[arg(long, value_name = "CHANNELS", requires = "auto")]
+    pub prefer_channels: Option<u32>,
+
+    /// The Buffer Time in milliseconds --auto aims for. Defaults to 100.
+    #[arg(long, value_name = "MS", requires = "auto")]
+    pub target_buffer_time: Option<u32>,
+
+    /// Save the choices to a preset file that can be replayed with `awiz apply`, once they are written.
+    #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+    pub save_preset: Option<PathBuf>,
+
+    /// Print the generated configuration to stdout, and everything else to stderr, instead of writing it. Nothing is touched.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Write the configuration to PATH instead of /etc/asound.conf.
+    #[arg(long, value_name = "PATH", global = true, conflicts_with = "user")]
+    pub output: Option<PathBuf>,
+
+    /// Write the configuration to ~/.asoundrc instead of /etc/asound.conf.
+    #[arg(long, global = true)]
+    pub user: bool,
+
+    /// Probe the simulated devices described in FILE instead of the real sound cards. Implies --dry-run unless --output is given.
+    #[arg(long, value_name = "FILE", global = true)]
+    pub simulate: Option<PathBuf>,
+
+    /// Replay a session recorded with `awiz probe --record` instead of probing the real sound cards. Implies --dry-run unless --output is given.
+    #[arg(long, value_name = "FILE", global = true, conflicts_with = "simulate")]
+    pub replay: Option<PathBuf>,
+
+    /// Probe the sound cards even if the results of an earlier probe are cached.
+    #[arg(long, global = true)]
+    pub reprobe: bool,
+
+    /// How many seconds probing a sound card may take before it is ignored. Defaults to 30.
+    #[arg(long, value_name = "SECONDS", global = true)]
+    pub probe_timeout: Option<u64>,
+
+    /// Wait for PCMs that are in use to be released instead of ignoring them.
+    #[arg(long, global = true)]
+    pub wait_busy: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Replay the choices stored in a preset file without any prompts.
+    Apply {
+        /// The preset file, as written by --save-preset.
+        preset: PathBuf,
+
+        /// Commit the Configuration without asking for confirmation.
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// List, restore and prune backups of the configuration file.
+    Backups {
+        #[command(subcommand)]
+        action: BackupsAction,
+    },
+    /// Show how the Configuration stored in a preset file differs from the configuration file.
+    Diff {
+        /// The preset file, as written by --save-preset.
+        preset: PathBuf,
+    },
+    /// Show what an existing ALSA configuration file does.
+    Inspect {
+        /// The file to inspect. Defaults to the configuration file, see --output and --user.
+        file: Option<PathBuf>,
+
+        /// Import the default PCM of the file into a preset that can be replayed with `awiz apply`.
+        #[arg(long, value_name = "PATH")]
+        save_preset: Option<PathBuf>,
+    },
+    /// Probe the hardware and report what every PCM supports.
+    Probe {
+        /// Print the report as JSON.
+        #[arg(long, conflicts_with = "yaml")]
+        json: bool,
+
+        /// Print the report as YAML.
+        #[arg(long)]
+        yaml: bool,
+
+        /// Record every query made to the hardware to FILE, for `awiz --replay`.
+        #[arg(long, value_name = "FILE")]
+        record: Option<PathBuf>,
+
+        /// Time how long probing, and retrieving the Buffer Times of every Configuration, takes.
+        #[arg(long, conflicts_with_all = ["json", "yaml"])]
+        bench: bool,
+
+        /// Show which step made every invalid combination of Format, Sampling Rate and Channel Count invalid.
+        #[arg(long)]
+        explain: bool,
+    },
+    /// Test the PCMs of the configuration file, see --output and --user.
+    Test {
+        #[command(subcommand)]
+        action: TestAction,
+    },
+}
+
+impl Cli {
+    pub fn is_interactive(&self) -> bool {
+        self.playback.is_none() && self.capture.is_none() && !self.auto
+    }
+
+    pub fn playback_request(&self) -> Option<ConfigRequest> {
+        self.pcm_selector(self.playback.as_ref())
+            .map(|pcm| ConfigRequest {
+                pcm,
+                format: self.format,
+                rate: self.rate,
+                channels: self.channels,
+                buffer_time_ms: self.buffer_time,
+            })
+    }
+
+    pub fn capture_request(&self) -> Option<ConfigRequest> {
+        self.pcm_selector(self.capture.as_ref())
+            .map(|pcm| ConfigRequest {
+                pcm,
+                format: self.capture_format.or(self.format),
+                rate: self.capture_rate.or(self.rate),
+                channels: self.capture_channels.or(self.channels),
+                buffer_time_ms: self.capture_buffer_time.or(self.buffer_time),
+            })
+    }
+
+    pub fn scoring_policy(&self) -> Option<ScoringPolicy> {
+        self.auto.then_some(ScoringPolicy {
+            rate_family: self.rate_family.unwrap_or(DEFAULT_RATE_FAMILY),
+            channels: self.prefer_channels,
+            buffer_time_ms: self
+                .target_buffer_time
+                .unwrap_or(DEFAULT_TARGET_BUFFER_TIME_MS),
+        })
+    }
+
+    pub fn probe_options(&self) -> ProbeOptions {
+        ProbeOptions {
+            reprobe: self.reprobe,
+            timeout: Duration::from_secs(self.probe_timeout.unwrap_or(DEFAULT_PROBE_TIMEOUT_SECS)),
+            wait_busy: self.wait_busy,
+            explain: false,
+            quiet: false,
+            dry_run: self.dry_run,
+        }
+    }
+
+    fn pcm_selector(&self, pcm: Option<&String>) -> Option<PcmSelector> {
+        // With --auto and no explicit PCMs every PCM
+        // of both directions is a candidate.
+        match pcm {
+            Some(pcm) => Some(PcmSelector::Name(pcm.clone())),
+            None if self.auto && self.playback.is_none() && self.capture.is_none() => {
+                Some(PcmSelector::Any)
+            }
+            None => None,
+        }
+    }
+}
+
+// The choices a user would otherwise make at the prompts.
+// Anything left as None must be unambiguous for the PCM,
+// unless --auto is used in which case it is ranked instead.
+#[derive(Debug, Clone)]
+pub struct ConfigRequest {
+    pub pcm: PcmSelector,
+    pub format: Option<Format>,
+    pub rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub buffer_time_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PcmSelector {
+    // Every PCM, only used by --auto.
+    Any,
+    // An exact PCM name, for example hw:CARD=DAC,DEV=0.
+    Name(String),
+    // Anything that was given has to match. This is what
+    // presets use so that they survive card renumbering.
+    Match {
+        card_name: Option<String>,
+        description: Option<String>,
+        device_number: Option<u32>,
+    },
+}
+
+impl PcmSelector {
+    pub fn matches(&self, pcm: &AlsaPcm) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Name(name) => pcm.name == *name,
+            Self::Match {
+                card_name,
+                description,
+                device_number,
+            } => {
+                card_name.as_ref().map_or(true, |c| *c == pcm.card_name)
+                    && description.as_ref().map_or(true, |d| *d == pcm.description)
+                    && device_number.map_or(true, |d| d == pcm.device_number)
+            }
+        }
+    }
+}
+
+impl fmt::Display for PcmSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => write!(f, "Any PCM"),
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Match {
+                card_name,
+                description,
+                device_number,
+            } => {
+                let mut parts = Vec::with_capacity(3);
+
+                if let Some(card_name) = card_name {
+                    parts.push(format!("CARD={card_name}"));
+                }
+
+                if let Some(device_number) = device_number {
+                    parts.push(format!("DEV={device_number}"));
+                }
+
+                if let Some(description) = description {
+                    parts.push(format!("DESCRIPTION=\"{description}\""));
+                }
+
+                write!(f, "[{}]", parts.join(","))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupsAction {
+    /// List every backup with its timestamp and a summary of its contents.
+    List,
+    /// Restore a backup. The current file is backed up first.
+    Restore {
+        /// The id of the backup as shown by `awiz backups list`, or "latest".
+        id: String,
+    },
+    /// Remove all but the newest backups.
+    Prune {
+        /// How many of the newest backups to keep.
+        #[arg(long, value_name = "N")]
+        keep: usize,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TestAction {
+    /// Play a test signal on every channel in turn and show which channel is playing.
+    Playback {
+        /// The test signal, pink-noise (the default) or sine.
+        #[arg(long, value_enum)]
+        signal: Option<TestSignal>,
+
+        /// Only play the signal on this channel, counting from 1.
+        #[arg(long, value_name = "CHANNEL")]
+        channel: Option<u32>,
+
+        /// How many seconds the signal is played on each channel. Defaults to 2.
+        #[arg(long, value_name = "SECONDS")]
+        duration: Option<u64>,
+
+        /// How many times to go through the channels, 0 keeps going until Ctrl+C. Defaults to 1.
+        #[arg(long, value_name = "N")]
+        loops: Option<u32>,
+    },
+    /// Record from every channel with live level meters and check that they all record something.
+    Capture {
+        /// How many seconds to record. Defaults to 10.
+        #[arg(long, value_name = "SECONDS")]
+        duration: Option<u64>,
+    },
+    /// Play a test signal while recording it back and measure the round-trip latency.
+    /// The Playback PCM has to be connected to the Capture PCM, with a cable or snd-aloop.
+    Loopback,
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum TestSignal {
+    /// A 440 Hz sine.
+    Sine,
+    /// Pink noise, which is easier to place than a sine.
+    #[default]
+    PinkNoise,
+}
+
+pub fn parse_format(s: &str) -> Result<Format, String> {
+    // Accept both the full name (S24_3LE) and the
+    // native endian short hand (S24_3).
+    let wanted = s.trim().to_uppercase();
+
+    FORMATS
+        .iter()
+        .find(|f| {
+            let name = f.to_string();
+
+            name == wanted
+                || name
+                    .trim_end_matches("LE")
+                    .trim_end_matches("BE")
+                    .trim_end_matches('_')
+                    == wanted
+        })
+        .copied()
+        .ok_or_else(|| {
+            let supported_formats = FORMATS
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            format!("{s} is not supported by dmix/dsnoop ({supported_formats})")
+        })
+}
+
+pub fn parse_rate_family(s: &str) -> Result<u32, String> {
+    match s.trim().to_lowercase().as_str() {
+        "48000" | "48k" => Ok(48000),
+        "44100" | "44.1k" => Ok(44100),
+        _ => Err(format!(
+            "{s} is not a Sampling Rate family (48000 or 44100)"
+        )),
+    }
+}
@@ -0,0 +1,1033 @@
+use std::fmt::{self, Write};
+
+// A typed model of the ALSA configuration file format, the
+// serializer that turns it into a file and the parser that
+// reads one back in.
+// See:
+// https://www.alsa-project.org/alsa-doc/alsa-lib/conf.html
+
+const INDENT: &str = "    ";
+
+// Characters that end an unquoted word.
+const DELIMITERS: &[char] = &['{', '}', '[', ']', '=', ',', ';', '#', '"', '\'', '<', '>'];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Real(f64),
+    String(String),
+    Compound(Compound),
+    Array(Vec<Value>),
+    // A value alsa-lib works out when it loads the configuration,
+    // for example `@func refer` to use the value of another node.
+    Func { name: String, args: Compound },
+}
+
+impl Value {
+    // The value of the node at path, whatever it is by then.
+    pub fn refer(path: &str) -> Self {
+        Self::Func {
+            name: "refer".to_string(),
+            args: Compound::new().with("name", path),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&Compound> {
+        match self {
+            Self::Compound(compound) => Some(compound),
+            _ => None,
+        }
+    }
+}
+
+// Strings and numbers the way they would be written in a file,
+// compounds and functions as a short placeholder.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(value) => write!(f, "{value}"),
+            Self::Real(value) => write!(f, "{value:?}"),
+            Self::String(value) => write!(f, "{}", quote(value, false)),
+            Self::Compound(_) => write!(f, "{{…}}"),
+            Self::Array(values) => write!(
+                f,
+                "[{}]",
+                values
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            Self::Func { name, .. } => write!(f, "@func {name}"),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Self::Integer(value.into())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<Compound> for Value {
+    fn from(value: Compound) -> Self {
+        Self::Compound(value)
+    }
+}
+
+// What happens when a node with the same id already exists,
+// for example in /usr/share/alsa/alsa.conf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    // Merge into it, or create it. Written as nothing or `+`.
+    #[default]
+    Merge,
+    // Merge into it, but don't create it. `-`
+    MergeExisting,
+    // Leave it alone, only create it. `?`
+    Default,
+    // Replace it. `!`
+    Override,
+}
+
+impl Mode {
+    fn prefix(&self) -> &'static str {
+        match self {
+            Self::Merge => "",
+            Self::MergeExisting => "-",
+            Self::Default => "?",
+            Self::Override => "!",
+        }
+    }
+
+    fn from_prefix(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Self::Merge),
+            '-' => Some(Self::MergeExisting),
+            '?' => Some(Self::Default),
+            '!' => Some(Self::Override),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Id {
+    pub name: String,
+    pub mode: Mode,
+}
+
+// `a.b.c value` is shorthand for `a { b { c value } }`,
+// so a node has a path of ids rather than a single one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub path: Vec<Id>,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Node(Node),
+    // <file>, or <confdir:file> relative to /usr/share/alsa.
+    // Included files are not read, only recorded.
+    Include(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Compound {
+    pub items: Vec<Item>,
+}
+
+impl Compound {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, path: &str, value: impl Into<Value>) -> Self {
+        self.push(path, value);
+        self
+    }
+
+    // path is written the way it would be in a file, `pcm.!default`.
+    // It's always a literal, anything that comes from the hardware
+    // or the user only ever goes into values, which are escaped.
+    pub fn push(&mut self, path: &str, value: impl Into<Value>) {
+        let path = path
+            .split('.')
+            .map(|id| match id.chars().next().and_then(Mode::from_prefix) {
+                Some(mode) => Id {
+                    name: id[1..].to_string(),
+                    mode,
+                },
+                None => Id {
+                    name: id.to_string(),
+                    mode: Mode::Merge,
+                },
+            })
+            .collect();
+
+        self.items.push(Item::Node(Node {
+            path,
+            value: value.into(),
+        }));
+    }
+
+    pub fn nodes(&self) -> impl DoubleEndedIterator<Item = &Node> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Node(node) => Some(node),
+            Item::Include(_) => None,
+        })
+    }
+
+    pub fn includes(&self) -> impl Iterator<Item = &str> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Include(path) => Some(path.as_str()),
+            Item::Node(_) => None,
+        })
+    }
+
+    // The value at a dotted path such as `pcm.default.type`, no matter
+    // whether it was written as `pcm.default.type` or in nested compounds.
+    // Compounds that are defined more than once are merged the way
+    // alsa-lib merges them, anything else is replaced by the last one.
+    pub fn get(&self, path: &str) -> Option<Value> {
+        let path: Vec<&str> = path.split('.').collect();
+
+        self.lookup(&path)
+    }
+
+    pub fn get_str(&self, path: &str) -> Option<String> {
+        match self.get(path)? {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn lookup(&self, path: &[&str]) -> Option<Value> {
+        let mut found = None;
+
+        for node in self.nodes() {
+            let common = common_len(&node.path, path);
+
+            if common == node.path.len() {
+                // The node is path itself or one of its parents.
+                let rest = &path[common..];
+
+                let value = if rest.is_empty() {
+                    Some(node.value.clone())
+                } else {
+                    node.value.as_compound().and_then(|c| c.lookup(rest))
+                };
+
+                // An override throws away whatever was there before,
+                // even if it doesn't define path itself.
+                if node.path.iter().any(|id| id.mode == Mode::Override) {
+                    found = value;
+                } else {
+                    found = merge(found, value, false);
+                }
+            } else if common == path.len() {
+                // The node is somewhere below path.
+                let below = Compound {
+                    items: vec![Item::Node(Node {
+                        path: node.path[common..].to_vec(),
+                        value: node.value.clone(),
+                    })],
+                };
+
+                let replace = node.path[..common]
+                    .iter()
+                    .any(|id| id.mode == Mode::Override);
+
+                found = merge(found, Some(Value::Compound(below)), replace);
+            }
+        }
+
+        found
+    }
+
+    // The ids defined directly below a dotted path, for example
+    // every PCM for `pcm`, in the order they first appear.
+    pub fn ids(&self, path: &str) -> Vec<String> {
+        let path: Vec<&str> = path.split('.').filter(|id| !id.is_empty()).collect();
+
+        let mut ids = vec![];
+
+        self.collect_ids(&path, &mut ids);
+
+        ids
+    }
+
+    fn collect_ids(&self, path: &[&str], ids: &mut Vec<String>) {
+        for node in self.nodes() {
+            let common = common_len(&node.path, path);
+
+            if common == path.len() && node.path.len() > path.len() {
+                let id = &node.path[common].name;
+
+                if !ids.contains(id) {
+                    ids.push(id.clone());
+                }
+            } else if common == node.path.len() {
+                if let Some(compound) = node.value.as_compound() {
+                    compound.collect_ids(&path[common..], ids);
+                }
+            }
+        }
+    }
+
+    fn write_items(&self, out: &mut String, depth: usize) -> fmt::Result {
+        for item in &self.items {
+            item.write(out, depth)?;
+        }
+
+        Ok(())
+    }
+}
+
+// How many ids node_path and path have in common from the start.
+fn common_len(node_path: &[Id], path: &[&str]) -> usize {
+    node_path
+        .iter()
+        .zip(path)
+        .take_while(|(id, name)| id.name == **name)
+        .count()
+}
+
+fn merge(old: Option<Value>, new: Option<Value>, replace: bool) -> Option<Value> {
+    match (old, new) {
+        (old, None) => old,
+        (Some(Value::Compound(mut old)), Some(Value::Compound(new))) if !replace => {
+            old.items.extend(new.items);
+            Some(Value::Compound(old))
+        }
+        (_, new) => new,
+    }
+}
+
+impl Item {
+    fn write(&self, out: &mut String, depth: usize) -> fmt::Result {
+        let indent = INDENT.repeat(depth);
+
+        match self {
+            Self::Include(path) => writeln!(out, "{indent}<{path}>"),
+            Self::Node(node) => {
+                let key = node
+                    .path
+                    .iter()
+                    .map(|id| format!("{}{}", id.mode.prefix(), quote(&id.name, true)))
+                    .collect::<Vec<String>>()
+                    .join(".");
+
+                write!(out, "{indent}{key} ")?;
+
+                write_value(out, &node.value, depth)
+            }
+        }
+    }
+}
+
+fn write_value(out: &mut String, value: &Value, depth: usize) -> fmt::Result {
+    let indent = INDENT.repeat(depth);
+
+    match value {
+        Value::Compound(compound) => {
+            writeln!(out, "{{")?;
+            compound.write_items(out, depth + 1)?;
+            writeln!(out, "{indent}}}")
+        }
+        Value::Func { name, args } => {
+            writeln!(out, "{{")?;
+            writeln!(out, "{indent}{INDENT}@func {}", quote(name, false))?;
+            args.write_items(out, depth + 1)?;
+            writeln!(out, "{indent}}}")
+        }
+        Value::Array(values) => {
+            writeln!(out, "[")?;
+
+            for value in values {
+                write!(out, "{indent}{INDENT}")?;
+                write_value(out, value, depth + 1)?;
+            }
+
+            writeln!(out, "{indent}]")
+        }
+        value => writeln!(out, "{value}"),
+    }
+}
+
+// A whole file, with a blank line between top level nodes.
+impl fmt::Display for Compound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut blocks = Vec::with_capacity(self.items.len());
+
+        for item in &self.items {
+            let mut block = String::new();
+            item.write(&mut block, 0)?;
+            blocks.push(block);
+        }
+
+        write!(f, "{}", blocks.join("\n").trim_end())
+    }
+}
+
+// Plain words are written as they are, everything else is quoted.
+// A word that starts with a digit or a sign would be read as a number,
+// and in an id a dot would start the next id. Ids may start with @,
+// @func, @hooks and @args are ids with a special meaning.
+fn quote(value: &str, id: bool) -> String {
+    let plain = value.chars().next().map_or(false, |c| {
+        c.is_ascii_alphabetic() || c == '_' || (c == '@' && id)
+    }) && value
+        .chars()
+        .skip(1)
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || (c == '.' && !id));
+
+    // Array elements are numbered, 0, 1 and so on.
+    let index = id && !value.is_empty() && value.chars().all(|c| c.is_ascii_digit());
+
+    if plain || index {
+        return value.to_string();
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+
+    quoted.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            // alsa-lib reads \ followed by up to 3 octal digits as a byte.
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\{:03o}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+// Parse the contents of a configuration file. Includes are
+// recorded but not followed, @func and @hooks are kept as they
+// are for alsa-lib to deal with.
+pub fn parse(source: &str) -> Result<Compound, ParseError> {
+    let mut parser = Parser {
+        chars: source.chars().collect(),
+        pos: 0,
+    };
+
+    let items = parser.items(None)?;
+
+    Ok(Compound { items })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        self.error_at(self.pos, message)
+    }
+
+    fn error_at(&self, pos: usize, message: impl Into<String>) -> ParseError {
+        let before = &self.chars[..pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+
+        ParseError {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    // Whitespace and comments, and with separators also the
+    // optional , and ; that may follow a node or an array element.
+    fn skip(&mut self, separators: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                '#' => while !matches!(self.next(), None | Some('\n')) {},
+                ',' | ';' if separators => self.pos += 1,
+                c if c.is_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn items(&mut self, end: Option<char>) -> Result<Vec<Item>, ParseError> {
+        let mut items = vec![];
+
+        loop {
+            self.skip(true);
+
+            match self.peek() {
+                None => {
+                    return match end {
+                        None => Ok(items),
+                        Some(end) => Err(self.error(format!("Expected {end} before the end"))),
+                    }
+                }
+                Some(c) if Some(c) == end => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                Some('<') => items.push(Item::Include(self.include()?)),
+                Some(_) => items.push(Item::Node(self.node()?)),
+            }
+        }
+    }
+
+    fn include(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+
+        self.pos += 1;
+
+        let mut path = String::new();
+
+        loop {
+            match self.next() {
+                Some('>') => return Ok(path.trim().to_string()),
+                None | Some('\n') => return Err(self.error_at(start, "Unterminated include")),
+                Some(c) => path.push(c),
+            }
+        }
+    }
+
+    fn node(&mut self) -> Result<Node, ParseError> {
+        let mut path = vec![];
+
+        loop {
+            let mode = match self.peek().and_then(Mode::from_prefix) {
+                None => Mode::Merge,
+                Some(mode) => {
+                    self.pos += 1;
+                    mode
+                }
+            };
+
+            // A quoted id may be empty, "", a word may not.
+            let name = match self.peek() {
+                Some('"') | Some('\'') => self.quoted()?,
+                _ => match self.word(true) {
+                    word if !word.is_empty() => word,
+                    _ => {
+                        return Err(match self.peek() {
+                            None => self.error("Expected an id before the end"),
+                            Some(c) => self.error(format!("Expected an id, found {c}")),
+                        })
+                    }
+                },
+            };
+
+            path.push(Id { name, mode });
+
+            if self.peek() != Some('.') {
+                break;
+            }
+
+            self.pos += 1;
+        }
+
+        self.skip(false);
+
+        if self.peek() == Some('=') {
+            self.pos += 1;
+            self.skip(false);
+        }
+
+        let value = self.value()?;
+
+        Ok(Node { path, value })
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            None => Err(self.error("Expected a value before the end")),
+            Some('{') => {
+                self.pos += 1;
+
+                let compound = Compound {
+                    items: self.items(Some('}'))?,
+                };
+
+                Ok(func(compound))
+            }
+            Some('[') => {
+                self.pos += 1;
+
+                let mut values = vec![];
+
+                loop {
+                    self.skip(true);
+
+                    if self.peek() == Some(']') {
+                        self.pos += 1;
+                        return Ok(Value::Array(values));
+                    }
+
+                    values.push(self.value()?);
+                }
+            }
+            Some('"') | Some('\'') => Ok(Value::String(self.quoted()?)),
+            Some(c) if DELIMITERS.contains(&c) => {
+                Err(self.error(format!("Expected a value, found {c}")))
+            }
+            Some(_) => {
+                let word = self.word(false);
+
+                Ok(number(&word).unwrap_or(Value::String(word)))
+            }
+        }
+    }
+
+    fn word(&mut self, id: bool) -> String {
+        let mut word = String::new();
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || DELIMITERS.contains(&c) || (c == '.' && id) {
+                break;
+            }
+
+            word.push(c);
+            self.pos += 1;
+        }
+
+        word
+    }
+
+    fn quoted(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        let delimiter = self.next();
+
+        // Bytes rather than chars, the bytes of a multibyte
+        // character may each be written as an octal escape.
+        let mut value = vec![];
+
+        let push = |value: &mut Vec<u8>, c: char| {
+            value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+        };
+
+        loop {
+            match self.next() {
+                None => return Err(self.error_at(start, "Unterminated string")),
+                Some(c) if Some(c) == delimiter => {
+                    return Ok(String::from_utf8_lossy(&value).into_owned())
+                }
+                Some('\\') => match self.next() {
+                    None => return Err(self.error_at(start, "Unterminated string")),
+                    Some('n') => push(&mut value, '\n'),
+                    Some('t') => push(&mut value, '\t'),
+                    Some('v') => push(&mut value, '\x0b'),
+                    Some('b') => push(&mut value, '\x08'),
+                    Some('r') => push(&mut value, '\r'),
+                    Some('f') => push(&mut value, '\x0c'),
+                    Some(c @ '0'..='7') => {
+                        let mut byte = c.to_digit(8).unwrap_or(0);
+
+                        for _ in 0..2 {
+                            match self.peek().and_then(|c| c.to_digit(8)) {
+                                None => break,
+                                Some(digit) => {
+                                    byte = byte * 8 + digit;
+                                    self.pos += 1;
+                                }
+                            }
+                        }
+
+                        value.push((byte & 0xff) as u8);
+                    }
+                    Some(c) => push(&mut value, c),
+                },
+                Some(c) => push(&mut value, c),
+            }
+        }
+    }
+}
+
+// alsa-lib reads anything that looks like a number as one.
+fn number(word: &str) -> Option<Value> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, word.strip_prefix('+').unwrap_or(word)),
+    };
+
+    let integer = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => digits
+            .chars()
+            .all(|c| c.is_ascii_digit())
+            .then(|| digits.parse::<i64>().ok())
+            .flatten(),
+    };
+
+    if let Some(integer) = integer {
+        return Some(Value::Integer(if negative { -integer } else { integer }));
+    }
+
+    let starts_like_a_number = digits
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_digit() || c == '.');
+
+    starts_like_a_number
+        .then(|| word.parse::<f64>().ok().map(Value::Real))
+        .flatten()
+}
+
+// A compound that starts with `@func name` is a function call.
+fn func(mut compound: Compound) -> Value {
+    let name = match compound.items.first() {
+        Some(Item::Node(node)) if node.path.len() == 1 && node.path[0].name == "@func" => {
+            node.value.as_str().map(|name| name.to_string())
+        }
+        _ => None,
+    };
+
+    match name {
+        None => Value::Compound(compound),
+        Some(name) => {
+            compound.items.remove(0);
+
+            Value::Func {
+                name,
+                args: compound,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(name: &str, mode: Mode) -> Id {
+        Id {
+            name: name.to_string(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn parse_modes() {
+        let conf = parse("pcm.!default hw\n?a 1\n-b 2\n+c 3\nd 4").unwrap();
+
+        let paths: Vec<&Vec<Id>> = conf.nodes().map(|node| &node.path).collect();
+
+        assert_eq!(
+            paths,
+            [
+                &vec![id("pcm", Mode::Merge), id("default", Mode::Override)],
+                &vec![id("a", Mode::Default)],
+                &vec![id("b", Mode::MergeExisting)],
+                &vec![id("c", Mode::Merge)],
+                &vec![id("d", Mode::Merge)],
+            ]
+        );
+    }
+
+    #[test]
+    fn override_replaces_what_came_before() {
+        let conf =
+            parse("pcm.default { type plug slave.pcm dmix }\npcm.!default { type hw card 0 }")
+                .unwrap();
+
+        assert_eq!(conf.get_str("pcm.default.type").as_deref(), Some("hw"));
+        assert_eq!(conf.get("pcm.default.slave"), None);
+        assert_eq!(conf.get("pcm.default.card"), Some(Value::Integer(0)));
+    }
+
+    #[test]
+    fn merge_combines_compounds() {
+        let conf = parse("pcm.dac { type hw }\npcm.dac.card DAC").unwrap();
+
+        assert_eq!(conf.get_str("pcm.dac.type").as_deref(), Some("hw"));
+        assert_eq!(conf.get_str("pcm.dac.card").as_deref(), Some("DAC"));
+        assert_eq!(conf.ids("pcm"), ["dac"]);
+    }
+
+    #[test]
+    fn parse_includes() {
+        let conf = parse("</etc/alsa/conf.d/extra.conf>\n< confdir:pcm/dmix.conf >\na 1").unwrap();
+
+        assert_eq!(
+            conf.includes().collect::<Vec<&str>>(),
+            ["/etc/alsa/conf.d/extra.conf", "confdir:pcm/dmix.conf"]
+        );
+
+        assert_eq!(conf.get("a"), Some(Value::Integer(1)));
+    }
+
+    #[test]
+    fn parse_func() {
+        let conf = parse("card { @func refer name defaults.pcm.card }").unwrap();
+
+        assert_eq!(conf.get("card"), Some(Value::refer("defaults.pcm.card")));
+    }
+
+    #[test]
+    fn parse_hooks() {
+        let conf = parse(
+            "@hooks [ { func load files [ \"/etc/asound.conf\" \"~/.asoundrc\" ] errors false } ]",
+        )
+        .unwrap();
+
+        let hooks = match conf.get("@hooks") {
+            Some(Value::Array(hooks)) => hooks,
+            hooks => panic!("@hooks is {hooks:?}"),
+        };
+
+        let hook = hooks[0].as_compound().unwrap();
+
+        assert_eq!(hook.get_str("func").as_deref(), Some("load"));
+        assert_eq!(
+            hook.get("files"),
+            Some(Value::Array(vec![
+                "/etc/asound.conf".into(),
+                "~/.asoundrc".into()
+            ]))
+        );
+        assert_eq!(hook.get_str("errors").as_deref(), Some("false"));
+    }
+
+    #[test]
+    fn parse_comments_and_separators() {
+        let conf =
+            parse("# A comment\na 1; # Another one\nb = 2,\nc { # Inside\n d \"#not a comment\" }")
+                .unwrap();
+
+        assert_eq!(conf.get("a"), Some(Value::Integer(1)));
+        assert_eq!(conf.get("b"), Some(Value::Integer(2)));
+        assert_eq!(conf.get_str("c.d").as_deref(), Some("#not a comment"));
+    }
+
+    #[test]
+    fn parse_values() {
+        let conf = parse("a -12\nb 0x1f\nc 1.5\nd 2ch\ne 'single'\nf [ 1 two ]").unwrap();
+
+        assert_eq!(conf.get("a"), Some(Value::Integer(-12)));
+        assert_eq!(conf.get("b"), Some(Value::Integer(31)));
+        assert_eq!(conf.get("c"), Some(Value::Real(1.5)));
+        assert_eq!(conf.get_str("d").as_deref(), Some("2ch"));
+        assert_eq!(conf.get_str("e").as_deref(), Some("single"));
+        assert_eq!(
+            conf.get("f"),
+            Some(Value::Array(vec![Value::Integer(1), "two".into()]))
+        );
+    }
+
+    #[test]
+    fn parse_escapes() {
+        let conf = parse(
+            r#"a "tab\there \"quoted\" back\\slash"
+b "caf\303\251"
+c "\101\102C""#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            conf.get_str("a").as_deref(),
+            Some("tab\there \"quoted\" back\\slash")
+        );
+        assert_eq!(conf.get_str("b").as_deref(), Some("café"));
+        assert_eq!(conf.get_str("c").as_deref(), Some("ABC"));
+    }
+
+    #[test]
+    fn parse_error_positions() {
+        let error = |source: &str| {
+            let error = parse(source).unwrap_err();
+
+            (error.line, error.column)
+        };
+
+        assert_eq!(error("a 1\nb {\n  c 2\n"), (4, 1));
+        assert_eq!(error("a 1\n  b \"unterminated\n"), (2, 5));
+        assert_eq!(error("a 1\nb }"), (2, 3));
+        assert_eq!(error("a 1\n<unterminated\nb 2"), (2, 1));
+        assert_eq!(error("a {\n  = 1 }"), (2, 3));
+    }
+
+    #[test]
+    fn parse_serialize_round_trip() {
+        let source = r#"
+<confdir:extra.conf>
+
+pcm.!default {
+    type asym
+    playback.pcm dmix_DAC
+    capture.pcm {
+        type plug
+        slave.pcm "hw:CARD=Mic,DEV=0"
+    }
+}
+
+?defaults.pcm.rate_converter [
+    speexrate_medium
+    samplerate
+]
+
+-ctl.default.card {
+    @func refer
+    name defaults.ctl.card
+}
+
+"card with spaces" "A \"quoted\" name\\\001"
+
+numbers {
+    integer -3
+    real 0.5
+}
+"#;
+
+        let conf = parse(source).unwrap();
+        let serialized = conf.to_string();
+
+        assert_eq!(parse(&serialized).unwrap(), conf);
+        assert_eq!(serialized, source.trim());
+    }
+
+    // Card names can be anything the driver or the user makes of them.
+    const CARD_NAMES: [&str; 9] = [
+        "DAC",
+        "USB Audio",
+        "My \"Best\" DAC",
+        "back\\slash",
+        "tab\tnew\nline\u{1}bell\u{7}",
+        "1st",
+        "-minus",
+        "Café",
+        "",
+    ];
+
+    #[test]
+    fn quote_values() {
+        assert_eq!(quote("DAC", false), "DAC");
+        assert_eq!(quote("S32_LE", false), "S32_LE");
+        assert_eq!(quote("defaults.pcm.ipc_key", false), "defaults.pcm.ipc_key");
+        assert_eq!(quote("USB Audio", false), r#""USB Audio""#);
+        assert_eq!(quote("My \"Best\" DAC", false), r#""My \"Best\" DAC""#);
+        assert_eq!(quote("back\\slash", false), r#""back\\slash""#);
+        assert_eq!(quote("a\tb\nc\rd", false), r#""a\tb\nc\rd""#);
+        assert_eq!(quote("a\u{1}b\u{7f}", false), r#""a\001b\177""#);
+        assert_eq!(quote("1st", false), r#""1st""#);
+        assert_eq!(quote("-minus", false), r#""-minus""#);
+        assert_eq!(quote("hw:1,0", false), r#""hw:1,0""#);
+        assert_eq!(quote("Café", false), r#""Café""#);
+        assert_eq!(quote("", false), r#""""#);
+        assert_eq!(quote("@func", false), r#""@func""#);
+    }
+
+    #[test]
+    fn quote_ids() {
+        assert_eq!(quote("default", true), "default");
+        assert_eq!(quote("@func", true), "@func");
+        assert_eq!(quote("0", true), "0");
+        assert_eq!(quote("12", true), "12");
+        assert_eq!(quote("a.b", true), r#""a.b""#);
+        assert_eq!(quote("my pcm", true), r#""my pcm""#);
+        assert_eq!(quote("1st", true), r#""1st""#);
+        assert_eq!(quote("", true), r#""""#);
+    }
+
+    #[test]
+    fn card_names_survive_a_round_trip() {
+        for name in CARD_NAMES {
+            let conf = Compound::new().with(
+                "ctl.!default",
+                Compound::new().with("type", "hw").with("card", name),
+            );
+
+            let parsed = parse(&conf.to_string()).unwrap();
+
+            assert_eq!(parsed, conf, "{name:?}");
+            assert_eq!(
+                parsed.get_str("ctl.default.card").as_deref(),
+                Some(name),
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ids_survive_a_round_trip() {
+        for name in CARD_NAMES.into_iter().chain(["a.b", "@args", "0"]) {
+            let conf = Compound {
+                items: vec![Item::Node(Node {
+                    path: vec![id("pcm", Mode::Merge), id(name, Mode::Override)],
+                    value: Value::Integer(1),
+                })],
+            };
+
+            assert_eq!(parse(&conf.to_string()).unwrap(), conf, "{name:?}");
+        }
+    }
+}
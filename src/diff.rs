@@ -0,0 +1,334 @@
+use std::{fmt::Display, fs, io::ErrorKind};
+
+use colored::*;
+
+use similar::{ChangeTag, TextDiff};
+
+use crate::{
+    conf::{parse, Compound},
+    inspect::{default_pcm, rate_converter},
+    preset::PresetConfiguration,
+    OutputTarget,
+};
+
+// How many unchanged lines are shown around every change.
+const CONTEXT_LINES: usize = 3;
+
+// What committing config would change about target, as a colored
+// unified diff followed by what that means in plain words.
+pub fn show_changes(target: &OutputTarget, config: &str) {
+    let current = match fs::read_to_string(&target.path) {
+        Ok(current) => current,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            println!(
+                "{}",
+                format!(
+                    "\n{} does not exist yet, it will be created.",
+                    target.display()
+                )
+                .cyan()
+            );
+
+            return;
+        }
+        Err(e) => {
+            println!(
+                "{}",
+                format!("\nCould not read {}: {e}", target.display()).cyan()
+            );
+
+            return;
+        }
+    };
+
+    if current.trim_end() == config.trim_end() {
+        println!(
+            "{}",
+            format!(
+                "\n{} already contains exactly this configuration.",
+                target.display()
+            )
+            .cyan()
+        );
+
+        return;
+    }
+
+    println!("{}", format!("\nChanges to {}:\n", target.display()).cyan());
+
+    show_diff(&current, config, &target.display(), "new");
+
+    match (parse(&current), parse(config)) {
+        (Err(e), _) => println!(
+            "{}",
+            format!(
+                "\n{} is not a valid ALSA configuration, {e}. It will be replaced entirely.",
+                target.display()
+            )
+            .cyan()
+        ),
+        (Ok(current), Ok(config)) => {
+            println!();
+
+            for change in describe_changes(&current, &config) {
+                println!("{}", change.cyan());
+            }
+        }
+        _ => (),
+    }
+}
+
+fn show_diff(old: &str, new: &str, old_name: &str, new_name: &str) {
+    let diff = TextDiff::from_lines(old, new);
+
+    println!("{}", format!("--- {old_name}").bold());
+    println!("{}", format!("+++ {new_name}").bold());
+
+    for hunk in diff
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .iter_hunks()
+    {
+        println!("{}", hunk.header().to_string().cyan());
+
+        for change in hunk.iter_changes() {
+            let line = change.value().trim_end_matches('\n');
+
+            match change.tag() {
+                ChangeTag::Delete => println!("{}", format!("-{line}").red()),
+                ChangeTag::Insert => println!("{}", format!("+{line}").green()),
+                ChangeTag::Equal => println!(" {line}"),
+            }
+        }
+    }
+}
+
+// What changes between old and new for the default PCMs and the
+// Sample Rate Converter, one sentence per direction.
+fn describe_changes(old: &Compound, new: &Compound) -> Vec<String> {
+    let mut changes = vec![];
+
+    for (direction, label) in [
+        ("playback", "Default playback"),
+        ("capture", "Default capture"),
+    ] {
+        match (default_pcm(old, direction), default_pcm(new, direction)) {
+            (None, None) => (),
+            (None, Some(new)) => {
+                let settings = settings(&new);
+
+                if settings.is_empty() {
+                    changes.push(format!("{label} is set to {}.", location(&new)));
+                } else {
+                    changes.push(format!("{label} is set to {}, {settings}.", location(&new)));
+                }
+            }
+            (Some(old), None) => {
+                changes.push(format!("{label} no longer goes to {}.", location(&old)))
+            }
+            (Some(old), Some(new)) => {
+                let mut parts = vec![];
+
+                let (old_location, new_location) = (location(&old), location(&new));
+
+                if old_location != new_location {
+                    parts.push(format!("moves from {old_location} to {new_location}"));
+                }
+
+                change(&mut parts, "Format", &old.format, &new.format, "");
+                change(&mut parts, "Sampling Rate", &old.rate, &new.rate, "");
+                change(
+                    &mut parts,
+                    "Channel Count",
+                    &old.channels,
+                    &new.channels,
+                    "",
+                );
+                change(
+                    &mut parts,
+                    "Buffer Time",
+                    &old.buffer_time_ms,
+                    &new.buffer_time_ms,
+                    " ms",
+                );
+
+                if parts.is_empty() {
+                    continue;
+                }
+
+                if old_location == new_location {
+                    parts.insert(0, format!("stays at {new_location}"));
+                }
+
+                changes.push(format!("{label} {}.", parts.join(", ")));
+            }
+        }
+    }
+
+    let (old_converter, new_converter) = (rate_converter(old), rate_converter(new));
+
+    if old_converter != new_converter {
+        changes.push(format!(
+            "Sample Rate Converter {} → {}.",
+            old_converter.as_deref().unwrap_or("default"),
+            new_converter.as_deref().unwrap_or("default")
+        ));
+    }
+
+    if changes.is_empty() {
+        changes.push("The default PCMs and their settings stay the same.".to_string());
+    }
+
+    changes
+}
+
+fn change<T: PartialEq + Display>(
+    parts: &mut Vec<String>,
+    name: &str,
+    old: &Option<T>,
+    new: &Option<T>,
+    unit: &str,
+) {
+    if old != new {
+        parts.push(format!(
+            "{name} {} → {}",
+            optional(old, unit),
+            optional(new, unit)
+        ));
+    }
+}
+
+fn optional<T: Display>(value: &Option<T>, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{value}{unit}"),
+        None => "unset".to_string(),
+    }
+}
+
+fn location(config: &PresetConfiguration) -> String {
+    match (&config.card, config.device) {
+        (Some(card), Some(device)) => format!("card {card} device {device}"),
+        (Some(card), None) => format!("card {card}"),
+        (None, _) => "an unknown card".to_string(),
+    }
+}
+
+fn settings(config: &PresetConfiguration) -> String {
+    [
+        (
+            "Format",
+            config.format.as_ref().map(|format| format.to_string()),
+        ),
+        ("Sampling Rate", config.rate.map(|rate| rate.to_string())),
+        (
+            "Channel Count",
+            config.channels.map(|channels| channels.to_string()),
+        ),
+        (
+            "Buffer Time",
+            config
+                .buffer_time_ms
+                .map(|buffer_time_ms| format!("{buffer_time_ms} ms")),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some(format!("{name} {}", value?)))
+    .collect::<Vec<String>>()
+    .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The way build_asound_conf writes a playback-only configuration, trimmed down.
+    fn dmix(card: &str, rate: u32) -> String {
+        format!(
+            r#"
+pcm.playback {{
+    type dmix
+    slave {{
+        pcm {{
+            type hw
+            card {card}
+            device 0
+        }}
+        channels 2
+        rate {rate}
+        format S32_LE
+        buffer_time 100000
+    }}
+}}
+
+pcm.!default {{
+    type asym
+    playback.pcm {{
+        type plug
+        slave.pcm playback
+    }}
+}}
+"#
+        )
+    }
+
+    fn changes(old: &str, new: &str) -> Vec<String> {
+        describe_changes(&parse(old).unwrap(), &parse(new).unwrap())
+    }
+
+    #[test]
+    fn from_a_hand_written_hw_default() {
+        assert_eq!(
+            changes(
+                r#"pcm.!default { type plug slave.pcm "hw:1,0" }"#,
+                &dmix("DAC", 48000)
+            ),
+            [
+                "Default playback moves from card 1 device 0 to card DAC device 0, Format unset → S32_LE, Sampling Rate unset → 48000, Channel Count unset → 2, Buffer Time unset → 100 ms.",
+                "Default capture no longer goes to card 1 device 0.",
+            ]
+        );
+    }
+
+    #[test]
+    fn to_another_card() {
+        assert_eq!(
+            changes(&dmix("HDMI", 48000), &dmix("DAC", 48000)),
+            ["Default playback moves from card HDMI device 0 to card DAC device 0."]
+        );
+    }
+
+    #[test]
+    fn same_card_other_settings() {
+        assert_eq!(
+            changes(&dmix("DAC", 44100), &dmix("DAC", 48000)),
+            ["Default playback stays at card DAC device 0, Sampling Rate 44100 → 48000."]
+        );
+    }
+
+    #[test]
+    fn from_nothing() {
+        assert_eq!(
+            changes("", &dmix("DAC", 48000)),
+            ["Default playback is set to card DAC device 0, Format S32_LE, Sampling Rate 48000, Channel Count 2, Buffer Time 100 ms."]
+        );
+    }
+
+    #[test]
+    fn nothing_changes() {
+        assert_eq!(
+            changes(&dmix("DAC", 48000), &dmix("DAC", 48000)),
+            ["The default PCMs and their settings stay the same."]
+        );
+    }
+
+    #[test]
+    fn converter_changes() {
+        let old = dmix("DAC", 48000);
+        let new = format!("{old}\ndefaults.pcm.rate_converter speexrate_best");
+
+        assert_eq!(
+            changes(&old, &new),
+            ["Sample Rate Converter default → speexrate_best."]
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use std::{fs, path::Path};
+
+use colored::*;
+
+use comfy_table::{
+    modifiers::UTF8_SOLID_INNER_BORDERS, presets::UTF8_FULL, Attribute, Cell, ContentArrangement,
+    Table,
+};
+
+use crate::{
+    conf::{parse, Compound, Value},
+    preset::{Preset, PresetConfiguration},
+    US_PER_MS,
+};
+
+// How many plugins deep a PCM is followed to find the hardware,
+// a config that refers to itself would go on forever otherwise.
+const MAX_PCM_DEPTH: usize = 16;
+
+pub fn load_conf(path: &Path) -> Result<Compound, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+    parse(&contents)
+        .map_err(|e| format!("{} is not a valid ALSA configuration, {e}", path.display()))
+}
+
+// Every PCM and control a config defines and what it does,
+// as far as that can be told without asking alsa-lib.
+pub fn show_inspection(conf: &Compound) {
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(vec![
+            Cell::new("DEFINITION").add_attribute(Attribute::Bold),
+            Cell::new("TYPE").add_attribute(Attribute::Bold),
+            Cell::new("DETAILS").add_attribute(Attribute::Bold),
+        ]);
+
+    let mut definitions = 0;
+
+    for namespace in ["pcm", "ctl"] {
+        for id in conf.ids(namespace) {
+            let path = format!("{namespace}.{id}");
+
+            if let Some(value) = conf.get(&path) {
+                let (kind, details) = describe(&value);
+
+                table.add_row(vec![Cell::new(path), Cell::new(kind), Cell::new(details)]);
+
+                definitions += 1;
+            }
+        }
+    }
+
+    if definitions == 0 {
+        println!("{}", "\nIt does not define any PCMs or controls…".cyan());
+    } else {
+        println!("\n{table}");
+    }
+
+    for (path, value) in leaves(conf, "defaults") {
+        println!("{}", format!("{path} is set to {value}.").cyan());
+    }
+
+    for include in conf.includes() {
+        println!("{}", format!("It includes <{include}>.").cyan());
+    }
+
+    if conf.get("@hooks").is_some() {
+        println!(
+            "{}",
+            "It has @hooks, which can load more configuration when it is loaded.".cyan()
+        );
+    }
+}
+
+// A preset that recreates the default PCM of conf, as
+// long as it ends up at dmix, dsnoop or hw PCMs.
+pub fn to_preset(conf: &Compound) -> Result<Preset, String> {
+    to_preset_with(conf, proc_card_id)
+}
+
+// The same, with card indices looked up by card_id instead of in /proc.
+pub fn to_preset_with(
+    conf: &Compound,
+    card_id: impl Fn(u32) -> Option<String>,
+) -> Result<Preset, String> {
+    let playback =
+        find_hardware(conf, "playback").map(|pcm| to_preset_configuration(&pcm, &card_id));
+    let capture = find_hardware(conf, "capture").map(|pcm| to_preset_configuration(&pcm, &card_id));
+
+    if playback.is_none() && capture.is_none() {
+        return Err(
+            "Its default PCM does not end up at a dmix, dsnoop or hw PCM, there is nothing to import."
+                .to_string(),
+        );
+    }
+
+    Ok(Preset {
+        converter: rate_converter(conf),
+        playback: playback.transpose()?,
+        capture: capture.transpose()?,
+    })
+}
+
+// What the default PCM of conf ends up at in direction, "playback" or "capture",
+// None if that's not a dmix, dsnoop or hw PCM or its card can't be told.
+pub fn default_pcm(conf: &Compound, direction: &str) -> Option<PresetConfiguration> {
+    default_pcm_with(conf, direction, proc_card_id)
+}
+
+fn default_pcm_with(
+    conf: &Compound,
+    direction: &str,
+    card_id: impl Fn(u32) -> Option<String>,
+) -> Option<PresetConfiguration> {
+    find_hardware(conf, direction).and_then(|pcm| to_preset_configuration(&pcm, &card_id).ok())
+}
+
+// The id of the card with index, what presets and CARD= use, from
+// /proc/asound/card<index>/id. None if there is no such card here.
+fn proc_card_id(index: u32) -> Option<String> {
+    fs::read_to_string(format!("/proc/asound/card{index}/id"))
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+pub fn rate_converter(conf: &Compound) -> Option<String> {
+    match conf.get("defaults.pcm.rate_converter") {
+        Some(Value::String(converter)) => Some(converter),
+        // A list of converters to try in order.
+        Some(Value::Array(converters)) => converters
+            .first()
+            .and_then(|converter| converter.as_str())
+            .map(|converter| converter.to_string()),
+        _ => None,
+    }
+}
+
+// Follow the default PCM through asym, plug and the like, and references
+// to other PCMs, to the hardware or to dmix for playback and dsnoop for capture.
+// A reference such as "hw:1,0" is the hardware itself.
+fn find_hardware(conf: &Compound, direction: &str) -> Option<Compound> {
+    let mixer = if direction == "capture" {
+        "dsnoop"
+    } else {
+        "dmix"
+    };
+
+    let mut pcm = conf.get("pcm.default")?;
+
+    for _ in 0..MAX_PCM_DEPTH {
+        let next = match pcm {
+            Value::String(name) => match parse_hw_name(&name) {
+                Some(hw) => return Some(hw_pcm(hw)),
+                None => conf.get(&format!("pcm.{name}")),
+            },
+            Value::Compound(compound) => match pcm_type(&compound).as_str() {
+                "hw" => return Some(compound),
+                kind if kind == mixer => return Some(compound),
+                "asym" => compound.get(&format!("{direction}.pcm")),
+                _ => compound.get("slave.pcm"),
+            },
+            _ => None,
+        };
+
+        pcm = next?;
+    }
+
+    None
+}
+
+// A card given by its index, such as hw:1,0 or card 1, is stored by its id
+// instead, presets are matched on that. If there is no such card here the
+// index is kept as is.
+fn to_preset_configuration(
+    pcm: &Compound,
+    card_id: &impl Fn(u32) -> Option<String>,
+) -> Result<PresetConfiguration, String> {
+    let (card, device) = match (pcm_type(pcm).as_str(), pcm.get("slave.pcm")) {
+        ("hw", _) => (pcm.get("card").map(card_name), integer(pcm.get("device"))),
+        (_, Some(Value::Compound(slave))) => (
+            slave.get("card").map(card_name),
+            integer(slave.get("device")),
+        ),
+        (_, Some(Value::String(name))) => parse_hw_name(&name).unwrap_or_default(),
+        _ => (None, None),
+    };
+
+    let card = match card {
+        None => return Err("Could not tell which card its default PCM uses.".to_string()),
+        Some(card) => card.parse::<u32>().ok().and_then(card_id).unwrap_or(card),
+    };
+
+    Ok(PresetConfiguration {
+        card: Some(card),
+        device,
+        description: None,
+        format: pcm.get_str("slave.format"),
+        rate: integer(pcm.get("slave.rate")),
+        channels: integer(pcm.get("slave.channels")),
+        buffer_time_ms: integer(pcm.get("slave.buffer_time"))
+            .map(|buffer_time| buffer_time / US_PER_MS)
+            .filter(|buffer_time_ms| *buffer_time_ms > 0),
+    })
+}
+
+fn describe(value: &Value) -> (String, String) {
+    let pcm = match value {
+        Value::Compound(pcm) => pcm,
+        Value::String(name) => return ("alias".to_string(), format!("Same as {name}")),
+        value => return ("?".to_string(), value.to_string()),
+    };
+
+    let details = match pcm_type(pcm).as_str() {
+        "hw" => hardware(pcm),
+        "asym" => format!(
+            "Playback: {}, Capture: {}",
+            reference(pcm.get("playback.pcm")),
+            reference(pcm.get("capture.pcm"))
+        ),
+        _ => {
+            let mut details = vec![];
+
+            if let Some(slave) = pcm.get("slave.pcm") {
+                details.push(format!("Slave: {}", reference(Some(slave))));
+            }
+
+            for (name, label) in [
+                ("format", "Format"),
+                ("rate", "Sampling Rate"),
+                ("channels", "Channel Count"),
+            ] {
+                if let Some(value) = pcm.get(&format!("slave.{name}")) {
+                    details.push(format!("{label}: {value}"));
+                }
+            }
+
+            if let Some(buffer_time) = integer(pcm.get("slave.buffer_time")) {
+                if buffer_time > 0 {
+                    details.push(format!("Buffer Time: {} ms", buffer_time / US_PER_MS));
+                }
+            }
+
+            details.join(", ")
+        }
+    };
+
+    (pcm_type(pcm), details)
+}
+
+// A PCM that is used by another one, either by name or inline.
+fn reference(value: Option<Value>) -> String {
+    match value {
+        None => "none".to_string(),
+        Some(Value::String(name)) => name,
+        Some(Value::Compound(pcm)) => match (pcm_type(&pcm).as_str(), pcm.get("slave.pcm")) {
+            ("hw", _) => format!("hw ({})", hardware(&pcm)),
+            (kind, None) => kind.to_string(),
+            (kind, Some(slave)) => format!("{kind} → {}", reference(Some(slave))),
+        },
+        Some(value) => value.to_string(),
+    }
+}
+
+fn hardware(pcm: &Compound) -> String {
+    ["card", "device", "subdevice"]
+        .iter()
+        .filter_map(|name| Some(format!("{name} {}", pcm.get(name)?)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn pcm_type(pcm: &Compound) -> String {
+    pcm.get_str("type").unwrap_or_else(|| "?".to_string())
+}
+
+fn card_name(card: Value) -> String {
+    match card {
+        Value::String(card) => card,
+        card => card.to_string(),
+    }
+}
+
+fn integer(value: Option<Value>) -> Option<u32> {
+    value?
+        .as_integer()
+        .and_then(|value| u32::try_from(value).ok())
+}
+
+// hw:CARD=DAC,DEV=0 or hw:DAC,0, and the same for plughw.
+fn parse_hw_name(name: &str) -> Option<(Option<String>, Option<u32>)> {
+    let args = name
+        .strip_prefix("hw:")
+        .or_else(|| name.strip_prefix("plughw:"))?;
+
+    let mut card = None;
+    let mut device = None;
+
+    for (i, arg) in args.split(',').enumerate() {
+        match arg.split_once('=') {
+            Some(("CARD", value)) => card = Some(value.trim_matches('"').to_string()),
+            Some(("DEV", value)) => device = value.parse::<u32>().ok(),
+            Some(_) => (),
+            None if i == 0 => card = Some(arg.trim_matches('"').to_string()),
+            None if i == 1 => device = arg.parse::<u32>().ok(),
+            None => (),
+        }
+    }
+
+    Some((card, device))
+}
+
+// The hw PCM a name such as "hw:1,0" stands for.
+fn hw_pcm((card, device): (Option<String>, Option<u32>)) -> Compound {
+    let mut pcm = Compound::new().with("type", "hw");
+
+    if let Some(card) = card {
+        pcm.push("card", card);
+    }
+
+    if let Some(device) = device {
+        pcm.push("device", device);
+    }
+
+    pcm
+}
+
+// Every value below path that isn't a compound, with its full path.
+fn leaves(conf: &Compound, path: &str) -> Vec<(String, Value)> {
+    let ids = conf.ids(path);
+
+    if ids.is_empty() {
+        return conf
+            .get(path)
+            .filter(|value| value.as_compound().is_none())
+            .map(|value| vec![(path.to_string(), value)])
+            .unwrap_or_default();
+    }
+
+    ids.iter()
+        .flat_map(|id| leaves(conf, &format!("{path}.{id}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only card 1 is known, as DAC.
+    fn card_id(index: u32) -> Option<String> {
+        (index == 1).then(|| "DAC".to_string())
+    }
+
+    fn playback(source: &str) -> Option<(Option<String>, Option<u32>)> {
+        let conf = parse(source).unwrap();
+
+        default_pcm_with(&conf, "playback", card_id).map(|pcm| (pcm.card, pcm.device))
+    }
+
+    #[test]
+    fn hw_names_are_hardware() {
+        assert_eq!(
+            playback(r#"pcm.!default { type plug slave.pcm "hw:2,0" }"#),
+            Some((Some("2".to_string()), Some(0)))
+        );
+
+        assert_eq!(
+            playback(r#"pcm.!default "plughw:CARD=DAC,DEV=2""#),
+            Some((Some("DAC".to_string()), Some(2)))
+        );
+
+        assert_eq!(
+            playback(r#"pcm.!default { type asym playback.pcm "hw:DAC" capture.pcm "hw:Mic" }"#),
+            Some((Some("DAC".to_string()), None))
+        );
+    }
+
+    #[test]
+    fn card_indices_are_ids() {
+        assert_eq!(
+            playback(r#"pcm.!default { type plug slave.pcm "hw:1,0" }"#),
+            Some((Some("DAC".to_string()), Some(0)))
+        );
+
+        assert_eq!(
+            playback("pcm.!default { type hw card 1 device 2 }"),
+            Some((Some("DAC".to_string()), Some(2)))
+        );
+
+        assert_eq!(
+            playback(r#"pcm.!default { type dmix slave.pcm { type hw card "1" } }"#),
+            Some((Some("DAC".to_string()), None))
+        );
+
+        // Ids are kept as they are.
+        assert_eq!(
+            playback("pcm.!default { type hw card Mic }"),
+            Some((Some("Mic".to_string()), None))
+        );
+    }
+
+    #[test]
+    fn references_are_followed() {
+        let source = r#"
+pcm.!default {
+    type asym
+    playback.pcm "out"
+}
+
+pcm.out {
+    type plug
+    slave.pcm dmixed
+}
+
+pcm.dmixed {
+    type dmix
+    slave {
+        pcm {
+            type hw
+            card DAC
+            device 1
+        }
+        format S32_LE
+        rate 96000
+        channels 2
+        buffer_time 40000
+    }
+}
+"#;
+
+        let conf = parse(source).unwrap();
+        let pcm = default_pcm(&conf, "playback").unwrap();
+
+        assert_eq!(pcm.card.as_deref(), Some("DAC"));
+        assert_eq!(pcm.device, Some(1));
+        assert_eq!(pcm.format.as_deref(), Some("S32_LE"));
+        assert_eq!(pcm.rate, Some(96000));
+        assert_eq!(pcm.channels, Some(2));
+        assert_eq!(pcm.buffer_time_ms, Some(40));
+
+        assert!(default_pcm(&conf, "capture").is_none());
+    }
+
+    #[test]
+    fn loops_end() {
+        assert_eq!(playback("pcm.!default a\npcm.a b\npcm.b a"), None);
+    }
+}
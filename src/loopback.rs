@@ -0,0 +1,266 @@
+use std::{path::Path, time::Duration};
+
+use alsa::Direction;
+
+use colored::*;
+
+use comfy_table::{
+    modifiers::UTF8_SOLID_INNER_BORDERS, presets::UTF8_FULL, Attribute, Cell, ContentArrangement,
+    Table,
+};
+
+use crate::{
+    signal::{cross_correlate, interleave, pink_noise},
+    stream::Stream,
+    TEST_TONE_AMPLITUDE,
+};
+
+// Silence before the signal so that none of it is lost while the PCMs start.
+const LEAD_TIME: Duration = Duration::from_millis(200);
+const SIGNAL_DURATION: Duration = Duration::from_millis(500);
+// How long to keep recording after the signal, the longest round trip that can be measured.
+const MAX_LATENCY: Duration = Duration::from_secs(1);
+// How much is written and read at a time.
+const CHUNK_TIME: Duration = Duration::from_millis(10);
+
+// How closely, from 0.0 to 1.0, the recording has to match the signal
+// for the signal to count as found. Noise alone gets nowhere near it.
+const MIN_CORRELATION: f64 = 0.3;
+
+// Play pink noise through the Playback PCM of the configuration at path
+// while recording its Capture PCM, find the noise in the recording and
+// report how long it took to make the round trip.
+pub fn test_loopback(path: &Path) -> Result<(), String> {
+    let (mut playback, playback_params) = Stream::open_installed(path, Direction::Playback)?;
+    let (mut capture, capture_params) = Stream::open_installed(path, Direction::Capture)?;
+
+    if playback_params.rate != capture_params.rate {
+        return Err(format!(
+            "The Playback PCM runs at {} Hz and the Capture PCM at {} Hz, they have to be the same.",
+            playback_params.rate, capture_params.rate
+        ));
+    }
+
+    let rate = playback_params.rate;
+    let frames = |duration: Duration| (rate as f64 * duration.as_secs_f64()) as usize;
+
+    let signal = pink_noise(rate, SIGNAL_DURATION, TEST_TONE_AMPLITUDE);
+
+    let lead = frames(LEAD_TIME);
+
+    let mut output = vec![0.0; lead];
+
+    output.extend(&signal);
+    output.resize(output.len() + frames(MAX_LATENCY), 0.0);
+
+    let total = output.len();
+    let chunk = frames(CHUNK_TIME);
+    let capture_channels = capture_params.channels as usize;
+
+    println!(
+        "{}",
+        format!(
+            "\nPlaying pink noise through the Playback PCM of {} while recording its Capture PCM…",
+            path.display()
+        )
+        .cyan()
+    );
+
+    let mut recorded: Vec<Vec<f32>> = vec![Vec::with_capacity(total); capture_channels];
+    let mut written = 0;
+    let mut read = 0;
+
+    // Writing and reading in lockstep, like a program that
+    // plays and records at the same time would.
+    while read < total {
+        if written < total {
+            let end = (written + chunk).min(total);
+
+            playback.write(&interleave(&output[written..end], playback_params.channels))?;
+
+            written = end;
+        }
+
+        let samples = capture.read(chunk.min(total - read))?;
+
+        for frame in samples.chunks_exact(capture_channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                recorded[channel].push(*sample);
+            }
+        }
+
+        read += samples.len() / capture_channels;
+    }
+
+    let (channel, offset, correlation) = recorded
+        .iter()
+        .enumerate()
+        .map(|(channel, recording)| {
+            let (offset, correlation) = find(&signal, recording);
+
+            (channel, offset, correlation)
+        })
+        .max_by(|a, b| a.2.abs().total_cmp(&b.2.abs()))
+        .ok_or_else(|| "The Capture PCM has no channels.".to_string())?;
+
+    if correlation.abs() < MIN_CORRELATION {
+        return Err(
+            "The test signal was not found in the recording. Is the Playback PCM connected to the Capture PCM, with a cable or with the snd-aloop kernel module?"
+                .to_string(),
+        );
+    }
+
+    let latency_ms = latency_ms(offset, lead, rate)?;
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(vec![
+            Cell::new("PLAYBACK BUFFER TIME").add_attribute(Attribute::Bold),
+            Cell::new("CAPTURE BUFFER TIME").add_attribute(Attribute::Bold),
+            Cell::new("ROUND-TRIP LATENCY").add_attribute(Attribute::Bold),
+        ])
+        .add_row(vec![
+            Cell::new(format!("{} ms", playback_params.buffer_time_ms)),
+            Cell::new(format!("{} ms", capture_params.buffer_time_ms)),
+            Cell::new(format!("{latency_ms:.1} ms")),
+        ]);
+
+    println!("\n{table}");
+
+    println!(
+        "{}",
+        format!(
+            "\nThe signal came back on Capture channel {} with a correlation of {:.2}.",
+            channel + 1,
+            correlation.abs()
+        )
+        .cyan()
+    );
+
+    if correlation < 0.0 {
+        println!(
+            "{}",
+            "It came back upside down, the polarity is inverted somewhere along the way.".cyan()
+        );
+    }
+
+    Ok(())
+}
+
+// The round trip of a signal that was played lead frames into the
+// recording and found offset frames into it. It can't have come back
+// before it was played, unless the Capture PCM started late.
+fn latency_ms(offset: usize, lead: usize, rate: u32) -> Result<f64, String> {
+    let ms = |frames: usize| frames as f64 * 1000.0 / rate as f64;
+
+    match offset.checked_sub(lead) {
+        Some(latency) => Ok(ms(latency)),
+        None => Err(format!(
+            "The test signal was found {:.1} ms before it was played, the Capture PCM probably started late. The latency could not be measured.",
+            ms(lead - offset)
+        )),
+    }
+}
+
+// Where signal starts in recording, and how well it matches there
+// from -1.0 (upside down) to 1.0 (exactly).
+fn find(signal: &[f32], recording: &[f32]) -> (usize, f64) {
+    let correlation = cross_correlate(signal, recording);
+
+    let Some((offset, peak)) = correlation
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+    else {
+        return (0, 0.0);
+    };
+
+    let energy = |samples: &[f32]| {
+        samples
+            .iter()
+            .map(|sample| (*sample as f64).powi(2))
+            .sum::<f64>()
+    };
+
+    let end = (offset + signal.len()).min(recording.len());
+
+    let norm = (energy(signal) * energy(&recording[offset..end])).sqrt();
+
+    if norm > 0.0 {
+        (offset, peak / norm)
+    } else {
+        (offset, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::sine;
+
+    const RATE: u32 = 48000;
+
+    // signal delayed by delay frames at gain, with a bit of mains hum on top.
+    fn recording(signal: &[f32], delay: usize, gain: f32) -> Vec<f32> {
+        let hum = sine(RATE, 50.0, Duration::from_secs(1), 0.01);
+
+        let mut recording = vec![0.0; delay];
+
+        recording.extend(signal.iter().map(|sample| sample * gain));
+        recording.resize(delay + signal.len() + 4800, 0.0);
+
+        recording
+            .iter()
+            .zip(hum.iter().cycle())
+            .map(|(sample, hum)| sample + hum)
+            .collect()
+    }
+
+    fn signal() -> Vec<f32> {
+        pink_noise(RATE, SIGNAL_DURATION, TEST_TONE_AMPLITUDE)
+    }
+
+    #[test]
+    fn latency_and_polarity() {
+        let signal = signal();
+
+        let (offset, correlation) = find(&signal, &recording(&signal, 12000, 0.3));
+
+        assert_eq!(offset, 12000);
+        assert!(correlation > 0.9, "{correlation}");
+
+        let (offset, correlation) = find(&signal, &recording(&signal, 9600, -0.3));
+
+        assert_eq!(offset, 9600);
+        assert!(correlation < -0.9, "{correlation}");
+
+        // The lead is 9600 frames at 48kHz.
+        assert_eq!(latency_ms(12000, 9600, RATE), Ok(50.0));
+        assert_eq!(latency_ms(9600, 9600, RATE), Ok(0.0));
+    }
+
+    #[test]
+    fn nothing_to_find() {
+        let signal = signal();
+
+        let (_, correlation) = find(&signal, &recording(&[], 48000, 0.0));
+
+        assert!(correlation.abs() < MIN_CORRELATION, "{correlation}");
+
+        assert_eq!(find(&signal, &[0.0; 4800]).1, 0.0);
+        assert_eq!(find(&signal, &[]), (0, 0.0));
+    }
+
+    #[test]
+    fn found_before_it_was_played() {
+        let error = latency_ms(4800, 9600, RATE).unwrap_err();
+
+        assert!(error.starts_with("The test signal was found 100.0 ms before it was played"));
+    }
+}
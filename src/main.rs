@@ -1,40 +1,105 @@
+mod auto;
+mod backend;
+mod backups;
+mod cache;
+mod cli;
+mod conf;
+mod diff;
+mod inspect;
+mod loopback;
+mod meter;
+mod preset;
+mod probe;
+mod report;
+mod signal;
+mod speakers;
+mod stream;
+mod validate;
+
 use std::{
     cmp::Ordering,
-    fs,
+    collections::HashMap,
+    env, fs,
     fs::File,
-    io::{stdin, stdout, Write},
+    io::{self, stdin, stdout, Write},
     ops::RangeInclusive,
-    process::exit,
-    sync::mpsc,
+    os::unix::{
+        fs::MetadataExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+    process::{self, exit},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        mpsc, Arc, Mutex,
+    },
     thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use alsa::{
-    device_name::HintIter,
-    pcm::{Format, HwParams, PCM},
-    Direction, ValueOr,
-};
+use alsa::{pcm::Format, Direction, ValueOr};
 
 use comfy_table::{
     modifiers::UTF8_SOLID_INNER_BORDERS, presets::UTF8_FULL, Attribute, Cell, ContentArrangement,
     Table,
 };
 
+use clap::Parser;
 use colored::*;
 use glob::glob;
 use itertools::Itertools;
 use which::which;
 
+use auto::{show_ranking, ScoringPolicy};
+use backend::{
+    Backend, Device, HardwareBackend, Holder, HwSpace, SessionBackend, SimulatedBackend,
+};
+use backups::{find_backup, find_backups, prune_backups, restore_backup, show_backups};
+use cache::{Fingerprint, ProbeCache, CACHE_DIR};
+use cli::{BackupsAction, Cli, Command, ConfigRequest, PcmSelector, TestAction};
+use conf::{Compound, Value};
+use diff::show_changes;
+use inspect::{load_conf, show_inspection, to_preset};
+use loopback::test_loopback;
+use meter::test_microphones;
+use preset::Preset;
+use probe::{
+    branch, check_install, exactly, find_valid, open_space, refine, show_bench, show_explanation,
+    show_summary, Param, RejectedPcm, Rejection, Step, StepFailure, TestedCombination,
+};
+use report::ProbeReport;
+use signal::{interleave, sine};
+use speakers::test_speakers;
+use stream::Stream;
+use validate::{validate_asound_conf, ConfigTree};
+
 const FORMATS: [Format; 4] = [Format::s16(), Format::s24_3(), Format::s24(), Format::s32()];
 
 const MIN_RATE: u32 = 3000;
 const MAX_RATE: u32 = 768000;
+// The rates the kernel knows by name, as of Linux 6.12. See:
+// https://github.com/torvalds/linux/blob/master/sound/core/pcm_native.c (snd_pcm_known_rates)
+const STANDARD_RATES: [u32; 20] = [
+    5512, 8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000,
+    128000, 176400, 192000, 352800, 384000, 705600, 768000,
+];
 const US_PER_MS: u32 = 1000;
 const PERIODS_PER_BUFFER: u32 = 5;
 const MIN_BUFFER_TIME_US: u32 = 1000;
 const MAX_BUFFER_TIME_US: u32 = 1000000;
 
+const TEST_TONE_FREQUENCY: f64 = 440.0;
+const TEST_TONE_DURATION: Duration = Duration::from_secs(2);
+// -12 dBFS, loud enough to hear but not to hurt.
+const TEST_TONE_AMPLITUDE: f64 = 0.25;
+const DEFAULT_TEST_DURATION_SECS: u64 = 2;
+const DEFAULT_CAPTURE_TEST_DURATION_SECS: u64 = 10;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+// How long workers get to stop after Ctrl+C before they are left behind.
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(1);
+const BUSY_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
 const CONFLICTING_SOFTWARE: [[&str; 2]; 3] = [
     ["pulseaudio", "PulseAudio"],
     ["pipewire", "PipeWire"],
@@ -42,73 +107,93 @@ const CONFLICTING_SOFTWARE: [[&str; 2]; 3] = [
 ];
 
 const ASOUND_FILE_PATH: &str = "/etc/asound.conf";
-const DUMMY_FILE_PATH_TEMPLATE: &str = "/etc/foobarbaz{now}";
-const BACKUP_FILE_PATH_TEMPLATE: &str = "/etc/asound.conf.bak{now}";
+const USER_ASOUND_FILE_NAME: &str = ".asoundrc";
+const DUMMY_FILE_NAME_TEMPLATE: &str = "foobarbaz{now}";
+const BACKUP_FILE_PATH_TEMPLATE: &str = "{path}.bak{now}";
+
+// 2 is used by clap for usage errors.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_PCM_NOT_FOUND: i32 = 3;
+const EXIT_INVALID_CONFIGURATION: i32 = 4;
+const EXIT_INVALID_CONVERTER: i32 = 5;
+const EXIT_INVALID_PRESET: i32 = 6;
+const EXIT_INVALID_ASOUND_CONF: i32 = 7;
+// What a shell reports for a process killed by SIGINT.
+const EXIT_CANCELLED: i32 = 130;
 
 const CONVERTERS_GLOB_PATH: &str = "/usr/lib/*/alsa-lib/libasound_module_rate_*";
 const CONVERTERS_PREFIX: &str = "/libasound_module_rate_";
 
-// dmix and dsnoop are basically mirror images of each other.
-// See:
-// https://github.com/alsa-project/alsa-lib/blob/master/src/conf/pcm/dmix.conf
-// https://github.com/alsa-project/alsa-lib/blob/master/src/conf/pcm/dsnoop.conf
-const PLAYBACK_CAPTURE_TEMPLATE: &str = "\
-pcm.{playback_capture} {
-    type {dmix_dsnoop}
-    ipc_key {
-        @func refer
-        name defaults.pcm.ipc_key
-    }
-    ipc_gid {
-        @func refer
-        name defaults.pcm.ipc_gid
-    }
-    ipc_perm {
-        @func refer
-        name defaults.pcm.ipc_perm
-    }
-    tstamp_type {
-        @func refer
-        name defaults.pcm.tstamp_type
+#[derive(Debug)]
+struct OutputTarget {
+    pub path: PathBuf,
+    pub dry_run: bool,
+    // Where a dry run prints the configuration, see keep_stdout_for_config.
+    config_out: Option<File>,
+}
+
+impl OutputTarget {
+    pub fn new(output: Option<&Path>, user: bool, dry_run: bool) -> Result<Self, String> {
+        let path = match (output, user) {
+            (Some(output), _) => output.to_path_buf(),
+            (None, true) => match env::var_os("HOME") {
+                Some(home) if !home.is_empty() => Path::new(&home).join(USER_ASOUND_FILE_NAME),
+                _ => {
+                    return Err(
+                        "Could not determine your home directory, $HOME is not set.".to_string()
+                    )
+                }
+            },
+            (None, false) => PathBuf::from(ASOUND_FILE_PATH),
+        };
+
+        Ok(Self {
+            path,
+            dry_run,
+            config_out: None,
+        })
     }
-    slave {
-        pcm {
-            type hw
-            card {card}
-            device {device}
-            subdevice {sub_device}
+
+    // With --dry-run the configuration is the only thing that goes to
+    // stdout, so that `awiz --dry-run > asound.conf` gives a usable file.
+    // Everything else, prompts included, goes to stderr from here on.
+    pub fn keep_stdout_for_config(&mut self) {
+        let _ = stdout().flush();
+
+        unsafe {
+            let fd = libc::dup(libc::STDOUT_FILENO);
+
+            if fd < 0 {
+                return;
+            }
+
+            if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+                libc::close(fd);
+
+                return;
+            }
+
+            self.config_out = Some(File::from_raw_fd(fd));
         }
-        channels {channels}
-        rate {rate}
-        format {fmt}
-        period_size 0
-        buffer_size 0
-        periods 0
-        buffer_time {buffer_time}
-        period_time {period_time}
     }
-}";
 
-// See:
-// https://github.com/alsa-project/alsa-lib/blob/master/src/pcm/pcm_asym.c#L20
-const ASYM_TEMPLATE: &str = "\
-pcm.!default {
-    type asym
-    capture.pcm {
-        type plug
-        slave.pcm {input_pcm}
+    pub fn dir(&self) -> PathBuf {
+        match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
     }
-    playback.pcm {
-        type plug
-        slave.pcm {output_pcm}
+
+    pub fn display(&self) -> String {
+        self.path.display().to_string()
     }
-}";
 
-const CONTROL_TEMPLATE: &str = "\
-ctl.!default {
-    type hw
-    card {card}
-}";
+    pub fn backup_path(&self, now: &str) -> String {
+        BACKUP_FILE_PATH_TEMPLATE
+            .replace("{path}", &self.display())
+            .replace("{now}", now)
+    }
+}
 
 #[derive(Debug, Clone)]
 enum WorkerJob {
@@ -120,18 +205,133 @@ enum WorkerJob {
     Done,
 }
 
+// What workers tell the ThreadManager while they work.
+#[derive(Debug)]
+enum ProbeEvent {
+    Pcm {
+        card_name: String,
+        name: String,
+    },
+    ConfigurationTested {
+        card_name: String,
+    },
+    // Only with --wait-busy, otherwise it's a Rejection.
+    Busy {
+        card_name: String,
+        name: String,
+        holders: Vec<Holder>,
+    },
+    Done {
+        card_name: String,
+        playback_pcms: Vec<AlsaPcm>,
+        capture_pcms: Vec<AlsaPcm>,
+        rejected_pcms: Vec<RejectedPcm>,
+    },
+}
+
+// Handed to AlsaPcm::new so that it can report progress
+// and notice that it should stop.
+#[derive(Debug, Clone)]
+struct ProbeProgress {
+    card_name: String,
+    events: mpsc::Sender<ProbeEvent>,
+    cancelled: Arc<AtomicBool>,
+    pub wait_busy: bool,
+    pub explain: bool,
+}
+
+impl ProbeProgress {
+    pub fn pcm(&self, name: &str) {
+        let _ = self.events.send(ProbeEvent::Pcm {
+            card_name: self.card_name.clone(),
+            name: name.to_string(),
+        });
+    }
+
+    pub fn configuration_tested(&self) {
+        let _ = self.events.send(ProbeEvent::ConfigurationTested {
+            card_name: self.card_name.clone(),
+        });
+    }
+
+    pub fn busy(&self, name: &str, holders: Vec<Holder>) {
+        let _ = self.events.send(ProbeEvent::Busy {
+            card_name: self.card_name.clone(),
+            name: name.to_string(),
+            holders,
+        });
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(AtomicOrdering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ProbeOptions {
+    pub reprobe: bool,
+    // How long a card gets before it's given up on.
+    pub timeout: Duration,
+    // Wait for busy PCMs to be released instead of ignoring them.
+    pub wait_busy: bool,
+    // Keep every combination that was tested and why
+    // the invalid ones are invalid, for --explain.
+    pub explain: bool,
+    // Nothing but the results on stdout, for --json and --yaml.
+    pub quiet: bool,
+    // --dry-run, the results aren't cached either.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default)]
+struct ProbedPcms {
+    pub playback: Vec<AlsaPcm>,
+    pub capture: Vec<AlsaPcm>,
+    pub rejected: Vec<RejectedPcm>,
+    pub cancelled: bool,
+}
+
+// Set by the SIGINT handler while the cards are being probed
+// or the levels of a Capture PCM are being metered.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, AtomicOrdering::SeqCst);
+}
+
 #[derive(Debug)]
 struct ThreadManager {
+    backend: Arc<dyn Backend>,
+    options: ProbeOptions,
+    // Set to cancel probing, by the SIGINT handler unless it's a test.
+    interrupted: &'static AtomicBool,
     workers: Vec<ThreadWorker>,
+    event_sender: mpsc::Sender<ProbeEvent>,
+    event_receiver: mpsc::Receiver<ProbeEvent>,
 }
 
 impl ThreadManager {
-    pub fn new() -> Self {
+    pub fn new(backend: Arc<dyn Backend>, options: ProbeOptions) -> Self {
+        Self::with_interrupt(backend, options, &INTERRUPTED)
+    }
+
+    fn with_interrupt(
+        backend: Arc<dyn Backend>,
+        options: ProbeOptions,
+        interrupted: &'static AtomicBool,
+    ) -> Self {
         // The ThreadManager's job is to keep track
         // of works, give them jobs and make sure that
         // there's only ever one worker per card.
+        let (event_sender, event_receiver) = mpsc::channel();
+
         Self {
+            backend,
+            options,
+            interrupted,
             workers: Vec::with_capacity(20),
+            event_sender,
+            event_receiver,
         }
     }
 
@@ -158,7 +358,12 @@ impl ThreadManager {
         }
 
         if !job_sent {
-            let mut worker = ThreadWorker::new(card_name.clone());
+            let mut worker = ThreadWorker::new(
+                card_name.clone(),
+                self.backend.clone(),
+                self.event_sender.clone(),
+                &self.options,
+            );
 
             if worker.add_job(name, &card_name, direction) {
                 self.workers.push(worker);
@@ -166,67 +371,275 @@ impl ThreadManager {
         }
     }
 
-    pub fn get_pcms(&mut self) -> (Vec<AlsaPcm>, Vec<AlsaPcm>) {
-        let mut playback_pcms = Vec::with_capacity(20);
-        let mut capture_pcms = Vec::with_capacity(20);
+    pub fn get_pcms(&mut self) -> ProbedPcms {
+        let options = self.options;
+        let mut probed = ProbedPcms::default();
 
         for worker in self.workers.iter_mut() {
-            if let Some((p_pcms, c_pcms)) = worker.get_pcms() {
-                playback_pcms.extend(p_pcms);
-                capture_pcms.extend(c_pcms);
+            worker.finish();
+        }
+
+        // Ctrl+C stops the workers instead of killing us in the middle
+        // of talking to the hardware. Whatever handler was there before
+        // is put back once we're done.
+        self.interrupted.store(false, AtomicOrdering::SeqCst);
+
+        let previous_handler = unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+
+        let show_progress = !options.quiet && unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+
+        let mut status = String::new();
+
+        let mut progress: HashMap<String, CardProgress> = HashMap::new();
+
+        // Busy PCMs that have been announced already.
+        let mut waiting_for: Vec<String> = vec![];
+
+        // When Ctrl+C was pressed, the grace period is counted from then.
+        let mut cancelled_at = None;
+
+        while !self.workers.is_empty() {
+            if self.interrupted.load(AtomicOrdering::SeqCst) && cancelled_at.is_none() {
+                cancelled_at = Some(Instant::now());
+                probed.cancelled = true;
+
+                for worker in self.workers.iter_mut() {
+                    worker.cancel();
+                }
+            }
+
+            match self.event_receiver.recv_timeout(PROGRESS_INTERVAL) {
+                Ok(ProbeEvent::Pcm { card_name, name }) => {
+                    let card = progress.entry(card_name.clone()).or_default();
+
+                    card.pcm += 1;
+                    card.name = name;
+                    card.tested = 0;
+
+                    status = self.status(&card_name, card);
+                }
+                Ok(ProbeEvent::ConfigurationTested { card_name }) => {
+                    let card = progress.entry(card_name.clone()).or_default();
+
+                    card.tested += 1;
+
+                    status = self.status(&card_name, card);
+                }
+                Ok(ProbeEvent::Busy {
+                    card_name,
+                    name,
+                    holders,
+                }) => {
+                    let holders = if holders.is_empty() {
+                        "another process".to_string()
+                    } else {
+                        holders.iter().map(|holder| holder.to_string()).join(", ")
+                    };
+
+                    // Waiting on purpose is not hanging.
+                    for worker in self.workers.iter_mut() {
+                        if worker.card_name == card_name {
+                            worker.started = Instant::now();
+                        }
+                    }
+
+                    if !waiting_for.contains(&name) {
+                        clear_status(show_progress);
+
+                        println!(
+                            "{}",
+                            format!(
+                                "\n{name} is in use by {holders}, waiting for it to be released…"
+                            )
+                            .cyan()
+                        );
+
+                        waiting_for.push(name.clone());
+                    }
+
+                    status = format!("{card_name}: waiting for {name}, in use by {holders}…");
+                }
+                Ok(ProbeEvent::Done {
+                    card_name,
+                    playback_pcms,
+                    capture_pcms,
+                    rejected_pcms,
+                }) => {
+                    probed.playback.extend(playback_pcms);
+                    probed.capture.extend(capture_pcms);
+                    probed.rejected.extend(rejected_pcms);
+
+                    // The worker is on its way out, so this won't block.
+                    self.workers.retain(|worker| worker.card_name != card_name);
+                }
+                Err(_) => (),
+            }
+
+            // A hung driver can block a worker forever, there's
+            // nothing to be done about that but to leave it behind.
+            let expired = |worker: &ThreadWorker| match cancelled_at {
+                Some(cancelled_at) => cancelled_at.elapsed() >= CANCEL_GRACE_PERIOD,
+                None => worker.started.elapsed() >= options.timeout,
+            };
+
+            let rejection = match cancelled_at {
+                Some(_) => Rejection::Cancelled,
+                None => Rejection::TimedOut {
+                    seconds: options.timeout.as_secs(),
+                },
+            };
+
+            for worker in self.workers.iter_mut() {
+                if expired(worker) {
+                    worker.abandon();
+
+                    // Whatever it did find is lost with it.
+                    probed
+                        .rejected
+                        .extend(worker.pcms.iter().map(|(name, direction)| RejectedPcm {
+                            name: name.clone(),
+                            card_name: worker.card_name.clone(),
+                            direction: *direction,
+                            rejection: rejection.clone(),
+                            tested: vec![],
+                        }));
+                }
+            }
+
+            self.workers.retain(|worker| !worker.is_abandoned());
+
+            if show_progress && !status.is_empty() {
+                print!("\r\x1b[K{}", status.cyan());
+
+                let _ = stdout().flush();
             }
         }
 
-        (playback_pcms, capture_pcms)
+        clear_status(show_progress);
+
+        unsafe {
+            libc::signal(libc::SIGINT, previous_handler);
+        }
+
+        probed
+    }
+
+    fn status(&self, card_name: &str, card: &CardProgress) -> String {
+        let pcms = self
+            .workers
+            .iter()
+            .find(|worker| worker.card_name == card_name)
+            .map_or(card.pcm, |worker| worker.pcms.len());
+
+        format!(
+            "{card_name}: {} (PCM {} of {pcms}), Configurations tested: {}…",
+            card.name, card.pcm, card.tested
+        )
     }
 }
 
-type WorkerHandle = thread::JoinHandle<Option<(Vec<AlsaPcm>, Vec<AlsaPcm>)>>;
+// What the latest event of a card was about.
+#[derive(Debug, Default)]
+struct CardProgress {
+    pcm: usize,
+    name: String,
+    tested: usize,
+}
+
+fn clear_status(show_progress: bool) {
+    if show_progress {
+        print!("\r\x1b[K");
+
+        let _ = stdout().flush();
+    }
+}
 
 #[derive(Debug)]
 struct ThreadWorker {
     pub card_name: String,
-    thread_handle: Option<WorkerHandle>,
+    pub started: Instant,
+    // The PCMs it was given, in case it has to be abandoned.
+    pub pcms: Vec<(String, Direction)>,
+    cancelled: Arc<AtomicBool>,
+    thread_handle: Option<thread::JoinHandle<()>>,
     job_sender: Option<mpsc::Sender<WorkerJob>>,
 }
 
 impl ThreadWorker {
-    pub fn new(card_name: String) -> Self {
+    pub fn new(
+        card_name: String,
+        backend: Arc<dyn Backend>,
+        events: mpsc::Sender<ProbeEvent>,
+        options: &ProbeOptions,
+    ) -> Self {
         // Workers handle all jobs for one card in a
         // synchronous manner to avoid concurrently
         // opening the same card which for cards
         // that lack some sort of builtin mixer will fail.
         let (job_sender, job_receiver) = mpsc::channel();
 
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let progress = ProbeProgress {
+            card_name: card_name.clone(),
+            events,
+            cancelled: cancelled.clone(),
+            wait_busy: options.wait_busy,
+            explain: options.explain,
+        };
+
         let thread_handle = Some(thread::spawn(move || {
             let mut playback_pcms = Vec::with_capacity(20);
             let mut capture_pcms = Vec::with_capacity(20);
+            let mut rejected_pcms = Vec::with_capacity(20);
 
             loop {
                 match job_receiver.recv() {
-                    Err(_) => return None,
+                    Err(_) => return,
                     Ok(job) => match job {
-                        WorkerJob::Done => return Some((playback_pcms, capture_pcms)),
+                        WorkerJob::Done => {
+                            let _ = progress.events.send(ProbeEvent::Done {
+                                card_name: progress.card_name.clone(),
+                                playback_pcms,
+                                capture_pcms,
+                                rejected_pcms,
+                            });
+
+                            return;
+                        }
+                        // Keep draining the jobs until Done
+                        // but don't touch the hardware anymore.
                         WorkerJob::GetPcm {
                             name,
                             card_name,
                             direction,
-                        } => match direction {
-                            Direction::Playback => {
-                                let alsa_pcm = AlsaPcm::new(&name, &card_name, direction);
-
-                                if let Some(alsa_pcm) = alsa_pcm {
-                                    playback_pcms.push(alsa_pcm);
-                                }
-                            }
-                            Direction::Capture => {
-                                let alsa_pcm = AlsaPcm::new(&name, &card_name, direction);
-
-                                if let Some(alsa_pcm) = alsa_pcm {
-                                    capture_pcms.push(alsa_pcm);
-                                }
+                        } if progress.is_cancelled() => rejected_pcms.push(RejectedPcm {
+                            name,
+                            card_name,
+                            direction,
+                            rejection: Rejection::Cancelled,
+                            tested: vec![],
+                        }),
+                        WorkerJob::GetPcm {
+                            name,
+                            card_name,
+                            direction,
+                        } => {
+                            progress.pcm(&name);
+
+                            match AlsaPcm::new(&backend, &name, &card_name, direction, &progress) {
+                                Ok(alsa_pcm) => match direction {
+                                    Direction::Playback => playback_pcms.push(alsa_pcm),
+                                    Direction::Capture => capture_pcms.push(alsa_pcm),
+                                },
+                                Err(rejected) => rejected_pcms.push(rejected),
                             }
-                        },
+                        }
                     },
                 }
             }
@@ -234,6 +647,9 @@ impl ThreadWorker {
 
         Self {
             card_name,
+            started: Instant::now(),
+            pcms: vec![],
+            cancelled,
             thread_handle,
             job_sender: Some(job_sender),
         }
@@ -247,35 +663,44 @@ impl ThreadWorker {
                 direction,
             };
 
-            return sender.send(job).is_ok();
+            if sender.send(job).is_ok() {
+                self.pcms.push((name.to_string(), direction));
+
+                return true;
+            }
         }
 
         false
     }
 
-    pub fn get_pcms(&mut self) -> Option<(Vec<AlsaPcm>, Vec<AlsaPcm>)> {
+    pub fn finish(&mut self) {
         if let Some(sender) = self.job_sender.take() {
             // Send a WorkerJob::Done to break the loop
-            // in the worker thread so it joins the main thread
-            // and returns it's results.
+            // in the worker thread so it sends it's results
+            // and joins the main thread.
             let _ = sender.send(WorkerJob::Done);
         }
+    }
 
-        if let Some(handle) = self.thread_handle.take() {
-            if let Ok(pcms) = handle.join() {
-                return pcms;
-            }
-        }
+    pub fn cancel(&mut self) {
+        self.cancelled.store(true, AtomicOrdering::Relaxed);
+    }
 
-        None
+    pub fn abandon(&mut self) {
+        // The thread is left to its own devices, if it ever
+        // comes back it will stop at the first chance it gets.
+        self.cancel();
+        self.thread_handle.take();
+    }
+
+    pub fn is_abandoned(&self) -> bool {
+        self.thread_handle.is_none()
     }
 }
 
 impl Drop for ThreadWorker {
     fn drop(&mut self) {
-        if let Some(sender) = self.job_sender.take() {
-            let _ = sender.send(WorkerJob::Done);
-        }
+        self.finish();
 
         if let Some(handle) = self.thread_handle.take() {
             let _ = handle.join();
@@ -296,12 +721,22 @@ struct ValidConfiguration {
     pub channels: u32,
     pub buffer_time_ms: u32,
     buffer_time_range: RangeInclusive<u32>,
+    // Shared between clones, see get_buffer_times_ms.
+    buffer_times_cache: Arc<Mutex<Option<BufferTimes>>>,
+    backend: Arc<dyn Backend>,
+}
+
+// The valid Buffer Times of a Configuration, and why the first one past
+// them was refused, None if they go all the way up to the maximum.
+#[derive(Debug, Clone, Default)]
+struct BufferTimes {
+    valid_ms: Vec<u32>,
+    limit: Option<StepFailure>,
 }
 
 impl ValidConfiguration {
-    pub fn new(pcm: AlsaPcm, format: Format, rate: u32, channels: u32) -> Self {
-        let (buffer_time_min, buffer_time_max) =
-            Self::get_buffer_time_range(&pcm.name, pcm.direction, format, rate, channels);
+    pub fn new(pcm: AlsaPcm, hwp: &dyn HwSpace, format: Format, rate: u32, channels: u32) -> Self {
+        let (buffer_time_min, buffer_time_max) = Self::get_buffer_time_range(hwp);
 
         let fallback_buffer_time_ms = (buffer_time_max / 2).max(buffer_time_min) / US_PER_MS;
 
@@ -317,178 +752,121 @@ impl ValidConfiguration {
             channels,
             buffer_time_ms: fallback_buffer_time_ms,
             buffer_time_range: buffer_time_min..=buffer_time_max,
+            buffer_times_cache: Arc::new(Mutex::new(None)),
+            backend: pcm.backend,
         }
     }
 
-    pub fn get_buffer_times_ms(&mut self) -> Vec<u32> {
-        let mut buffer_times_ms = Vec::with_capacity(1000);
+    pub fn get_buffer_times_ms(&self) -> Vec<u32> {
+        self.get_buffer_times().valid_ms
+    }
 
-        for buffer_time in self.buffer_time_range.clone().step_by(US_PER_MS as usize) {
-            let period_time = buffer_time / PERIODS_PER_BUFFER;
+    fn get_buffer_times(&self) -> BufferTimes {
+        // The answer for a Configuration never changes,
+        // so it's only ever searched for once.
+        let mut cache = self
+            .buffer_times_cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
 
-            if self.test_buffer_times(buffer_time, period_time) {
-                buffer_times_ms.push(buffer_time / US_PER_MS);
-            }
+        if let Some(buffer_times) = cache.as_ref() {
+            return buffer_times.clone();
         }
 
-        buffer_times_ms
-    }
+        let mut buffer_times = BufferTimes::default();
 
-    fn get_buffer_time_range(
-        name: &str,
-        direction: Direction,
-        format: Format,
-        rate: u32,
-        channels: u32,
-    ) -> (u32, u32) {
-        if let Ok(pcm) = PCM::new(name, direction, false) {
-            if let Ok(hwp) = HwParams::any(&pcm) {
-                match hwp.set_rate_resample(false) {
-                    Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                    Ok(_) => match hwp.get_rate_resample() {
-                        Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                        Ok(actual_rate_resample) => {
-                            if actual_rate_resample {
-                                return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US);
-                            }
-                        }
-                    },
-                }
+        // Open the PCM and narrow it down to this Configuration
+        // once, every Buffer Time is then tested on a copy.
+        if let Ok(pcm) = self.backend.open(&self.name, self.direction) {
+            if let Some(hwp) = self.configuration_space(&*pcm) {
+                let mut failures = HashMap::new();
 
-                match hwp.set_format(format) {
-                    Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                    Ok(_) => match hwp.get_format() {
-                        Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                        Ok(actual_format) => {
-                            if actual_format != format {
-                                return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US);
-                            }
-                        }
-                    },
-                }
+                let (valid_ms, rejected_ms) = find_valid(
+                    self.buffer_time_range.start() / US_PER_MS,
+                    self.buffer_time_range.end() / US_PER_MS,
+                    |buffer_time_ms| {
+                        let buffer_time = buffer_time_ms * US_PER_MS;
+                        let period_time = buffer_time / PERIODS_PER_BUFFER;
 
-                match hwp.set_rate(rate, ValueOr::Nearest) {
-                    Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                    Ok(_) => match hwp.get_rate() {
-                        Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                        Ok(actual_rate) => {
-                            if actual_rate != rate {
-                                return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US);
-                            }
-                        }
-                    },
-                }
+                        match Self::explain_buffer_times(&*hwp, buffer_time, period_time) {
+                            Ok(()) => true,
+                            Err(failure) => {
+                                failures.insert(buffer_time_ms, failure);
 
-                match hwp.set_channels(channels) {
-                    Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                    Ok(_) => match hwp.get_channels() {
-                        Err(_) => return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US),
-                        Ok(actual_channels) => {
-                            if actual_channels != channels {
-                                return (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US);
+                                false
                             }
                         }
                     },
-                }
-
-                let buffer_time_min = match hwp.get_buffer_time_min() {
-                    Err(_) => MIN_BUFFER_TIME_US,
-                    Ok(buffer_time_min) => {
-                        ((buffer_time_min / US_PER_MS) * US_PER_MS).max(MIN_BUFFER_TIME_US)
-                    }
-                };
+                );
 
-                let buffer_time_max = match hwp.get_buffer_time_max() {
-                    Err(_) => MAX_BUFFER_TIME_US,
-                    Ok(buffer_time_max) => {
-                        ((buffer_time_max / US_PER_MS) * US_PER_MS).min(MAX_BUFFER_TIME_US)
-                    }
+                buffer_times = BufferTimes {
+                    valid_ms,
+                    limit: rejected_ms.and_then(|rejected_ms| failures.remove(&rejected_ms)),
                 };
-
-                return (buffer_time_min, buffer_time_max);
             }
         }
-        (MIN_BUFFER_TIME_US, MAX_BUFFER_TIME_US)
-    }
-
-    fn test_buffer_times(&mut self, buffer_time: u32, period_time: u32) -> bool {
-        // It's basically all or nothing with PCMs and HwParams.
-        // Once they are in an error state they can't be reused.
-        // So every time we test a combination of params we
-        // have to create new ones from scratch.
-        if let Ok(pcm) = PCM::new(&self.name, self.direction, false) {
-            if let Ok(hwp) = HwParams::any(&pcm) {
-                match hwp.set_rate_resample(false) {
-                    Err(_) => return false,
-                    Ok(_) => match hwp.get_rate_resample() {
-                        Err(_) => return false,
-                        Ok(actual_rate_resample) => {
-                            if actual_rate_resample {
-                                return false;
-                            }
-                        }
-                    },
-                }
 
-                match hwp.set_format(self.format) {
-                    Err(_) => return false,
-                    Ok(_) => match hwp.get_format() {
-                        Err(_) => return false,
-                        Ok(actual_format) => {
-                            if actual_format != self.format {
-                                return false;
-                            }
-                        }
-                    },
-                }
+        *cache = Some(buffer_times.clone());
 
-                match hwp.set_rate(self.rate, ValueOr::Nearest) {
-                    Err(_) => return false,
-                    Ok(_) => match hwp.get_rate() {
-                        Err(_) => return false,
-                        Ok(actual_rate) => {
-                            if actual_rate != self.rate {
-                                return false;
-                            }
-                        }
-                    },
-                }
+        buffer_times
+    }
 
-                match hwp.set_channels(self.channels) {
-                    Err(_) => return false,
-                    Ok(_) => match hwp.get_channels() {
-                        Err(_) => return false,
-                        Ok(actual_channels) => {
-                            if actual_channels != self.channels {
-                                return false;
-                            }
-                        }
-                    },
-                }
+    fn configuration_space<'a>(&self, pcm: &'a dyn Device) -> Option<Box<dyn HwSpace + 'a>> {
+        let hwp = open_space(pcm)?;
 
-                match hwp.set_buffer_time_near(buffer_time, ValueOr::Nearest) {
-                    Err(_) => return false,
-                    Ok(actual_buffer_time) => {
-                        if actual_buffer_time != buffer_time {
-                            return false;
-                        }
-                    }
-                }
+        let refined = refine(&*hwp, Param::Format(self.format))
+            && refine(&*hwp, Param::Rate(self.rate))
+            && refine(&*hwp, Param::Channels(self.channels));
 
-                match hwp.set_period_time_near(period_time, ValueOr::Nearest) {
-                    Err(_) => return false,
-                    Ok(actual_period_time) => {
-                        if actual_period_time != period_time {
-                            return false;
-                        }
-                    }
-                }
+        refined.then_some(hwp)
+    }
 
-                return pcm.hw_params(&hwp).is_ok();
+    fn get_buffer_time_range(hwp: &dyn HwSpace) -> (u32, u32) {
+        let buffer_time_min = match hwp.get_buffer_time_min() {
+            Err(_) => MIN_BUFFER_TIME_US,
+            Ok(buffer_time_min) => {
+                ((buffer_time_min / US_PER_MS) * US_PER_MS).max(MIN_BUFFER_TIME_US)
             }
-        }
+        };
 
-        false
+        let buffer_time_max = match hwp.get_buffer_time_max() {
+            Err(_) => MAX_BUFFER_TIME_US,
+            Ok(buffer_time_max) => {
+                ((buffer_time_max / US_PER_MS) * US_PER_MS).min(MAX_BUFFER_TIME_US)
+            }
+        };
+
+        (buffer_time_min, buffer_time_max)
+    }
+
+    fn explain_buffer_times(
+        hwp: &dyn HwSpace,
+        buffer_time: u32,
+        period_time: u32,
+    ) -> Result<(), StepFailure> {
+        let hwp = hwp
+            .try_clone()
+            .map_err(|e| StepFailure::refused(Step::BufferTime, buffer_time, e))?;
+
+        exactly(
+            Step::BufferTime,
+            buffer_time,
+            hwp.set_buffer_time_near(buffer_time, ValueOr::Nearest),
+        )?;
+
+        exactly(
+            Step::PeriodTime,
+            period_time,
+            hwp.set_period_time_near(period_time, ValueOr::Nearest),
+        )?;
+
+        hwp.install().map_err(StepFailure::not_installed)
+    }
+
+    // Why the Buffer Times end where they do, or why there are none
+    // at all. None if they simply go all the way up to the maximum.
+    pub fn explain_buffer_time_limit(&self) -> Option<StepFailure> {
+        self.get_buffer_times().limit
     }
 }
 
@@ -502,26 +880,53 @@ struct AlsaPcm {
     pub sub_device_number: u32,
     pub formats: Vec<Format>,
     pub rates: Vec<u32>,
+    // Set if the PCM supports any rate in a continuous range,
+    // rates then only holds the standard rates within it.
+    pub rate_range: Option<RangeInclusive<u32>>,
     pub channels: Vec<u32>,
     pub valid_configurations: Vec<ValidConfiguration>,
+    // Only filled in with --explain.
+    pub tested: Vec<TestedCombination>,
+    pub probe_time: Duration,
+    backend: Arc<dyn Backend>,
 }
 
 impl AlsaPcm {
-    pub fn new(name: &str, card_name: &str, direction: Direction) -> Option<Self> {
+    pub fn new(
+        backend: &Arc<dyn Backend>,
+        name: &str,
+        card_name: &str,
+        direction: Direction,
+        progress: &ProbeProgress,
+    ) -> Result<Self, RejectedPcm> {
+        let start = Instant::now();
+        let reject = |rejection, tested| RejectedPcm {
+            name: name.to_string(),
+            card_name: card_name.to_string(),
+            direction,
+            rejection,
+            tested,
+        };
         let mut description = String::new();
         let mut device_number: u32 = 0;
         let mut sub_device_number: u32 = 0;
         let mut formats = Vec::with_capacity(4);
-        let mut rates = Vec::with_capacity(100);
+        let mut rates = Vec::with_capacity(STANDARD_RATES.len());
+        let mut rate_range = None;
         let mut channels = Vec::with_capacity(100);
 
-        if let Ok(pcm) = PCM::new(name, direction, false) {
+        {
+            // Scoped so that it's closed again before
+            // the Configurations are tested.
+            let pcm = Self::open(backend, name, direction, progress)
+                .map_err(|rejection| reject(rejection, vec![]))?;
+
             if let Ok(info) = pcm.info() {
-                description = info.get_name().unwrap_or("NONE").to_string();
-                device_number = info.get_device();
-                sub_device_number = info.get_subdevice();
+                description = info.name.unwrap_or_else(|| "NONE".to_string());
+                device_number = info.device;
+                sub_device_number = info.subdevice;
 
-                if let Ok(hwp) = HwParams::any(&pcm) {
+                if let Ok(hwp) = pcm.hw_params_any() {
                     if hwp.set_rate_resample(false).is_ok() {
                         for f in FORMATS {
                             if hwp.test_format(f).is_ok() {
@@ -530,67 +935,13 @@ impl AlsaPcm {
                         }
 
                         if formats.is_empty() {
-                            let supported_formats = format!(
-                                "{:?}",
-                                FORMATS
-                                    .iter()
-                                    .map(|f| f.to_string())
-                                    .collect::<Vec<String>>()
-                            )
-                            .replace('"', "");
-
-                            println!(
-                                "{}",
-                                format!(
-                                    "\n{name} does not support any formats supported by dmix/dsnoop ({supported_formats})."
-                                ).bold().yellow()
-                            );
-
-                            println!(
-                                "{}",
-                                format!("\n{name} is not software mixable, and will be ignored.")
-                                    .bold()
-                                    .yellow()
-                            );
-
-                            return None;
+                            return Err(reject(Rejection::NoMixableFormats, vec![]));
                         }
 
                         let min_rate = hwp.get_rate_min().unwrap_or(MIN_RATE).max(MIN_RATE);
                         let max_rate = hwp.get_rate_max().unwrap_or(MAX_RATE).min(MAX_RATE);
 
-                        for r in min_rate..=max_rate {
-                            if hwp.test_rate(r).is_ok() {
-                                if rates.len() != rates.capacity() {
-                                    rates.push(r);
-                                } else {
-                                    println!(
-                                        "{}",
-                                        format!(
-                                            "\n{name} is reporting an unusually large number of supported sampling rates (100+)."
-                                        ).bold().yellow()
-                                    );
-
-                                    println!(
-                                        "{}",
-                                        format!(
-                                            "\n{name} is more than likely not a real hardware device, but is actually a hardware device behind a plug plugin."
-                                        ).bold().yellow()
-                                    );
-
-                                    println!(
-                                        "{}",
-                                        format!(
-                                            "\n{name} is not software mixable, and will be ignored."
-                                        )
-                                        .bold()
-                                        .yellow()
-                                    );
-
-                                    return None;
-                                }
-                            }
-                        }
+                        (rates, rate_range) = Self::get_rates(&*hwp, min_rate, max_rate);
 
                         let min_channels = hwp.get_channels_min().unwrap_or(1).max(1);
                         let max_channels = hwp.get_channels_max().unwrap_or(u32::MAX).max(1);
@@ -600,30 +951,7 @@ impl AlsaPcm {
                                 if channels.len() != channels.capacity() {
                                     channels.push(c);
                                 } else {
-                                    println!(
-                                        "{}",
-                                        format!(
-                                            "\n{name} is reporting an unusually large number of supported channel counts (100+)."
-                                        ).bold().yellow()
-                                    );
-
-                                    println!(
-                                        "{}",
-                                        format!(
-                                            "\n{name} is more than likely not a real hardware device, but is actually a hardware device behind a plug plugin."
-                                        ).bold().yellow()
-                                    );
-
-                                    println!(
-                                        "{}",
-                                        format!(
-                                            "\n{name} is not software mixable, and will be ignored."
-                                        )
-                                        .bold()
-                                        .yellow()
-                                    );
-
-                                    return None;
+                                    return Err(reject(Rejection::TooManyChannels, vec![]));
                                 }
                             }
                         }
@@ -641,21 +969,35 @@ impl AlsaPcm {
             sub_device_number,
             formats,
             rates,
+            rate_range,
             channels,
             valid_configurations: vec![],
+            tested: vec![],
+            probe_time: Duration::ZERO,
+            backend: backend.clone(),
         };
 
-        let valid_configs = Self::get_valid_configurations(&pcm);
+        let (valid_configs, tested) = Self::get_valid_configurations(&pcm, progress);
 
-        if valid_configs.is_empty() {
-            println!(
-                "{}",
-                format!("\n{name} has no valid configurations, and will be ignored.")
-                    .bold()
-                    .yellow()
-            );
+        // --explain also says why the Buffer Times end where they do,
+        // which means finding them here, where a hung driver can't hang us.
+        if progress.explain {
+            for config in &valid_configs {
+                if progress.is_cancelled() {
+                    break;
+                }
 
-            None
+                config.get_buffer_times();
+            }
+        }
+
+        // Whatever was found so far is incomplete.
+        if progress.is_cancelled() {
+            return Err(reject(Rejection::Cancelled, vec![]));
+        }
+
+        if valid_configs.is_empty() {
+            Err(reject(Rejection::NoValidConfigurations, tested))
         } else {
             // Filter out Formats, rates and channels that never
             // appear in a valid config.
@@ -672,84 +1014,90 @@ impl AlsaPcm {
             });
 
             pcm.valid_configurations = valid_configs;
+            pcm.tested = tested;
+            pcm.probe_time = start.elapsed();
 
-            Some(pcm)
+            Ok(pcm)
         }
     }
 
-    fn test_params(
+    // Open the PCM. If it's busy find out who is using it
+    // and, with --wait-busy, keep trying until they let go.
+    fn open<'a>(
+        backend: &'a Arc<dyn Backend>,
         name: &str,
         direction: Direction,
-        format: Format,
-        rate: Option<u32>,
-        channels: Option<u32>,
-    ) -> bool {
-        // It's basically all or nothing with PCMs and HwParams.
-        // Once they are in an error state they can't be reused.
-        // So every time we test a combination of params we
-        // have to create new ones from scratch.
-        if let Ok(pcm) = PCM::new(name, direction, false) {
-            if let Ok(hwp) = HwParams::any(&pcm) {
-                match hwp.set_rate_resample(false) {
-                    Err(_) => return false,
-                    Ok(_) => match hwp.get_rate_resample() {
-                        Err(_) => return false,
-                        Ok(actual_rate_resample) => {
-                            if actual_rate_resample {
-                                return false;
-                            }
-                        }
-                    },
-                }
-
-                match hwp.set_format(format) {
-                    Err(_) => return false,
-                    Ok(_) => match hwp.get_format() {
-                        Err(_) => return false,
-                        Ok(actual_format) => {
-                            if actual_format != format {
-                                return false;
-                            }
-                        }
-                    },
-                }
+        progress: &ProbeProgress,
+    ) -> Result<Box<dyn Device + 'a>, Rejection> {
+        loop {
+            match backend.open(name, direction) {
+                Ok(pcm) => return Ok(pcm),
+                Err(e) if e.errno == libc::EBUSY => {
+                    let holders = backend.holders(name, direction);
 
-                if let Some(rate) = rate {
-                    match hwp.set_rate(rate, ValueOr::Nearest) {
-                        Err(_) => return false,
-                        Ok(_) => match hwp.get_rate() {
-                            Err(_) => return false,
-                            Ok(actual_rate) => {
-                                if actual_rate != rate {
-                                    return false;
-                                }
-                            }
-                        },
+                    if !progress.wait_busy {
+                        return Err(Rejection::Busy { holders });
                     }
-                }
 
-                if let Some(channels) = channels {
-                    match hwp.set_channels(channels) {
-                        Err(_) => return false,
-                        Ok(_) => match hwp.get_channels() {
-                            Err(_) => return false,
-                            Ok(actual_channels) => {
-                                if actual_channels != channels {
-                                    return false;
-                                }
-                            }
-                        },
+                    if progress.is_cancelled() {
+                        return Err(Rejection::Cancelled);
                     }
-                }
 
-                return pcm.hw_params(&hwp).is_ok();
+                    progress.busy(name, holders);
+
+                    thread::sleep(BUSY_RETRY_INTERVAL);
+                }
+                Err(e) => return Err(Rejection::OpenFailed { errno: e.errno }),
             }
         }
+    }
 
-        false
+    fn get_rates(
+        hwp: &dyn HwSpace,
+        min_rate: u32,
+        max_rate: u32,
+    ) -> (Vec<u32>, Option<RangeInclusive<u32>>) {
+        // Testing every integer between min_rate and max_rate can mean
+        // hundreds of thousands of calls. Real hardware either supports
+        // a handful of rates, almost always from the standard table,
+        // or any rate in a continuous range. So test the standard rates,
+        // min_rate and max_rate, and a few odd rates in between to tell
+        // the two apart.
+        if min_rate > max_rate {
+            return (vec![], None);
+        }
+
+        let mut rates: Vec<u32> = STANDARD_RATES
+            .into_iter()
+            .chain([min_rate, max_rate])
+            .filter(|r| (min_rate..=max_rate).contains(r))
+            .filter(|r| hwp.test_rate(*r).is_ok())
+            .collect();
+
+        rates.sort();
+        rates.dedup();
+
+        let continuous = max_rate - min_rate > 2
+            && [
+                min_rate + 1,
+                (min_rate + (max_rate - min_rate) / 2) | 1,
+                max_rate - 1,
+            ]
+            .iter()
+            .all(|r| hwp.test_rate(*r).is_ok());
+
+        if continuous {
+            // Nobody wants 3001Hz just because it's possible.
+            rates.retain(|r| STANDARD_RATES.contains(r));
+        }
+
+        (rates, continuous.then_some(min_rate..=max_rate))
     }
 
-    fn get_valid_configurations(pcm: &AlsaPcm) -> Vec<ValidConfiguration> {
+    fn get_valid_configurations(
+        pcm: &AlsaPcm,
+        progress: &ProbeProgress,
+    ) -> (Vec<ValidConfiguration>, Vec<TestedCombination>) {
         // The supported formats, rates and channels are a bit deceptive.
         // Not all combinations necessarily result in a valid config.
         //
@@ -758,30 +1106,80 @@ impl AlsaPcm {
         let possible_num_configs = pcm.formats.len() * pcm.rates.len() * pcm.channels.len();
 
         let mut configs = Vec::with_capacity(possible_num_configs);
+        let mut tested = vec![];
 
+        let device = match pcm.backend.open(&pcm.name, pcm.direction) {
+            Err(_) => return (configs, tested),
+            Ok(device) => device,
+        };
+
+        let hwp = match open_space(&*device) {
+            None => return (configs, tested),
+            Some(hwp) => hwp,
+        };
+
+        // If a Format or rate already fails every combination
+        // below it fails the same way, without asking the device.
         for format in &pcm.formats {
-            if Self::test_params(&pcm.name, pcm.direction, *format, None, None) {
-                for rate in &pcm.rates {
-                    if Self::test_params(&pcm.name, pcm.direction, *format, Some(*rate), None) {
-                        for channels in &pcm.channels {
-                            if Self::test_params(
-                                &pcm.name,
-                                pcm.direction,
-                                *format,
-                                Some(*rate),
-                                Some(*channels),
-                            ) {
-                                let valid_config =
-                                    ValidConfiguration::new(pcm.clone(), *format, *rate, *channels);
-                                configs.push(valid_config);
-                            }
-                        }
+            let format_hwp = branch(&*hwp, Param::Format(*format))
+                .and_then(|format_hwp| check_install(&*format_hwp).map(|_| format_hwp));
+
+            for rate in &pcm.rates {
+                let rate_hwp = format_hwp
+                    .as_ref()
+                    .map_err(Clone::clone)
+                    .and_then(|format_hwp| {
+                        branch(&**format_hwp, Param::Rate(*rate))
+                            .and_then(|rate_hwp| check_install(&*rate_hwp).map(|_| rate_hwp))
+                    });
+
+                if rate_hwp.is_err() && !progress.explain {
+                    continue;
+                }
+
+                for channels in &pcm.channels {
+                    if progress.is_cancelled() {
+                        return (configs, tested);
+                    }
+
+                    progress.configuration_tested();
+
+                    let channels_hwp =
+                        rate_hwp
+                            .as_ref()
+                            .map_err(Clone::clone)
+                            .and_then(|rate_hwp| {
+                                branch(&**rate_hwp, Param::Channels(*channels)).and_then(
+                                    |channels_hwp| {
+                                        check_install(&*channels_hwp).map(|_| channels_hwp)
+                                    },
+                                )
+                            });
+
+                    if progress.explain {
+                        tested.push(TestedCombination {
+                            format: *format,
+                            rate: *rate,
+                            channels: *channels,
+                            failure: channels_hwp.as_ref().err().cloned(),
+                        });
+                    }
+
+                    if let Ok(channels_hwp) = channels_hwp {
+                        let valid_config = ValidConfiguration::new(
+                            pcm.clone(),
+                            &*channels_hwp,
+                            *format,
+                            *rate,
+                            *channels,
+                        );
+                        configs.push(valid_config);
                     }
                 }
             }
         }
 
-        configs
+        (configs, tested)
     }
 }
 
@@ -902,7 +1300,15 @@ fn show_pcms(pcms: &[AlsaPcm]) {
             .add_row(vec![Cell::new(
                 format!("FORMATS: {:?}", formats).replace('"', ""),
             )])
-            .add_row(vec![Cell::new(format!("RATES: {:?}", pcm.rates))])
+            .add_row(vec![Cell::new(match &pcm.rate_range {
+                None => format!("RATES: {:?}", pcm.rates),
+                Some(range) => format!(
+                    "RATES: {:?} (continuous {} - {})",
+                    pcm.rates,
+                    range.start(),
+                    range.end()
+                ),
+            })])
             .add_row(vec![Cell::new(format!("CHANNELS: {:?}", pcm.channels))]);
     }
 
@@ -1037,9 +1443,11 @@ fn choose_a_configuration(mut configs: Vec<ValidConfiguration>) -> ValidConfigur
             );
 
             println!(
-                    "{}",
-                    format!("\nIf you experience issues you may need to manually edit {ASOUND_FILE_PATH} to correct them.").bold().yellow()
-                );
+                "{}",
+                "\nIf you experience issues you may need to manually edit the generated configuration to correct them."
+                    .bold()
+                    .yellow()
+            );
         }
     }
 
@@ -1113,6 +1521,74 @@ fn show_configuration(config: &ValidConfiguration) {
     println!("\n{table}");
 }
 
+// Play a test tone through the default PCM of a configuration with config as
+// its Playback, without installing it. True if it sounded right to the user.
+fn audition(config: &ValidConfiguration) -> bool {
+    let confirm = user_input(
+        "Please Enter \"T\" to hear a test tone through it, or anything else to skip that: ",
+    )
+    .to_lowercase();
+
+    if confirm != "t" {
+        return true;
+    }
+
+    loop {
+        if let Err(e) = play_test_tone(config) {
+            eprintln!("{}", format!("\nError: {e}").bold().red());
+
+            println!("{}", "\nPlease choose another Configuration.".cyan());
+
+            return false;
+        }
+
+        let confirm = user_input(
+            "Did it sound right? Please Enter \"OK\" if it did, \"T\" to hear it again, or anything else to choose another Configuration: ",
+        )
+        .to_lowercase();
+
+        match confirm.as_str() {
+            "ok" => return true,
+            "t" => continue,
+            _ => return false,
+        }
+    }
+}
+
+fn play_test_tone(config: &ValidConfiguration) -> Result<(), String> {
+    let tree = ConfigTree::with_config(&build_asound_conf(Some(config), None, None))?;
+
+    let mut stream = Stream::open(
+        tree,
+        "default",
+        Direction::Playback,
+        config.format,
+        config.rate,
+        config.channels,
+        config.buffer_time_ms * US_PER_MS,
+    )?;
+
+    println!(
+        "{}",
+        format!(
+            "\nPlaying a {TEST_TONE_FREQUENCY} Hz test tone on all {} channels…",
+            config.channels
+        )
+        .cyan()
+    );
+
+    let tone = sine(
+        config.rate,
+        TEST_TONE_FREQUENCY,
+        TEST_TONE_DURATION,
+        TEST_TONE_AMPLITUDE,
+    );
+
+    stream.write(&interleave(&tone, config.channels))?;
+
+    stream.drain()
+}
+
 fn choose_a_converter(converters: &[String]) -> &str {
     let vec_len = converters.len();
     let mut converter_index = 0;
@@ -1138,22 +1614,308 @@ fn choose_a_converter(converters: &[String]) -> &str {
     &converters[converter_index]
 }
 
-fn get_pcms() -> (Vec<AlsaPcm>, Vec<AlsaPcm>) {
-    let mut thread_manager = ThreadManager::new();
+fn select_configuration(
+    pcms: &[AlsaPcm],
+    request: &ConfigRequest,
+    direction: Direction,
+    policy: Option<&ScoringPolicy>,
+) -> Result<ValidConfiguration, (i32, String)> {
+    // The non-interactive counterpart to choose_a_pcm and
+    // choose_a_configuration. Every requested value is checked
+    // against what was actually probed. Values that were not
+    // requested are only filled in if there is exactly one choice,
+    // or ranked by the ScoringPolicy with --auto.
+    let matches: Vec<&AlsaPcm> = pcms.iter().filter(|pcm| request.pcm.matches(pcm)).collect();
+
+    if matches.is_empty() {
+        let available = if pcms.is_empty() {
+            "NONE".to_string()
+        } else {
+            pcms.iter().map(|pcm| pcm.name.as_str()).join(", ")
+        };
+
+        return Err((
+            EXIT_PCM_NOT_FOUND,
+            format!(
+                "{} is not an available {direction:?} PCM.\nAvailable {direction:?} PCMs: {available}",
+                request.pcm
+            ),
+        ));
+    }
+
+    let (mut config, target_buffer_time_ms) = match policy {
+        Some(policy) => {
+            let configs: Vec<ValidConfiguration> = matches
+                .iter()
+                .flat_map(|pcm| pcm.valid_configurations.clone())
+                .filter(|c| request.format.map_or(true, |f| f == c.format))
+                .filter(|c| request.rate.map_or(true, |r| r == c.rate))
+                .filter(|c| request.channels.map_or(true, |ch| ch == c.channels))
+                .collect();
+
+            if configs.is_empty() {
+                return Err((
+                    EXIT_INVALID_CONFIGURATION,
+                    format!(
+                        "{} has no valid Configuration that matches the requested Format, Sampling Rate and Channel Count.",
+                        request.pcm
+                    ),
+                ));
+            }
+
+            let ranked = policy.rank(configs);
 
-    if let Ok(hints) = HintIter::new_str(None, "pcm") {
-        for hint in hints {
-            if let Some(name) = hint.name {
-                if name.starts_with("hw:") {
-                    if let Some(direction) = hint.direction {
-                        thread_manager.add_job(&name, direction);
-                    }
+            show_ranking(&ranked, direction);
+
+            (ranked[0].config.clone(), policy.buffer_time_ms)
+        }
+        None => {
+            let pcm = match matches[..] {
+                [pcm] => pcm,
+                _ => {
+                    return Err((
+                        EXIT_PCM_NOT_FOUND,
+                        format!(
+                            "{} matches more than one {direction:?} PCM: {}",
+                            request.pcm,
+                            matches.iter().map(|pcm| pcm.name.as_str()).join(", ")
+                        ),
+                    ));
+                }
+            };
+
+            let mut configs = pcm.valid_configurations.clone();
+
+            narrow_configurations(&mut configs, &pcm.name, "Format", request.format, |c| {
+                c.format
+            })
+            .map_err(|e| (EXIT_INVALID_CONFIGURATION, e))?;
+
+            narrow_configurations(
+                &mut configs,
+                &pcm.name,
+                "Sampling Rate",
+                request.rate,
+                |c| c.rate,
+            )
+            .map_err(|e| (EXIT_INVALID_CONFIGURATION, e))?;
+
+            narrow_configurations(
+                &mut configs,
+                &pcm.name,
+                "Channel Count",
+                request.channels,
+                |c| c.channels,
+            )
+            .map_err(|e| (EXIT_INVALID_CONFIGURATION, e))?;
+
+            let config = configs.remove(0);
+
+            // Same as the interactive fallback, but snapped
+            // to the nearest time that was actually reported.
+            let fallback_buffer_time_ms = config.buffer_time_ms;
+
+            (config, fallback_buffer_time_ms)
+        }
+    };
+
+    let buffer_times_ms = config.get_buffer_times_ms();
+
+    match request.buffer_time_ms {
+        Some(buffer_time_ms) => {
+            if buffer_times_ms.contains(&buffer_time_ms) {
+                config.buffer_time_ms = buffer_time_ms;
+            } else {
+                let available = if buffer_times_ms.is_empty() {
+                    "NONE".to_string()
+                } else {
+                    format_ranges(&buffer_times_ms)
+                };
+
+                return Err((
+                    EXIT_INVALID_CONFIGURATION,
+                    format!(
+                        "{} does not support a Buffer Time of {buffer_time_ms} milliseconds with {} {} {}ch.\nAvailable Buffer Times in milliseconds: {available}",
+                        config.name, config.format, config.rate, config.channels
+                    ),
+                ));
+            }
+        }
+        None => {
+            if let Some(buffer_time_ms) = buffer_times_ms
+                .iter()
+                .min_by_key(|x| x.abs_diff(target_buffer_time_ms))
+            {
+                config.buffer_time_ms = *buffer_time_ms;
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+fn narrow_configurations<T, F>(
+    configs: &mut Vec<ValidConfiguration>,
+    name: &str,
+    param: &str,
+    wanted: Option<T>,
+    key: F,
+) -> Result<(), String>
+where
+    T: Copy + Ord + std::hash::Hash + std::fmt::Display,
+    F: Fn(&ValidConfiguration) -> T,
+{
+    let mut available: Vec<T> = configs.iter().map(&key).unique().collect();
+
+    available.sort();
+
+    let value = match wanted {
+        Some(value) if available.contains(&value) => value,
+        Some(value) => {
+            return Err(format!(
+                "{name} does not support a {param} of {value} in combination with the other choices.\nAvailable {param}s: {}",
+                available.iter().join(", ")
+            ))
+        }
+        None if available.len() == 1 => available[0],
+        None => {
+            return Err(format!(
+                "{name} supports more than one {param}, please choose one.\nAvailable {param}s: {}",
+                available.iter().join(", ")
+            ))
+        }
+    };
+
+    configs.retain(|config| key(config) == value);
+
+    Ok(())
+}
+
+fn format_ranges(values: &[u32]) -> String {
+    // Collapse runs of consecutive values so that
+    // hundreds of Buffer Times stay readable.
+    let mut ranges: Vec<String> = Vec::with_capacity(values.len());
+    let mut iter = values.iter().peekable();
+
+    while let Some(&start) = iter.next() {
+        let mut end = start;
+
+        while let Some(&&next) = iter.peek() {
+            if next != end + 1 {
+                break;
+            }
+
+            end = next;
+            iter.next();
+        }
+
+        if start == end {
+            ranges.push(start.to_string());
+        } else {
+            ranges.push(format!("{start} - {end}"));
+        }
+    }
+
+    ranges.join(", ")
+}
+
+fn select_converter(converters: &[String], wanted: Option<&str>) -> Result<Option<String>, String> {
+    match wanted {
+        None => Ok(None),
+        Some(wanted) => {
+            if converters.iter().any(|converter| converter == wanted) {
+                Ok(Some(wanted.to_string()))
+            } else {
+                let available = if converters.is_empty() {
+                    "NONE".to_string()
+                } else {
+                    converters.join(", ")
+                };
+
+                Err(format!(
+                    "{wanted} is not an available Sample Rate Converter.\nAvailable Sample Rate Converters: {available}"
+                ))
+            }
+        }
+    }
+}
+
+fn exit_with_error<T: std::fmt::Display>(message: T, code: i32) -> ! {
+    let message = format!("\nError: {message}").bold().red();
+
+    eprintln!("{message}");
+    exit(code);
+}
+
+fn get_pcms(backend: &Arc<dyn Backend>, options: &ProbeOptions) -> ProbedPcms {
+    let mut thread_manager = ThreadManager::new(backend.clone(), *options);
+
+    for hint in backend.hints() {
+        if let Some(name) = hint.name {
+            if name.starts_with("hw:") {
+                if let Some(direction) = hint.direction {
+                    thread_manager.add_job(&name, direction);
                 }
             }
         }
     }
 
-    thread_manager.get_pcms()
+    let probed = thread_manager.get_pcms();
+
+    if probed.cancelled {
+        println!("{}", "\nProbing was cancelled.".cyan());
+
+        exit(EXIT_CANCELLED);
+    }
+
+    probed
+}
+
+// Probing can take a while and the hardware of the systems awiz is
+// meant for rarely changes, so the results are cached. The cache is
+// only used as long as the hardware and alsa-lib stay the same.
+fn get_cached_pcms(backend: &Arc<dyn Backend>, options: &ProbeOptions) -> ProbedPcms {
+    // Only the real hardware is worth caching.
+    let fingerprint = match backend.is_hardware().then(Fingerprint::current).flatten() {
+        None => return get_pcms(backend, options),
+        Some(fingerprint) => fingerprint,
+    };
+
+    let cache = ProbeCache::new(Path::new(CACHE_DIR));
+
+    if !options.reprobe {
+        if let Some(Ok(probed)) = cache
+            .load(&fingerprint)
+            .map(|report| report.to_probed(backend))
+        {
+            if !options.quiet {
+                println!(
+                    "{}",
+                    "\nThe hardware has not changed since it was last probed, using the cached results.\nRun with --reprobe to probe it again."
+                        .cyan()
+                );
+            }
+
+            return probed;
+        }
+    }
+
+    let probed = get_pcms(backend, options);
+
+    // Not being able to write the cache, for instance because we're
+    // not root, is not a problem. It just means probing again next time.
+    // A probe that's missing PCMs that were busy, or cards
+    // that timed out, is not worth keeping.
+    if !options.dry_run
+        && !probed
+            .rejected
+            .iter()
+            .any(|rejected| rejected.rejection.is_transient())
+    {
+        let _ = cache.store(&fingerprint, &ProbeReport::new(&probed));
+    }
+
+    probed
 }
 
 fn get_rate_converters() -> Vec<String> {
@@ -1176,15 +1938,20 @@ fn get_rate_converters() -> Vec<String> {
     rate_converters
 }
 
-fn permission_check(now: &str) {
+fn permission_check(now: &str, target: &OutputTarget) {
     // The most effective and least brittle way to see if
-    // we have write privileges to /etc is to just try to
-    // write a dummy file in /etc.
-    let path = DUMMY_FILE_PATH_TEMPLATE.replace("{now}", now);
+    // we have write privileges to the target directory is
+    // to just try to write a dummy file there.
+    let dir = target.dir();
+    let path = dir.join(DUMMY_FILE_NAME_TEMPLATE.replace("{now}", now));
+
     if let Err(e) = File::create(path.clone()) {
-        let message = format!("\nError: This utility requires write privileges to /etc: {e}")
-            .bold()
-            .red();
+        let message = format!(
+            "\nError: This utility requires write privileges to {}: {e}",
+            dir.display()
+        )
+        .bold()
+        .red();
 
         eprintln!("{message}");
         exit(1);
@@ -1222,170 +1989,864 @@ fn conflict_check() {
 }
 
 fn build_asound_conf(
-    playback_config: Option<ValidConfiguration>,
-    capture_config: Option<ValidConfiguration>,
+    playback_config: Option<&ValidConfiguration>,
+    capture_config: Option<&ValidConfiguration>,
     rate_converter: Option<&str>,
 ) -> String {
-    let mut config_blocks = Vec::with_capacity(5);
-    let mut input_pcm = "\"null\"".to_string();
-    let mut output_pcm = "\"null\"".to_string();
-    let mut converter = String::new();
-    let mut playback = String::new();
-    let mut capture = String::new();
-    let mut control = String::new();
+    let mut conf = Compound::new();
+    let mut input_pcm = "null";
+    let mut output_pcm = "null";
+    let mut control_card = None;
 
     if let Some(rate_converter) = rate_converter {
-        converter = format!("defaults.pcm.rate_converter {rate_converter}");
+        conf.push("defaults.pcm.rate_converter", rate_converter);
     }
 
     if let Some(config) = playback_config {
-        output_pcm = "\"playback\"".to_string();
+        output_pcm = "playback";
 
-        let buffer_time = config.buffer_time_ms * US_PER_MS;
-        let period_time = buffer_time / PERIODS_PER_BUFFER;
+        conf.push("pcm.playback", dmix_dsnoop("dmix", config));
 
-        playback = PLAYBACK_CAPTURE_TEMPLATE
-            .replace("{playback_capture}", "playback")
-            .replace("{dmix_dsnoop}", "dmix")
-            .replace("{card}", &config.card_name)
-            .replace("{device}", &config.device_number.to_string())
-            .replace("{sub_device}", &config.sub_device_number.to_string())
-            .replace("{channels}", &config.channels.to_string())
-            .replace("{rate}", &config.rate.to_string())
-            .replace("{fmt}", &config.format.to_string())
-            .replace("{buffer_time}", &buffer_time.to_string())
-            .replace("{period_time}", &period_time.to_string());
-
-        control = CONTROL_TEMPLATE.replace("{card}", &config.card_name);
+        control_card = Some(config.card_name.as_str());
     }
 
     if let Some(config) = capture_config {
-        input_pcm = "\"capture\"".to_string();
-
-        let buffer_time = config.buffer_time_ms * US_PER_MS;
-        let period_time = buffer_time / PERIODS_PER_BUFFER;
+        input_pcm = "capture";
 
-        capture = PLAYBACK_CAPTURE_TEMPLATE
-            .replace("{playback_capture}", "capture")
-            .replace("{dmix_dsnoop}", "dsnoop")
-            .replace("{card}", &config.card_name)
-            .replace("{device}", &config.device_number.to_string())
-            .replace("{sub_device}", &config.sub_device_number.to_string())
-            .replace("{channels}", &config.channels.to_string())
-            .replace("{rate}", &config.rate.to_string())
-            .replace("{fmt}", &config.format.to_string())
-            .replace("{buffer_time}", &buffer_time.to_string())
-            .replace("{period_time}", &period_time.to_string());
+        conf.push("pcm.capture", dmix_dsnoop("dsnoop", config));
 
-        if control.is_empty() {
-            control = CONTROL_TEMPLATE.replace("{card}", &config.card_name);
-        }
+        control_card = control_card.or(Some(config.card_name.as_str()));
     }
 
-    if !converter.is_empty() {
-        config_blocks.push(format!("{converter}\n"));
+    // See:
+    // https://github.com/alsa-project/alsa-lib/blob/master/src/pcm/pcm_asym.c#L20
+    conf.push(
+        "pcm.!default",
+        Compound::new()
+            .with("type", "asym")
+            .with(
+                "capture.pcm",
+                Compound::new()
+                    .with("type", "plug")
+                    .with("slave.pcm", input_pcm),
+            )
+            .with(
+                "playback.pcm",
+                Compound::new()
+                    .with("type", "plug")
+                    .with("slave.pcm", output_pcm),
+            ),
+    );
+
+    if let Some(card) = control_card {
+        conf.push(
+            "ctl.!default",
+            Compound::new().with("type", "hw").with("card", card),
+        );
     }
 
-    if !playback.is_empty() {
-        config_blocks.push(format!("{playback}\n"));
+    conf.to_string()
+}
+
+fn dmix_dsnoop(plugin: &str, config: &ValidConfiguration) -> Compound {
+    // dmix and dsnoop are basically mirror images of each other.
+    // See:
+    // https://github.com/alsa-project/alsa-lib/blob/master/src/conf/pcm/dmix.conf
+    // https://github.com/alsa-project/alsa-lib/blob/master/src/conf/pcm/dsnoop.conf
+    let buffer_time = config.buffer_time_ms * US_PER_MS;
+    let period_time = buffer_time / PERIODS_PER_BUFFER;
+
+    Compound::new()
+        .with("type", plugin)
+        .with("ipc_key", Value::refer("defaults.pcm.ipc_key"))
+        .with("ipc_gid", Value::refer("defaults.pcm.ipc_gid"))
+        .with("ipc_perm", Value::refer("defaults.pcm.ipc_perm"))
+        .with("tstamp_type", Value::refer("defaults.pcm.tstamp_type"))
+        .with(
+            "slave",
+            Compound::new()
+                .with(
+                    "pcm",
+                    Compound::new()
+                        .with("type", "hw")
+                        .with("card", config.card_name.as_str())
+                        .with("device", config.device_number)
+                        .with("subdevice", config.sub_device_number),
+                )
+                .with("channels", config.channels)
+                .with("rate", config.rate)
+                .with("format", config.format.to_string())
+                .with("period_size", Value::Integer(0))
+                .with("buffer_size", Value::Integer(0))
+                .with("periods", Value::Integer(0))
+                .with("buffer_time", buffer_time)
+                .with("period_time", period_time),
+        )
+}
+
+fn backup_asound_conf(now: &str, target: &OutputTarget) -> Option<String> {
+    // The backup is a copy so that the live file
+    // stays in place until the new one is complete.
+    if !target.path.exists() {
+        return None;
     }
 
-    if !capture.is_empty() {
-        config_blocks.push(format!("{capture}\n"));
+    let path = target.backup_path(now);
+
+    if let Err(e) = fs::copy(&target.path, &path) {
+        exit_with_error(
+            format!("Could not back up {}: {e}", target.display()),
+            EXIT_FAILURE,
+        );
     }
 
-    let asym = ASYM_TEMPLATE
-        .replace("{input_pcm}", &input_pcm)
-        .replace("{output_pcm}", &output_pcm);
+    let message = format!("\n{} already exists backing it up to:", target.display()).cyan();
+    println!("{message}");
+    println!("{}", path.cyan());
+
+    Some(path)
+}
+
+fn replace_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // Write to a temporary file in the same directory, fsync it,
+    // give it the permissions and ownership of the file it replaces
+    // and then rename it into place so that the file is never seen
+    // half written, or missing, even if we crash or the disk is full.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let temp_path = dir.join(format!(".{file_name}.awiz{}", process::id()));
+
+    let existing = fs::metadata(path).ok();
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+
+            if let Some(existing) = existing.as_ref() {
+                file.set_permissions(existing.permissions())?;
+
+                // Ownership can only be changed by root, only
+                // bother if it would actually be different.
+                let metadata = file.metadata()?;
 
-    config_blocks.push(asym);
+                if metadata.uid() != existing.uid() || metadata.gid() != existing.gid() {
+                    let result =
+                        unsafe { libc::fchown(file.as_raw_fd(), existing.uid(), existing.gid()) };
 
-    if !control.is_empty() {
-        config_blocks.push(format!("\n{control}"));
+                    if result != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+            }
+
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+
+        return result;
     }
 
-    config_blocks.join("\n")
+    // Make sure the rename itself is on disk.
+    File::open(dir).and_then(|dir| dir.sync_all())
 }
 
-fn backup_asound_conf(now: &str) {
-    let path = BACKUP_FILE_PATH_TEMPLATE.replace("{now}", now);
+fn rollback_asound_conf(target: &OutputTarget, backup: Option<&str>) -> io::Result<()> {
+    // Put things back the way they were before we started.
+    match backup {
+        Some(backup) => {
+            let original = fs::read(backup)?;
+
+            if fs::read(&target.path).ok().as_ref() != Some(&original) {
+                replace_atomically(&target.path, &original)?;
+            }
 
-    if fs::rename(ASOUND_FILE_PATH, path.clone()).is_ok() {
-        let message = format!("\n{ASOUND_FILE_PATH} already exists renaming it to:").cyan();
-        println!("{message}");
-        println!("{}", path.cyan());
+            Ok(())
+        }
+        None => match fs::remove_file(&target.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
     }
 }
 
-fn write_asound_conf(config: String) {
-    match File::create(ASOUND_FILE_PATH).as_mut() {
+fn write_asound_conf(config: String, target: &OutputTarget, backup: Option<&str>) {
+    match replace_atomically(&target.path, config.as_bytes()) {
         Err(e) => {
-            let message = format!("\nError: Could not write {ASOUND_FILE_PATH}: {e}")
+            let message = format!("\nError: Could not write {}: {e}", target.display())
                 .bold()
                 .red();
             eprintln!("{message}");
-            exit(1);
-        }
-        Ok(output) => match write!(output, "{}", &config) {
-            Err(e) => {
-                let message = format!("\nError: Could not write {ASOUND_FILE_PATH}: {e}")
+
+            match rollback_asound_conf(target, backup) {
+                Ok(_) => {
+                    println!(
+                        "{}",
+                        format!("\n{} was left unchanged.", target.display()).cyan()
+                    );
+                }
+                Err(e) => {
+                    let message = format!(
+                        "\nError: Could not roll back {}: {e}\nThe original can be restored with `awiz backups restore latest`.",
+                        target.display()
+                    )
                     .bold()
                     .red();
-                eprintln!("{message}");
-                exit(1);
+                    eprintln!("{message}");
+                }
             }
-            Ok(_) => {
-                println!(
-                    "{}",
-                    format!("\n{ASOUND_FILE_PATH} was written successfully.").cyan()
-                );
 
-                println!(
-                    "{}",
-                    format!("\nYou can revert your system to it's default state by deleting {ASOUND_FILE_PATH},").cyan()
-                );
+            exit(1);
+        }
+        Ok(_) => {
+            println!(
+                "{}",
+                format!("\n{} was written successfully.", target.display()).cyan()
+            );
 
-                println!(
-                    "{}",
-                    "or revert it from the back up, if one was created, if you have any issues with the generated config.".cyan()
-                );
+            println!(
+                "{}",
+                format!(
+                    "\nYou can revert your system to it's default state by deleting {},",
+                    target.display()
+                )
+                .cyan()
+            );
 
-                println!(
-                    "{}",
-                    "\nif you found this utility useful, and feel so inclined, you can buy me a RedBull at:".cyan()
-                );
+            println!(
+                "{}",
+                "or revert it from the back up, if one was created, if you have any issues with the generated config.".cyan()
+            );
 
-                println!(
-                    "{}",
-                    "\nhttps://github.com/sponsors/JasonLG1979".bold().cyan()
-                );
+            println!(
+                "{}",
+                "\nif you found this utility useful, and feel so inclined, you can buy me a RedBull at:".cyan()
+            );
 
-                println!("{}", "\nThanks, and happy listening!!!\n".bold().cyan());
-            }
-        },
+            println!(
+                "{}",
+                "\nhttps://github.com/sponsors/JasonLG1979".bold().cyan()
+            );
+
+            println!("{}", "\nThanks, and happy listening!!!\n".bold().cyan());
+        }
     }
 }
 
-fn main() {
-    let now = &SystemTime::now()
-        .duration_since(UNIX_EPOCH)
+// open_devices is false if the PCMs aren't real sound cards on this
+// machine, the configuration is then only loaded and resolved.
+fn commit_asound_conf(config: String, target: &OutputTarget, now: &str, open_devices: bool) {
+    if let Err(e) = validate_asound_conf(&config, open_devices) {
+        exit_with_error(
+            format!(
+                "The new configuration was rejected, {} was left unchanged.\n{e}",
+                target.display()
+            ),
+            EXIT_INVALID_ASOUND_CONF,
+        );
+    }
+
+    if target.dry_run {
+        println!(
+            "{}",
+            format!(
+                "\nDry run, nothing was written. {} would contain:\n",
+                target.display()
+            )
+            .cyan()
+        );
+
+        match target.config_out.as_ref() {
+            Some(mut out) => {
+                let _ = writeln!(out, "{config}");
+            }
+            None => println!("{config}"),
+        }
+
+        return;
+    }
+
+    let backup = backup_asound_conf(now, target);
+
+    write_asound_conf(config, target, backup.as_deref());
+}
+
+#[derive(Debug)]
+struct UnattendedRun {
+    pub playback: Option<ConfigRequest>,
+    pub capture: Option<ConfigRequest>,
+    pub converter: Option<String>,
+    pub policy: Option<ScoringPolicy>,
+    pub yes: bool,
+    // Only show what would change, nothing is written.
+    pub diff: bool,
+    pub save_preset: Option<PathBuf>,
+    pub probe: ProbeOptions,
+}
+
+fn run_non_interactive(
+    run: UnattendedRun,
+    backend: &Arc<dyn Backend>,
+    target: &OutputTarget,
+    now: &str,
+) {
+    if backend.is_hardware() {
+        conflict_check();
+    }
+
+    println!(
+        "{}",
+        "\nRetrieving PCM parameters. This may take a moment…".cyan()
+    );
+
+    let probed = get_cached_pcms(backend, &run.probe);
+
+    show_summary(&probed);
+
+    let ProbedPcms {
+        playback: playback_pcms,
+        capture: capture_pcms,
+        ..
+    } = probed;
+
+    let converters = get_rate_converters();
+
+    let policy = run.policy.as_ref();
+
+    let mut configs = [
+        (run.playback, &playback_pcms, Direction::Playback),
+        (run.capture, &capture_pcms, Direction::Capture),
+    ]
+    .into_iter()
+    .map(|(request, pcms, direction)| {
+        let request = request?;
+
+        if matches!(request.pcm, PcmSelector::Any) && pcms.is_empty() {
+            println!(
+                "{}",
+                format!("\nThere are no available {direction:?} PCMs…").cyan()
+            );
+
+            return None;
+        }
+
+        Some(
+            select_configuration(pcms, &request, direction, policy)
+                .unwrap_or_else(|(code, e)| exit_with_error(e, code)),
+        )
+    });
+
+    let playback_config = configs.next().flatten();
+    let capture_config = configs.next().flatten();
+
+    if playback_config.is_none() && capture_config.is_none() {
+        exit_with_error(
+            "There are no available PCMs to configure.",
+            EXIT_PCM_NOT_FOUND,
+        );
+    }
+
+    let converter = select_converter(&converters, run.converter.as_deref())
+        .unwrap_or_else(|e| exit_with_error(e, EXIT_INVALID_CONVERTER));
+
+    for config in playback_config.iter().chain(capture_config.iter()) {
+        show_configuration(config);
+    }
+
+    let config = build_asound_conf(
+        playback_config.as_ref(),
+        capture_config.as_ref(),
+        converter.as_deref(),
+    );
+
+    show_changes(target, &config);
+
+    if run.diff {
+        return;
+    }
+
+    if !run.yes && !target.dry_run {
+        let confirm = user_input(format!(
+            "Please Enter \"OK\" to commit your choices to {}: ",
+            target.display()
+        ))
+        .to_lowercase();
+
+        if confirm != "ok" {
+            println!("{}", "\nYou did not enter \"OK\".".cyan());
+
+            println!(
+                "{}",
+                "\nNo files or configurations have been changed.".cyan()
+            );
+
+            exit(EXIT_FAILURE);
+        }
+    }
+
+    commit_asound_conf(config, target, now, backend.is_hardware());
+
+    // Only once it's known to be good, a fleet would replay it.
+    if let Some(path) = run.save_preset.as_deref() {
+        save_preset_file(
+            path,
+            playback_config.as_ref(),
+            capture_config.as_ref(),
+            converter.as_deref(),
+        );
+    }
+}
+
+fn preset_run(path: &Path, yes: bool, diff: bool, probe: ProbeOptions) -> UnattendedRun {
+    let preset = Preset::load(path).unwrap_or_else(|e| exit_with_error(e, EXIT_INVALID_PRESET));
+
+    let playback_request = preset
+        .playback_request()
+        .unwrap_or_else(|e| exit_with_error(e, EXIT_INVALID_PRESET));
+
+    let capture_request = preset
+        .capture_request()
+        .unwrap_or_else(|e| exit_with_error(e, EXIT_INVALID_PRESET));
+
+    UnattendedRun {
+        playback: playback_request,
+        capture: capture_request,
+        converter: preset.converter,
+        policy: None,
+        yes,
+        diff,
+        save_preset: None,
+        probe,
+    }
+}
+
+fn save_preset_file(
+    path: &Path,
+    playback_config: Option<&ValidConfiguration>,
+    capture_config: Option<&ValidConfiguration>,
+    converter: Option<&str>,
+) {
+    match Preset::new(playback_config, capture_config, converter).save(path) {
+        Err(e) => exit_with_error(e, EXIT_FAILURE),
+        Ok(_) => {
+            println!(
+                "{}",
+                format!("\nYour choices were saved to {}.", path.display()).cyan()
+            );
+        }
+    }
+}
+
+fn run_probe(
+    backend: &Arc<dyn Backend>,
+    json: bool,
+    yaml: bool,
+    record: Option<&Path>,
+    bench: bool,
+    explain: bool,
+    options: ProbeOptions,
+) {
+    if !json && !yaml {
+        println!(
+            "{}",
+            "\nRetrieving PCM parameters. This may take a moment…".cyan()
+        );
+    }
+
+    let recorder = record.map(|_| Arc::new(SessionBackend::record(backend.clone())));
+
+    let backend = match &recorder {
+        Some(recorder) => recorder.clone() as Arc<dyn Backend>,
+        None => backend.clone(),
+    };
+
+    let start = Instant::now();
+
+    let options = ProbeOptions {
+        explain,
+        quiet: json || yaml,
+        ..options
+    };
+
+    // Recording, benchmarking and explaining are all about actually probing.
+    let probed = if record.is_some() || bench || explain {
+        get_pcms(&backend, &options)
+    } else {
+        get_cached_pcms(&backend, &options)
+    };
+
+    let probe_time = start.elapsed();
+
+    if let (Some(recorder), Some(path)) = (recorder, record) {
+        // The wizard also asks for the Buffer Times of the chosen
+        // Configuration, so a replay needs them for all of them.
+        if !json && !yaml {
+            println!(
+                "{}",
+                "\nRetrieving Buffer parameters. This may take a moment…".cyan()
+            );
+        }
+
+        for pcm in probed.playback.iter().chain(probed.capture.iter()) {
+            for config in &pcm.valid_configurations {
+                config.get_buffer_times_ms();
+            }
+        }
+
+        recorder
+            .save(path)
+            .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+
+        if !json && !yaml {
+            println!(
+                "{}",
+                format!(
+                    "\nThe probe session was recorded to {}, replay it with `awiz --replay {}`.",
+                    path.display(),
+                    path.display()
+                )
+                .cyan()
+            );
+        }
+    }
+
+    if json || yaml {
+        let report = ProbeReport::new(&probed);
+
+        let output = if json {
+            report.to_json()
+        } else {
+            report.to_yaml()
+        };
+
+        match output {
+            Err(e) => exit_with_error(e, EXIT_FAILURE),
+            Ok(output) => println!("{}", output.trim_end()),
+        }
+    } else {
+        for (pcms, direction) in [
+            (&probed.playback, Direction::Playback),
+            (&probed.capture, Direction::Capture),
+        ] {
+            if pcms.is_empty() {
+                println!(
+                    "{}",
+                    format!("\nThere are no available {direction:?} PCMs…").cyan()
+                );
+            } else {
+                show_pcms(pcms);
+            }
+        }
+
+        if explain {
+            show_explanation(&probed);
+        }
+
+        show_summary(&probed);
+    }
+
+    if bench {
+        let pcms: Vec<AlsaPcm> = probed.playback.into_iter().chain(probed.capture).collect();
+
+        show_bench(&pcms, probe_time);
+    }
+}
+
+fn run_inspect(path: &Path, save_preset: Option<&Path>) {
+    let conf = load_conf(path).unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+
+    println!("{}", format!("\nWhat {} does:", path.display()).cyan());
+
+    show_inspection(&conf);
+
+    if let Some(preset_path) = save_preset {
+        let preset = to_preset(&conf).unwrap_or_else(|e| {
+            exit_with_error(
+                format!("Could not import {}: {e}", path.display()),
+                EXIT_FAILURE,
+            )
+        });
+
+        preset
+            .save(preset_path)
+            .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+
+        println!(
+            "{}",
+            format!(
+                "\n{} was imported into {}, apply it with `awiz apply {}`.",
+                path.display(),
+                preset_path.display(),
+                preset_path.display()
+            )
+            .cyan()
+        );
+    }
+}
+
+fn run_test(action: &TestAction, target: &OutputTarget) {
+    match action {
+        TestAction::Playback {
+            signal,
+            channel,
+            duration,
+            loops,
+        } => test_speakers(
+            &target.path,
+            signal.unwrap_or_default(),
+            *channel,
+            Duration::from_secs(duration.unwrap_or(DEFAULT_TEST_DURATION_SECS)),
+            loops.unwrap_or(1),
+        ),
+        TestAction::Capture { duration } => test_microphones(
+            &target.path,
+            Duration::from_secs(duration.unwrap_or(DEFAULT_CAPTURE_TEST_DURATION_SECS)),
+        ),
+        TestAction::Loopback => test_loopback(&target.path),
+    }
+    .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+}
+
+fn run_backups(action: &BackupsAction, target: &OutputTarget, now: &str) {
+    match action {
+        BackupsAction::List => show_backups(target, &find_backups(target)),
+        BackupsAction::Restore { id } => {
+            let backup =
+                find_backup(target, id).unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+
+            if target.dry_run {
+                println!(
+                    "{}",
+                    format!(
+                        "\nDry run, nothing was changed. {} would be restored from {}.",
+                        target.display(),
+                        backup.path.display()
+                    )
+                    .cyan()
+                );
+
+                return;
+            }
+
+            permission_check(now, target);
+
+            if target.path.exists() {
+                let path = target.backup_path(now);
+
+                if let Err(e) = fs::copy(&target.path, &path) {
+                    exit_with_error(
+                        format!("Could not back up {}: {e}", target.display()),
+                        EXIT_FAILURE,
+                    );
+                }
+
+                println!(
+                    "{}",
+                    format!("\n{} was backed up to:", target.display()).cyan()
+                );
+
+                println!("{}", path.cyan());
+            }
+
+            restore_backup(&backup, target).unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+
+            println!(
+                "{}",
+                format!(
+                    "\n{} was restored from {} ({}).",
+                    target.display(),
+                    backup.path.display(),
+                    backup.timestamp()
+                )
+                .cyan()
+            );
+        }
+        BackupsAction::Prune { keep } => {
+            if !target.dry_run {
+                permission_check(now, target);
+            }
+
+            let backups = find_backups(target);
+
+            if backups.len() <= *keep {
+                println!(
+                    "{}",
+                    format!(
+                        "\nThere are {} backups of {}, nothing to prune.",
+                        backups.len(),
+                        target.display()
+                    )
+                    .cyan()
+                );
+
+                return;
+            }
+
+            let mut failed = false;
+
+            for result in prune_backups(&backups, *keep, target.dry_run) {
+                match result {
+                    Ok(path) => {
+                        let verb = if target.dry_run {
+                            "Would remove"
+                        } else {
+                            "Removed"
+                        };
+
+                        println!("{}", format!("\n{verb} {}", path.display()).cyan());
+                    }
+                    Err(e) => {
+                        failed = true;
+
+                        eprintln!("{}", format!("\nError: {e}").bold().red());
+                    }
+                }
+            }
+
+            if failed {
+                exit(EXIT_FAILURE);
+            }
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let backend: &Arc<dyn Backend> = &match (cli.simulate.as_deref(), cli.replay.as_deref()) {
+        (Some(path), _) => Arc::new(
+            SimulatedBackend::load(path).unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE)),
+        ),
+        (None, Some(path)) => Arc::new(
+            SessionBackend::replay(path).unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE)),
+        ),
+        (None, None) => Arc::new(HardwareBackend),
+    };
+
+    if let Some(Command::Probe {
+        json,
+        yaml,
+        record,
+        bench,
+        explain,
+    }) = &cli.command
+    {
+        run_probe(
+            backend,
+            *json,
+            *yaml,
+            record.as_deref(),
+            *bench,
+            *explain,
+            cli.probe_options(),
+        );
+
+        return;
+    }
+
+    let now = &SystemTime::now()
+        .duration_since(UNIX_EPOCH)
         .unwrap_or(Duration::ZERO)
         .as_millis()
         .to_string();
 
-    permission_check(now);
+    // A configuration for simulated or replayed sound cards has no business
+    // in the real configuration file, it's only written to --output.
+    let dry_run = cli.dry_run || (!backend.is_hardware() && cli.output.is_none());
+
+    let target = &mut OutputTarget::new(cli.output.as_deref(), cli.user, dry_run)
+        .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+
+    if let Some(Command::Backups { action }) = &cli.command {
+        run_backups(action, target, now);
+
+        return;
+    }
+
+    if let Some(Command::Inspect { file, save_preset }) = &cli.command {
+        run_inspect(
+            file.as_deref().unwrap_or(&target.path),
+            save_preset.as_deref(),
+        );
+
+        return;
+    }
+
+    if let Some(Command::Test { action }) = &cli.command {
+        run_test(action, target);
+
+        return;
+    }
+
+    if let Some(Command::Diff { preset }) = &cli.command {
+        run_non_interactive(
+            preset_run(preset, false, true, cli.probe_options()),
+            backend,
+            target,
+            now,
+        );
+
+        return;
+    }
+
+    if target.dry_run {
+        target.keep_stdout_for_config();
+    } else {
+        permission_check(now, target);
+    }
+
+    if let Some(Command::Apply { preset, yes }) = &cli.command {
+        run_non_interactive(
+            preset_run(preset, *yes, false, cli.probe_options()),
+            backend,
+            target,
+            now,
+        );
+
+        return;
+    }
+
+    if !cli.is_interactive() {
+        run_non_interactive(
+            UnattendedRun {
+                playback: cli.playback_request(),
+                capture: cli.capture_request(),
+                converter: cli.converter.clone(),
+                policy: cli.scoring_policy(),
+                yes: cli.yes,
+                diff: false,
+                save_preset: cli.save_preset.clone(),
+                probe: cli.probe_options(),
+            },
+            backend,
+            target,
+            now,
+        );
+
+        return;
+    }
 
     println!(
         "{} {} {}",
         "\nThis utility will backup".cyan(),
-        ASOUND_FILE_PATH.bold().italic().cyan(),
+        target.display().bold().italic().cyan(),
         "if it already exists,".cyan()
     );
 
     println!(
         "{} {} {}",
         "and generate a new".cyan(),
-        ASOUND_FILE_PATH.bold().italic().cyan(),
+        target.display().bold().italic().cyan(),
         "based on your choices.".cyan()
     );
 
@@ -1452,7 +2913,9 @@ fn main() {
         exit(0);
     }
 
-    conflict_check();
+    if backend.is_hardware() {
+        conflict_check();
+    }
 
     println!(
         "{}",
@@ -1468,6 +2931,13 @@ fn main() {
             .bold()
     );
 
+    println!(
+        "{}",
+        "Run with --wait-busy to wait for them to be released instead."
+            .cyan()
+            .bold()
+    );
+
     let enter = user_input("Please Press Enter to Continue");
 
     if !enter.is_empty() {
@@ -1479,7 +2949,15 @@ fn main() {
         "\nRetrieving PCM parameters. This may take a moment…".cyan()
     );
 
-    let (playback_pcms, capture_pcms) = get_pcms();
+    let probed = get_cached_pcms(backend, &cli.probe_options());
+
+    show_summary(&probed);
+
+    let ProbedPcms {
+        playback: playback_pcms,
+        capture: capture_pcms,
+        ..
+    } = probed;
 
     let converters = get_rate_converters();
 
@@ -1499,7 +2977,8 @@ fn main() {
                 let confirm = user_input("If this is acceptable Please Enter \"OK\" to Continue: ")
                     .to_lowercase();
 
-                if confirm != "ok" {
+                // There is nothing to hear with simulated or replayed sound cards.
+                if confirm != "ok" || (backend.is_hardware() && !audition(&config)) {
                     None
                 } else {
                     Some(config)
@@ -1553,10 +3032,20 @@ fn main() {
             None
         };
 
-        let confirm = user_input(format!(
-            "Please Enter \"OK\" to commit your choices to {ASOUND_FILE_PATH}: "
-        ))
-        .to_lowercase();
+        let config =
+            build_asound_conf(playback_config.as_ref(), capture_config.as_ref(), converter);
+
+        show_changes(target, &config);
+
+        let confirm = if target.dry_run {
+            "ok".to_string()
+        } else {
+            user_input(format!(
+                "Please Enter \"OK\" to commit your choices to {}: ",
+                target.display()
+            ))
+            .to_lowercase()
+        };
 
         if confirm != "ok" {
             println!("{}", "\nYou did not enter \"OK\".".cyan());
@@ -1569,12 +3058,635 @@ fn main() {
             exit(0);
         }
 
-        backup_asound_conf(now);
+        commit_asound_conf(config, target, now, backend.is_hardware());
 
-        write_asound_conf(build_asound_conf(
-            playback_config,
-            capture_config,
-            converter,
-        ));
+        // Only once it's known to be good, a fleet would replay it.
+        if let Some(path) = cli.save_preset.as_deref() {
+            save_preset_file(
+                path,
+                playback_config.as_ref(),
+                capture_config.as_ref(),
+                converter,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use conf::parse;
+
+    const DEVICES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/devices.toml");
+    const SESSION: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/session.json");
+    const SESSION_REPORT: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/examples/session-report.json");
+
+    fn options() -> ProbeOptions {
+        ProbeOptions {
+            reprobe: true,
+            timeout: Duration::from_secs(30),
+            wait_busy: false,
+            explain: false,
+            quiet: true,
+            dry_run: true,
+        }
+    }
+
+    fn probe_simulated() -> ProbedPcms {
+        let backend: Arc<dyn Backend> =
+            Arc::new(SimulatedBackend::load(Path::new(DEVICES)).unwrap());
+
+        get_cached_pcms(&backend, &options())
+    }
+
+    fn probe_replayed() -> ProbedPcms {
+        let backend: Arc<dyn Backend> =
+            Arc::new(SessionBackend::replay(Path::new(SESSION)).unwrap());
+
+        get_cached_pcms(&backend, &options())
+    }
+
+    // The cards are probed in parallel, so the PCMs are in no particular order.
+    fn normalized(mut report: ProbeReport) -> String {
+        report.awiz_version = String::new();
+        report.playback.sort_by(|a, b| a.name.cmp(&b.name));
+        report.capture.sort_by(|a, b| a.name.cmp(&b.name));
+        report.ignored.sort_by(|a, b| a.name.cmp(&b.name));
+
+        report.to_json().unwrap()
+    }
+
+    fn find<'a>(pcms: &'a [AlsaPcm], name: &str) -> &'a AlsaPcm {
+        pcms.iter()
+            .find(|pcm| pcm.name == name)
+            .unwrap_or_else(|| panic!("{name} was not accepted"))
+    }
+
+    #[test]
+    fn simulated_pcms_by_direction() {
+        let probed = probe_simulated();
+
+        // The cards are probed in parallel, so in no particular order.
+        let names = |pcms: &[AlsaPcm]| {
+            let mut names: Vec<String> = pcms.iter().map(|pcm| pcm.name.clone()).collect();
+
+            names.sort();
+
+            names
+        };
+
+        assert_eq!(
+            names(&probed.playback),
+            ["hw:CARD=DAC,DEV=0", "hw:CARD=HDMI,DEV=3"]
+        );
+
+        assert_eq!(
+            names(&probed.capture),
+            ["hw:CARD=Loopback,DEV=0", "hw:CARD=Mic,DEV=0"]
+        );
+
+        assert!(!probed.cancelled);
+    }
+
+    #[test]
+    fn simulated_rejections() {
+        let probed = probe_simulated();
+
+        let rejection = |name: &str| {
+            probed
+                .rejected
+                .iter()
+                .find(|rejected| rejected.name == name)
+                .map(|rejected| rejected.rejection.clone())
+        };
+
+        assert_eq!(
+            rejection("hw:CARD=Headset,DEV=0"),
+            Some(Rejection::Busy {
+                holders: vec![Holder {
+                    pid: 4242,
+                    command: "mpd".to_string()
+                }]
+            })
+        );
+
+        assert_eq!(
+            rejection("hw:CARD=SPDIF,DEV=0"),
+            Some(Rejection::NoMixableFormats)
+        );
+
+        assert_eq!(probed.rejected.len(), 2);
+    }
+
+    #[test]
+    fn simulated_refused_combinations_are_left_out() {
+        let probed = probe_simulated();
+        let dac = find(&probed.playback, "hw:CARD=DAC,DEV=0");
+
+        assert_eq!(dac.valid_configurations.len(), 16);
+
+        assert!(!dac.valid_configurations.iter().any(|config| {
+            config.format == Format::s32() && [176400, 192000].contains(&config.rate)
+        }));
+
+        assert!(dac
+            .valid_configurations
+            .iter()
+            .any(|config| config.format == Format::s32() && config.rate == 96000));
+    }
+
+    #[test]
+    fn simulated_lies_are_caught() {
+        let probed = probe_simulated();
+        let hdmi = find(&probed.playback, "hw:CARD=HDMI,DEV=3");
+
+        // It claims 44100 Hz but runs at 48000 Hz, and turns 6 channels into 8.
+        assert_eq!(hdmi.rates, [32000, 48000]);
+        assert_eq!(hdmi.channels, [2, 3, 4, 5, 7, 8]);
+
+        assert!(!hdmi
+            .valid_configurations
+            .iter()
+            .any(|config| config.rate == 44100 || config.channels == 6));
+    }
+
+    #[test]
+    fn simulated_buffer_times() {
+        let probed = probe_simulated();
+
+        let dac = find(&probed.playback, "hw:CARD=DAC,DEV=0");
+
+        assert_eq!(
+            dac.valid_configurations[0].get_buffer_times_ms(),
+            (2..=400).collect::<Vec<u32>>()
+        );
+
+        let hdmi = find(&probed.playback, "hw:CARD=HDMI,DEV=3");
+
+        assert_eq!(
+            hdmi.valid_configurations[0].get_buffer_times_ms(),
+            (5..=250).step_by(5).collect::<Vec<u32>>()
+        );
+    }
+
+    #[test]
+    fn simulated_continuous_rates() {
+        let probed = probe_simulated();
+        let loopback = find(&probed.capture, "hw:CARD=Loopback,DEV=0");
+
+        assert_eq!(loopback.rate_range, Some(8000..=192000));
+        assert_eq!(loopback.formats, [Format::s16(), Format::s32()]);
+        assert_eq!(loopback.channels, (1..=32).collect::<Vec<u32>>());
+    }
+
+    // What probing the devices described in devices finds, the rejections
+    // sorted, and how long it took, Ctrl+C'ed after cancel_after if set.
+    fn probe_slow(
+        devices: &str,
+        timeout: Duration,
+        cancel_after: Option<Duration>,
+    ) -> (ProbedPcms, Duration) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+
+        file.write_all(devices.as_bytes()).unwrap();
+
+        let backend: Arc<dyn Backend> = Arc::new(SimulatedBackend::load(file.path()).unwrap());
+
+        // Not INTERRUPTED, the other tests probe at the same time.
+        let interrupted: &'static AtomicBool = Box::leak(Box::new(AtomicBool::new(false)));
+
+        let mut thread_manager = ThreadManager::with_interrupt(
+            backend.clone(),
+            ProbeOptions {
+                timeout,
+                ..options()
+            },
+            interrupted,
+        );
+
+        for hint in backend.hints() {
+            thread_manager.add_job(&hint.name.unwrap(), hint.direction.unwrap());
+        }
+
+        if let Some(cancel_after) = cancel_after {
+            thread::spawn(move || {
+                thread::sleep(cancel_after);
+                interrupted.store(true, AtomicOrdering::SeqCst);
+            });
+        }
+
+        let started = Instant::now();
+        let mut probed = thread_manager.get_pcms();
+
+        probed.rejected.sort_by(|a, b| a.name.cmp(&b.name));
+
+        (probed, started.elapsed())
+    }
+
+    fn rejections(probed: &ProbedPcms) -> Vec<(&str, Rejection)> {
+        probed
+            .rejected
+            .iter()
+            .map(|rejected| (rejected.name.as_str(), rejected.rejection.clone()))
+            .collect()
+    }
+
+    const DAC: &str = r#"
+[[pcm]]
+name = "hw:CARD=DAC,DEV=0"
+direction = "playback"
+formats = ["S16_LE"]
+rates = [48000]
+channels = [2]
+"#;
+
+    // Each of the two PCMs of Slow has to wait for the other.
+    const SLOW: &str = r#"
+[[pcm]]
+name = "hw:CARD=Slow,DEV=0"
+direction = "playback"
+formats = ["S16_LE"]
+rates = [48000]
+channels = [2]
+delay_ms = 50
+
+[[pcm]]
+name = "hw:CARD=Slow,DEV=1"
+direction = "playback"
+formats = ["S16_LE"]
+rates = [48000]
+channels = [2]
+"#;
+
+    const HUNG: &str = r#"
+[[pcm]]
+name = "hw:CARD=Hung,DEV=0"
+direction = "playback"
+formats = ["S16_LE"]
+rates = [48000]
+channels = [2]
+delay_ms = 60000
+"#;
+
+    #[test]
+    fn hung_cards_time_out() {
+        let (probed, elapsed) =
+            probe_slow(&[DAC, SLOW, HUNG].concat(), Duration::from_secs(1), None);
+
+        assert_eq!(
+            rejections(&probed),
+            [("hw:CARD=Hung,DEV=0", Rejection::TimedOut { seconds: 1 })]
+        );
+        assert_eq!(probed.playback.len(), 3);
+        assert!(!probed.cancelled);
+        assert!(elapsed < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn cancelled_pcms_are_reported() {
+        let (probed, elapsed) = probe_slow(
+            SLOW,
+            Duration::from_secs(30),
+            Some(Duration::from_millis(20)),
+        );
+
+        // The one being probed and the one after it, that is
+        // skipped, without anything having to be abandoned.
+        assert_eq!(
+            rejections(&probed),
+            [
+                ("hw:CARD=Slow,DEV=0", Rejection::Cancelled),
+                ("hw:CARD=Slow,DEV=1", Rejection::Cancelled)
+            ]
+        );
+        assert!(probed.cancelled);
+        assert!(elapsed < CANCEL_GRACE_PERIOD);
+    }
+
+    #[test]
+    fn hung_cards_are_abandoned_when_cancelled() {
+        let (probed, elapsed) = probe_slow(
+            &[SLOW, HUNG].concat(),
+            Duration::from_secs(30),
+            Some(Duration::from_millis(20)),
+        );
+
+        assert_eq!(
+            rejections(&probed),
+            [
+                ("hw:CARD=Hung,DEV=0", Rejection::Cancelled),
+                ("hw:CARD=Slow,DEV=0", Rejection::Cancelled),
+                ("hw:CARD=Slow,DEV=1", Rejection::Cancelled)
+            ]
+        );
+        assert!(probed.cancelled);
+        // Abandoned after the grace period, not the timeout.
+        assert!(elapsed >= CANCEL_GRACE_PERIOD);
+        assert!(elapsed < CANCEL_GRACE_PERIOD + Duration::from_secs(1));
+    }
+
+    // The temporary files replace_atomically left behind in dir.
+    fn temp_files(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.contains(".awiz"))
+            .collect()
+    }
+
+    #[test]
+    fn replacing_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("asound.conf");
+
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        replace_atomically(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        assert!(temp_files(dir.path()).is_empty());
+
+        // And creates files that don't exist yet.
+        let path = dir.path().join("new.conf");
+
+        replace_atomically(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+    }
+
+    #[test]
+    fn failed_replacements_leave_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // The temporary file is written but can't be renamed over a directory.
+        let path = dir.path().join("asound.conf");
+
+        fs::create_dir(&path).unwrap();
+
+        assert!(replace_atomically(&path, b"new").is_err());
+        assert!(path.is_dir());
+        assert!(temp_files(dir.path()).is_empty());
+
+        // The temporary file can't even be created.
+        assert!(replace_atomically(&dir.path().join("missing/asound.conf"), b"new").is_err());
+        assert!(temp_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn rollback_restores_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let target =
+            OutputTarget::new(Some(&dir.path().join("asound.conf")), false, false).unwrap();
+        let backup = dir.path().join("asound.conf.bak1000");
+
+        // Not necessarily valid UTF-8.
+        let original = b"pcm.!default \"hw:0\"\n\xff\xfe\n".to_vec();
+
+        fs::write(&backup, &original).unwrap();
+        fs::write(&target.path, "half written").unwrap();
+
+        rollback_asound_conf(&target, backup.to_str()).unwrap();
+
+        assert_eq!(fs::read(&target.path).unwrap(), original);
+        assert_eq!(fs::read(&backup).unwrap(), original);
+
+        // A backup that can't be read is an error, the target is left alone.
+        fs::write(&target.path, "half written").unwrap();
+
+        assert!(rollback_asound_conf(&target, Some("/nonexistent/asound.conf.bak1")).is_err());
+        assert_eq!(fs::read(&target.path).unwrap(), b"half written");
+    }
+
+    #[test]
+    fn rollback_removes_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let target =
+            OutputTarget::new(Some(&dir.path().join("asound.conf")), false, false).unwrap();
+
+        fs::write(&target.path, "new").unwrap();
+
+        rollback_asound_conf(&target, None).unwrap();
+
+        assert!(!target.path.exists());
+
+        // Even if it never got written.
+        rollback_asound_conf(&target, None).unwrap();
+    }
+
+    #[test]
+    fn replayed_session_matches_its_report() {
+        let probed = probe_replayed();
+
+        let recorded: ProbeReport =
+            serde_json::from_str(&fs::read_to_string(SESSION_REPORT).unwrap()).unwrap();
+
+        assert_eq!(normalized(ProbeReport::new(&probed)), normalized(recorded));
+    }
+
+    #[test]
+    fn replayed_buffer_times() {
+        let probed = probe_replayed();
+
+        for pcm in probed.playback.iter().chain(&probed.capture) {
+            for config in &pcm.valid_configurations {
+                let expected: Vec<u32> = match pcm.card_name.as_str() {
+                    "HDMI" => (5..=50).step_by(5).collect(),
+                    _ => (10..=100).step_by(10).collect(),
+                };
+
+                assert_eq!(config.get_buffer_times_ms(), expected, "{}", pcm.name);
+            }
+        }
+    }
+
+    #[test]
+    fn imported_card_index_is_applied() {
+        let probed = probe_simulated();
+
+        let conf = parse(
+            r#"pcm.!default { type plug slave.pcm { type dmix slave { pcm "hw:1,0" rate 48000 } } }"#,
+        )
+        .unwrap();
+
+        let preset =
+            inspect::to_preset_with(&conf, |index| (index == 1).then(|| "DAC".to_string()))
+                .unwrap();
+
+        let request = preset.playback_request().unwrap().unwrap();
+
+        assert!(request
+            .pcm
+            .matches(find(&probed.playback, "hw:CARD=DAC,DEV=0")));
+        assert!(!request
+            .pcm
+            .matches(find(&probed.playback, "hw:CARD=HDMI,DEV=3")));
+        assert_eq!(request.rate, Some(48000));
+    }
+
+    fn simulated_config(name: &str, direction: Direction) -> ValidConfiguration {
+        let probed = probe_simulated();
+
+        let pcms = match direction {
+            Direction::Playback => &probed.playback,
+            Direction::Capture => &probed.capture,
+        };
+
+        let mut config = find(pcms, name).valid_configurations[0].clone();
+
+        config.buffer_time_ms = 50;
+
+        config
+    }
+
+    #[test]
+    fn playback_only_conf() {
+        let mut playback = simulated_config("hw:CARD=DAC,DEV=0", Direction::Playback);
+
+        playback.card_name = "My \"Best\" DAC".to_string();
+
+        let conf = build_asound_conf(Some(&playback), None, Some("speexrate_best"));
+
+        assert_eq!(
+            conf,
+            r#"defaults.pcm.rate_converter speexrate_best
+
+pcm.playback {
+    type dmix
+    ipc_key {
+        @func refer
+        name defaults.pcm.ipc_key
+    }
+    ipc_gid {
+        @func refer
+        name defaults.pcm.ipc_gid
+    }
+    ipc_perm {
+        @func refer
+        name defaults.pcm.ipc_perm
+    }
+    tstamp_type {
+        @func refer
+        name defaults.pcm.tstamp_type
+    }
+    slave {
+        pcm {
+            type hw
+            card "My \"Best\" DAC"
+            device 0
+            subdevice 0
+        }
+        channels 2
+        rate 44100
+        format S16_LE
+        period_size 0
+        buffer_size 0
+        periods 0
+        buffer_time 50000
+        period_time 10000
+    }
+}
+
+pcm.!default {
+    type asym
+    capture.pcm {
+        type plug
+        slave.pcm null
+    }
+    playback.pcm {
+        type plug
+        slave.pcm playback
+    }
+}
+
+ctl.!default {
+    type hw
+    card "My \"Best\" DAC"
+}"#
+        );
+    }
+
+    #[test]
+    fn capture_only_conf() {
+        let mut capture = simulated_config("hw:CARD=Mic,DEV=0", Direction::Capture);
+
+        capture.card_name = "1st\\Mic\tx".to_string();
+
+        let conf = parse(&build_asound_conf(None, Some(&capture), None)).unwrap();
+
+        assert_eq!(conf.get_str("pcm.capture.type").as_deref(), Some("dsnoop"));
+        assert_eq!(
+            conf.get_str("pcm.capture.slave.pcm.card").as_deref(),
+            Some("1st\\Mic\tx")
+        );
+        assert_eq!(conf.get("pcm.playback"), None);
+        assert_eq!(
+            conf.get_str("pcm.default.capture.pcm.slave.pcm").as_deref(),
+            Some("capture")
+        );
+        assert_eq!(
+            conf.get_str("pcm.default.playback.pcm.slave.pcm")
+                .as_deref(),
+            Some("null")
+        );
+        assert_eq!(
+            conf.get_str("ctl.default.card").as_deref(),
+            Some("1st\\Mic\tx")
+        );
+        assert_eq!(conf.get("defaults"), None);
+    }
+
+    #[test]
+    fn playback_and_capture_conf() {
+        let playback = simulated_config("hw:CARD=DAC,DEV=0", Direction::Playback);
+        let capture = simulated_config("hw:CARD=Mic,DEV=0", Direction::Capture);
+
+        let conf = parse(&build_asound_conf(Some(&playback), Some(&capture), None)).unwrap();
+
+        assert_eq!(conf.ids("pcm"), ["playback", "capture", "default"]);
+        assert_eq!(
+            conf.get_str("pcm.playback.slave.pcm.card").as_deref(),
+            Some("DAC")
+        );
+        assert_eq!(
+            conf.get_str("pcm.capture.slave.pcm.card").as_deref(),
+            Some("Mic")
+        );
+        assert_eq!(
+            conf.get_str("pcm.default.playback.pcm.slave.pcm")
+                .as_deref(),
+            Some("playback")
+        );
+        assert_eq!(
+            conf.get_str("pcm.default.capture.pcm.slave.pcm").as_deref(),
+            Some("capture")
+        );
+        // The control follows playback.
+        assert_eq!(conf.get_str("ctl.default.card").as_deref(), Some("DAC"));
+    }
+
+    #[test]
+    fn generated_confs_are_valid() {
+        let playback = simulated_config("hw:CARD=DAC,DEV=0", Direction::Playback);
+        let capture = simulated_config("hw:CARD=Mic,DEV=0", Direction::Capture);
+
+        for (playback, capture) in [
+            (Some(&playback), Some(&capture)),
+            (Some(&playback), None),
+            (None, Some(&capture)),
+        ] {
+            let conf = build_asound_conf(playback, capture, Some("speexrate_best"));
+
+            assert_eq!(validate_asound_conf(&conf, false), Ok(()), "{conf}");
+        }
     }
 }
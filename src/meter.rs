@@ -0,0 +1,339 @@
+use std::{
+    io::{stdout, Write},
+    path::Path,
+    sync::atomic::Ordering as AtomicOrdering,
+    time::{Duration, Instant},
+};
+
+use alsa::Direction;
+
+use colored::*;
+
+use comfy_table::{
+    modifiers::UTF8_SOLID_INNER_BORDERS, presets::UTF8_FULL, Attribute, Cell, ContentArrangement,
+    Table,
+};
+
+use crate::{on_interrupt, stream::Stream, INTERRUPTED};
+
+// How often the meters are updated.
+const METER_INTERVAL: Duration = Duration::from_millis(100);
+
+// A sample this close to full scale is counted as clipped.
+const CLIP_LEVEL: f32 = 0.999;
+
+// The range the meters show, anything quieter is an empty meter.
+const METER_FLOOR_DBFS: f64 = -60.0;
+const METER_WIDTH: usize = 30;
+
+#[derive(Debug, Default, Clone)]
+struct Levels {
+    peak: f32,
+    sum_of_squares: f64,
+    samples: u64,
+    clipped: u64,
+    all_zero: bool,
+}
+
+impl Levels {
+    fn new() -> Self {
+        Self {
+            all_zero: true,
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, sample: f32) {
+        let level = sample.abs();
+
+        self.peak = self.peak.max(level);
+        self.sum_of_squares += (sample as f64).powi(2);
+        self.samples += 1;
+
+        if level >= CLIP_LEVEL {
+            self.clipped += 1;
+        }
+
+        if sample != 0.0 {
+            self.all_zero = false;
+        }
+    }
+
+    fn peak_dbfs(&self) -> f64 {
+        dbfs(self.peak as f64)
+    }
+
+    fn rms_dbfs(&self) -> f64 {
+        if self.samples == 0 {
+            return f64::NEG_INFINITY;
+        }
+
+        dbfs((self.sum_of_squares / self.samples as f64).sqrt())
+    }
+
+    // None if nothing is wrong.
+    fn problem(&self) -> Option<String> {
+        if self.all_zero {
+            Some("Silent, every sample is zero".to_string())
+        } else if self.clipped > 0 {
+            Some(format!("Clipped {} times", self.clipped))
+        } else {
+            None
+        }
+    }
+}
+
+// Record from the Capture PCM of the configuration at path for duration,
+// or until Ctrl+C, showing live meters of every channel, and check that
+// every channel recorded something without clipping.
+pub fn test_microphones(path: &Path, duration: Duration) -> Result<(), String> {
+    if duration.is_zero() {
+        return Err("The duration has to be at least 1 second.".to_string());
+    }
+
+    let (mut stream, params) = Stream::open_installed(path, Direction::Capture)?;
+
+    let channels = params.channels as usize;
+
+    let names: Vec<String> = match stream.channel_names() {
+        Some(names) => names,
+        None => (1..=channels)
+            .map(|channel| format!("Channel {channel}"))
+            .collect(),
+    };
+
+    let show_meters = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+
+    println!(
+        "{}",
+        format!(
+            "\nRecording from the Capture PCM of {} ({} {} Hz {}ch) for {} seconds, Ctrl+C stops early.\n",
+            path.display(),
+            params.format,
+            params.rate,
+            params.channels,
+            duration.as_secs()
+        )
+        .cyan()
+    );
+
+    INTERRUPTED.store(false, AtomicOrdering::SeqCst);
+
+    let previous_handler = unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    };
+
+    let frames = (params.rate as f64 * METER_INTERVAL.as_secs_f64()) as usize;
+
+    let mut totals = vec![Levels::new(); channels];
+
+    let started = Instant::now();
+
+    let mut result = Ok(());
+
+    let mut first = true;
+
+    while started.elapsed() < duration && !INTERRUPTED.load(AtomicOrdering::SeqCst) {
+        let samples = match stream.read(frames) {
+            Ok(samples) => samples,
+            Err(e) => {
+                result = Err(e);
+
+                break;
+            }
+        };
+
+        let mut current = vec![Levels::new(); channels];
+
+        for frame in samples.chunks_exact(channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                current[channel].add(*sample);
+                totals[channel].add(*sample);
+            }
+        }
+
+        if show_meters {
+            // Move back up to redraw the meters in place.
+            if !first {
+                print!("\x1b[{channels}A");
+            }
+
+            for (name, levels) in names.iter().zip(&current) {
+                println!("\r\x1b[K{}", meter(name, levels));
+            }
+
+            let _ = stdout().flush();
+
+            first = false;
+        }
+    }
+
+    unsafe { libc::signal(libc::SIGINT, previous_handler) };
+
+    result?;
+
+    show_levels(&names, &totals);
+
+    let problems = totals
+        .iter()
+        .filter(|levels| levels.problem().is_some())
+        .count();
+
+    if problems == 0 {
+        println!(
+            "{}",
+            "\nEvery channel recorded something and nothing clipped.".cyan()
+        );
+
+        Ok(())
+    } else {
+        Err(format!(
+            "{problems} of {channels} channels did not record properly, see the RESULT column above."
+        ))
+    }
+}
+
+fn meter(name: &str, levels: &Levels) -> String {
+    let peak = levels.peak_dbfs();
+
+    let filled = (((peak - METER_FLOOR_DBFS) / -METER_FLOOR_DBFS).clamp(0.0, 1.0)
+        * METER_WIDTH as f64)
+        .round() as usize;
+
+    let bar = format!("{}{}", "█".repeat(filled), "·".repeat(METER_WIDTH - filled));
+
+    let bar = if levels.clipped > 0 {
+        bar.red()
+    } else {
+        bar.green()
+    };
+
+    format!(
+        "{name:<20} {bar} peak {} rms {}",
+        format_dbfs(peak),
+        format_dbfs(levels.rms_dbfs())
+    )
+}
+
+fn show_levels(names: &[String], totals: &[Levels]) {
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(vec![
+            Cell::new("CHANNEL").add_attribute(Attribute::Bold),
+            Cell::new("PEAK").add_attribute(Attribute::Bold),
+            Cell::new("RMS").add_attribute(Attribute::Bold),
+            Cell::new("RESULT").add_attribute(Attribute::Bold),
+        ]);
+
+    for (name, levels) in names.iter().zip(totals) {
+        table.add_row(vec![
+            Cell::new(name),
+            Cell::new(format_dbfs(levels.peak_dbfs())),
+            Cell::new(format_dbfs(levels.rms_dbfs())),
+            Cell::new(levels.problem().unwrap_or_else(|| "OK".to_string())),
+        ]);
+    }
+
+    println!("\n{table}");
+}
+
+fn dbfs(level: f64) -> f64 {
+    20.0 * level.log10()
+}
+
+fn format_dbfs(dbfs: f64) -> String {
+    if dbfs.is_finite() {
+        format!("{dbfs:6.1} dBFS")
+    } else {
+        "  -inf dBFS".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(samples: &[f32]) -> Levels {
+        let mut levels = Levels::new();
+
+        for sample in samples {
+            levels.add(*sample);
+        }
+
+        levels
+    }
+
+    #[test]
+    fn silence() {
+        let silent = levels(&[0.0; 4800]);
+
+        assert_eq!(
+            silent.problem().as_deref(),
+            Some("Silent, every sample is zero")
+        );
+        assert_eq!(silent.peak_dbfs(), f64::NEG_INFINITY);
+        assert_eq!(silent.rms_dbfs(), f64::NEG_INFINITY);
+        assert_eq!(format_dbfs(silent.rms_dbfs()), "  -inf dBFS");
+
+        // Nothing recorded at all is just as silent.
+        assert!(Levels::new().problem().is_some());
+        assert_eq!(Levels::new().rms_dbfs(), f64::NEG_INFINITY);
+
+        // A single sample that isn't zero is not.
+        let mut quiet = [0.0; 4800];
+
+        quiet[100] = 1e-6;
+
+        assert_eq!(levels(&quiet).problem(), None);
+    }
+
+    #[test]
+    fn clipping() {
+        let clipped = levels(&[0.5, 1.0, -0.2, -1.0, 0.9995, 0.998]);
+
+        assert_eq!(clipped.clipped, 3);
+        assert_eq!(clipped.problem().as_deref(), Some("Clipped 3 times"));
+        assert_eq!(clipped.peak_dbfs(), 0.0);
+    }
+
+    #[test]
+    fn normal_levels() {
+        // A sine at half of full scale.
+        let samples: Vec<f32> = (0..48000)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 48000.0).sin())
+            .collect();
+
+        let normal = levels(&samples);
+
+        assert_eq!(normal.problem(), None);
+        assert!((normal.peak_dbfs() - -6.02).abs() < 0.01);
+        // 3 dB below the peak.
+        assert!((normal.rms_dbfs() - -9.03).abs() < 0.01);
+        assert_eq!(format_dbfs(normal.rms_dbfs()), "  -9.0 dBFS");
+    }
+
+    #[test]
+    fn meters() {
+        let bar = |peak: f32| {
+            let meter = meter("Left", &levels(&[peak]));
+
+            meter.matches('█').count()
+        };
+
+        assert_eq!(bar(0.0), 0);
+        assert_eq!(bar(0.0001), 0);
+        assert_eq!(bar(0.0316), METER_WIDTH / 2);
+        assert_eq!(bar(1.0), METER_WIDTH);
+
+        assert!(meter("Left", &levels(&[0.5])).starts_with("Left                 "));
+        assert!(meter("Left", &levels(&[0.5])).contains("peak   -6.0 dBFS rms   -6.0 dBFS"));
+    }
+}
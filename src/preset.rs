@@ -0,0 +1,231 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::{parse_format, ConfigRequest, PcmSelector},
+    ValidConfiguration,
+};
+
+// A preset stores the same choices the wizard asks for so
+// that one unit can be configured by hand and the rest of
+// a fleet can replay it with `awiz apply` without prompts.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub converter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub playback: Option<PresetConfiguration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture: Option<PresetConfiguration>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PresetConfiguration {
+    // The PCM is matched on every field that is present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_time_ms: Option<u32>,
+}
+
+impl Preset {
+    pub fn new(
+        playback_config: Option<&ValidConfiguration>,
+        capture_config: Option<&ValidConfiguration>,
+        converter: Option<&str>,
+    ) -> Self {
+        Self {
+            converter: converter.map(|c| c.to_string()),
+            playback: playback_config.map(PresetConfiguration::from),
+            capture: capture_config.map(PresetConfiguration::from),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+        let preset: Self = toml::from_str(&contents)
+            .map_err(|e| format!("{} is not a valid preset: {e}", path.display()))?;
+
+        if preset.playback.is_none() && preset.capture.is_none() {
+            return Err(format!(
+                "{} does not contain a [playback] or [capture] section.",
+                path.display()
+            ));
+        }
+
+        Ok(preset)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = toml::to_string_pretty(self)
+            .map_err(|e| format!("Could not serialize the preset: {e}"))?;
+
+        let contents = format!(
+            "# Generated by awiz {}. Replay with: awiz apply {}\n\n{contents}",
+            env!("CARGO_PKG_VERSION"),
+            path.display()
+        );
+
+        fs::write(path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    pub fn playback_request(&self) -> Result<Option<ConfigRequest>, String> {
+        self.playback
+            .as_ref()
+            .map(|c| c.to_request("playback"))
+            .transpose()
+    }
+
+    pub fn capture_request(&self) -> Result<Option<ConfigRequest>, String> {
+        self.capture
+            .as_ref()
+            .map(|c| c.to_request("capture"))
+            .transpose()
+    }
+}
+
+impl PresetConfiguration {
+    fn to_request(&self, section: &str) -> Result<ConfigRequest, String> {
+        if self.card.is_none() && self.description.is_none() {
+            return Err(format!(
+                "The [{section}] section needs a card and/or a description to match a PCM."
+            ));
+        }
+
+        let format = self
+            .format
+            .as_deref()
+            .map(parse_format)
+            .transpose()
+            .map_err(|e| format!("The [{section}] section is not valid: {e}"))?;
+
+        Ok(ConfigRequest {
+            pcm: PcmSelector::Match {
+                card_name: self.card.clone(),
+                description: self.description.clone(),
+                device_number: self.device,
+            },
+            format,
+            rate: self.rate,
+            channels: self.channels,
+            buffer_time_ms: self.buffer_time_ms,
+        })
+    }
+}
+
+impl From<&ValidConfiguration> for PresetConfiguration {
+    fn from(config: &ValidConfiguration) -> Self {
+        Self {
+            card: Some(config.card_name.clone()),
+            device: Some(config.device_number),
+            description: Some(config.description.clone()),
+            format: Some(config.format.to_string()),
+            rate: Some(config.rate),
+            channels: Some(config.channels),
+            buffer_time_ms: Some(config.buffer_time_ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(contents: &str) -> Result<Preset, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preset.toml");
+
+        fs::write(&path, contents).unwrap();
+
+        Preset::load(&path)
+    }
+
+    #[test]
+    fn saved_presets_load() {
+        let preset = Preset {
+            converter: Some("speexrate_medium".to_string()),
+            playback: Some(PresetConfiguration {
+                card: Some("DAC".to_string()),
+                device: Some(0),
+                description: Some("USB \"Audio\" DAC".to_string()),
+                format: Some("S32_LE".to_string()),
+                rate: Some(48000),
+                channels: Some(2),
+                buffer_time_ms: Some(40),
+            }),
+            capture: Some(PresetConfiguration {
+                card: Some("Mic".to_string()),
+                ..Default::default()
+            }),
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dac.toml");
+
+        preset.save(&path).unwrap();
+
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("# Generated by awiz"));
+
+        assert_eq!(Preset::load(&path).unwrap(), preset);
+    }
+
+    #[test]
+    fn malformed_presets_are_rejected() {
+        let error = |contents: &str| load(contents).unwrap_err();
+
+        assert!(error("").contains("does not contain a [playback] or [capture] section"));
+        assert!(error("converter = \"speexrate\"").contains("does not contain"));
+        assert!(error("[playback\ncard = \"DAC\"").contains("is not a valid preset"));
+        assert!(error("[playback]\ncard = 1").contains("is not a valid preset"));
+        assert!(error("[playback]\nrate = -1").contains("is not a valid preset"));
+        assert!(error("[playback]\ncard = \"DAC\"\nbitrate = 1").contains("unknown field"));
+        assert!(error("[speakers]\ncard = \"DAC\"").contains("unknown field"));
+
+        assert!(Preset::load(Path::new("/nonexistent/preset.toml"))
+            .unwrap_err()
+            .starts_with("Could not read"));
+    }
+
+    #[test]
+    fn presets_need_a_card_or_description() {
+        let preset = load("[playback]\nrate = 48000").unwrap();
+
+        assert!(preset
+            .playback_request()
+            .unwrap_err()
+            .contains("needs a card and/or a description"));
+
+        let preset = load("[capture]\ncard = \"Mic\"\nformat = \"S17_LE\"").unwrap();
+
+        assert!(preset
+            .capture_request()
+            .unwrap_err()
+            .starts_with("The [capture] section is not valid"));
+
+        let preset = load("[capture]\ndescription = \"USB Mic\"\nformat = \"S16_LE\"").unwrap();
+        let request = preset.capture_request().unwrap().unwrap();
+
+        assert!(preset.playback_request().unwrap().is_none());
+        assert_eq!(
+            request.format.map(|f| f.to_string()).as_deref(),
+            Some("S16_LE")
+        );
+    }
+}
@@ -0,0 +1,821 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, io,
+    time::{Duration, Instant},
+};
+
+use alsa::{pcm::Format, Direction, ValueOr};
+
+use colored::*;
+
+use comfy_table::{
+    modifiers::UTF8_SOLID_INNER_BORDERS, presets::UTF8_FULL, Attribute, Cell, CellAlignment,
+    ContentArrangement, Table,
+};
+
+use itertools::Itertools;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{BackendError, BackendResult, Device, Holder, HwSpace},
+    format_ranges, AlsaPcm, ProbedPcms, ValidConfiguration, FORMATS,
+};
+
+// It's basically all or nothing with PCMs and HwParams.
+// Once they are in an error state they can't be reused.
+// So instead of opening the PCM again for every combination
+// of params, one configuration space is narrowed down one
+// param at a time, and every branch gets a copy of its own.
+
+#[derive(Debug, Clone, Copy)]
+pub enum Param {
+    Format(Format),
+    Rate(u32),
+    Channels(u32),
+}
+
+// The configuration space of the device with resampling disabled,
+// everything else is narrowed down from here.
+pub fn open_space(device: &dyn Device) -> Option<Box<dyn HwSpace + '_>> {
+    let hwp = device.hw_params_any().ok()?;
+
+    hwp.set_rate_resample(false).ok()?;
+
+    if hwp.get_rate_resample().ok()? {
+        return None;
+    }
+
+    Some(hwp)
+}
+
+// Narrow hwp down to param. The device has to
+// actually take the value, not pick another one.
+pub fn refine(hwp: &dyn HwSpace, param: Param) -> bool {
+    explain_refine(hwp, param).is_ok()
+}
+
+// refine, but with what went wrong if it didn't work.
+pub fn explain_refine(hwp: &dyn HwSpace, param: Param) -> Result<(), StepFailure> {
+    match param {
+        Param::Format(format) => exactly(
+            Step::Format,
+            format,
+            hwp.set_format(format).and_then(|_| hwp.get_format()),
+        ),
+        Param::Rate(rate) => exactly(
+            Step::Rate,
+            rate,
+            hwp.set_rate(rate, ValueOr::Nearest)
+                .and_then(|_| hwp.get_rate()),
+        ),
+        Param::Channels(channels) => exactly(
+            Step::Channels,
+            channels,
+            hwp.set_channels(channels).and_then(|_| hwp.get_channels()),
+        ),
+    }
+}
+
+// A copy of hwp narrowed down to param, hwp itself is left alone.
+pub fn branch<'a>(
+    hwp: &'a dyn HwSpace,
+    param: Param,
+) -> Result<Box<dyn HwSpace + 'a>, StepFailure> {
+    let copy = hwp
+        .try_clone()
+        .map_err(|e| StepFailure::refused(param.step(), param.value(), e))?;
+
+    explain_refine(&*copy, param)?;
+
+    Ok(copy)
+}
+
+// Whether the device accepts hwp for real. Installing
+// params changes them, so a copy is installed instead.
+pub fn check_install(hwp: &dyn HwSpace) -> Result<(), StepFailure> {
+    hwp.try_clone()
+        .and_then(|hwp| hwp.install())
+        .map_err(StepFailure::not_installed)
+}
+
+// Ok if the value the device ended up with, or the error
+// it gave instead, is the value that was asked for.
+pub fn exactly<T: PartialEq + fmt::Display>(
+    step: Step,
+    requested: T,
+    actual: BackendResult<T>,
+) -> Result<(), StepFailure> {
+    match actual {
+        Err(e) => Err(StepFailure::refused(step, requested, e)),
+        Ok(actual) if actual != requested => Err(StepFailure {
+            step,
+            requested: Some(requested.to_string()),
+            actual: Some(actual.to_string()),
+            errno: None,
+        }),
+        Ok(_) => Ok(()),
+    }
+}
+
+impl Param {
+    fn step(&self) -> Step {
+        match self {
+            Self::Format(_) => Step::Format,
+            Self::Rate(_) => Step::Rate,
+            Self::Channels(_) => Step::Channels,
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            Self::Format(format) => format.to_string(),
+            Self::Rate(rate) => rate.to_string(),
+            Self::Channels(channels) => channels.to_string(),
+        }
+    }
+}
+
+// The hardware parameters in the order they are set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Format,
+    Rate,
+    Channels,
+    BufferTime,
+    PeriodTime,
+    // Installing the params, where the driver gets the final say.
+    Install,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Format => write!(f, "Format"),
+            Self::Rate => write!(f, "Sampling Rate"),
+            Self::Channels => write!(f, "Channel Count"),
+            Self::BufferTime => write!(f, "Buffer Time"),
+            Self::PeriodTime => write!(f, "Period Time"),
+            Self::Install => write!(f, "Installing the params"),
+        }
+    }
+}
+
+// Which step made a combination of params invalid. Either the device
+// returned an error (errno), or it took the value but quietly replaced
+// it with another one (actual).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct StepFailure {
+    pub step: Step,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<i32>,
+}
+
+impl StepFailure {
+    pub fn refused(step: Step, requested: impl fmt::Display, e: BackendError) -> Self {
+        Self {
+            step,
+            requested: Some(requested.to_string()),
+            actual: None,
+            errno: Some(e.errno),
+        }
+    }
+
+    pub fn not_installed(e: BackendError) -> Self {
+        Self {
+            step: Step::Install,
+            requested: None,
+            actual: None,
+            errno: Some(e.errno),
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self.step {
+            Step::Rate => " Hz",
+            Step::BufferTime | Step::PeriodTime => " µs",
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for StepFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = self.unit();
+
+        match (&self.requested, &self.actual, self.errno) {
+            (Some(requested), Some(actual), _) => write!(
+                f,
+                "{} {requested}{unit} was changed to {actual}{unit} by the driver.",
+                self.step
+            ),
+            (Some(requested), None, Some(errno)) => write!(
+                f,
+                "{} {requested}{unit} was refused: {}.",
+                self.step,
+                io::Error::from_raw_os_error(errno)
+            ),
+            (_, _, Some(errno)) => write!(
+                f,
+                "{} was refused: {}.",
+                self.step,
+                io::Error::from_raw_os_error(errno)
+            ),
+            _ => write!(f, "{} failed.", self.step),
+        }
+    }
+}
+
+// One combination of Format, Sampling Rate and Channel Count
+// as tested with --explain, failure is None if it's valid.
+#[derive(Debug, Clone)]
+pub struct TestedCombination {
+    pub format: Format,
+    pub rate: u32,
+    pub channels: u32,
+    pub failure: Option<StepFailure>,
+}
+
+// Every value in min..=max that is valid, without testing all of them
+// if they turn out to be evenly spaced, and the first value past the last
+// valid one that was tested and turned out not to be, or min if none are
+// valid. That one is None if the valid values go all the way up to max.
+//
+// The valid Buffer Times of real hardware follow from frame counts and
+// period alignments, so they are usually evenly spaced, for example every
+// 10 ms at 44100Hz, from the first one up to some limit. Every value on
+// that grid up to the limit is tested, and the one halfway between each
+// pair of them, every value between the limit and the one before it and a
+// few past the limit, on and off the grid. If any of those says otherwise
+// every value in min..=max is tested after all.
+pub fn find_valid(
+    min: u32,
+    max: u32,
+    mut valid: impl FnMut(u32) -> bool,
+) -> (Vec<u32>, Option<u32>) {
+    if let Some(found) = find_evenly_spaced(min, max, &mut valid) {
+        return found;
+    }
+
+    let found: Vec<u32> = (min..=max).filter(|v| valid(*v)).collect();
+
+    let rejected = match found.last() {
+        None => min,
+        Some(last) => last + 1,
+    };
+
+    (found, (rejected <= max).then_some(rejected))
+}
+
+// The first valid value and the spacing are found by walking up from min,
+// the limit by doubling the distance until a value fails and then bisecting.
+// Every value in between is then tested, the ones halfway between them and
+// a few past the limit, None if any of them don't fit.
+fn find_evenly_spaced(
+    min: u32,
+    max: u32,
+    valid: &mut impl FnMut(u32) -> bool,
+) -> Option<(Vec<u32>, Option<u32>)> {
+    let first = match (min..=max).find(|v| valid(*v)) {
+        None => return Some((vec![], (min <= max).then_some(min))),
+        Some(first) => first,
+    };
+
+    let step = match (first + 1..=max).find(|v| valid(*v)) {
+        None => return Some((vec![first], (first < max).then_some(first + 1))),
+        Some(next) => next - first,
+    };
+
+    // From here on everything is an index into first, first + step, …
+    let last_index = (max - first) / step;
+    let mut valid_at = |i: u32| valid(first + i * step);
+    let halfway = |i: u32| first + i * step + step / 2;
+
+    // The first two are known to be valid.
+    let mut good = 1;
+    let mut bad = last_index + 1;
+    let mut jump = 1;
+
+    while good < last_index {
+        let probe = (good + jump).min(last_index);
+
+        if valid_at(probe) {
+            good = probe;
+            jump *= 2;
+        } else {
+            bad = probe;
+            break;
+        }
+    }
+
+    while bad - good > 1 {
+        let mid = good + (bad - good) / 2;
+
+        if valid_at(mid) {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+
+    // A gap in between, for example 1, 2, 3, 5 …
+    if !(2..good).all(&mut valid_at) {
+        return None;
+    }
+
+    // More past the limit, for example 1, 2, 4, 8 …
+    let mut past = 2;
+
+    while good + past <= last_index {
+        if valid_at(good + past) {
+            return None;
+        }
+
+        past *= 2;
+    }
+
+    if good < last_index && valid_at(last_index) {
+        return None;
+    }
+
+    let mut off_grid = vec![];
+
+    // Off the grid in between, for example 10, 20, 30, 35, 40 …
+    // The one between first and first + step is known to be invalid.
+    if step > 1 {
+        off_grid.extend((1..good).map(halfway));
+    }
+
+    // Off the grid right past the limit, for example 10, 20, 30, 35.
+    let limit_end = (first + (good + 1) * step).min(max + 1);
+    off_grid.extend(first + good * step + 1..limit_end);
+
+    // And off the grid further past it.
+    let mut past = 2;
+
+    while step > 1 && good + past <= last_index {
+        off_grid.push(halfway(good + past - 1));
+        past *= 2;
+    }
+
+    if step > 1 && good < last_index && halfway(last_index) <= max {
+        off_grid.push(halfway(last_index));
+    }
+
+    if off_grid.into_iter().any(valid) {
+        return None;
+    }
+
+    Some((
+        (0..=good).map(|i| first + i * step).collect(),
+        (bad <= last_index).then_some(first + bad * step),
+    ))
+}
+
+// Why a PCM was left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Rejection {
+    Busy { holders: Vec<Holder> },
+    OpenFailed { errno: i32 },
+    NoMixableFormats,
+    // Real hardware doesn't do 100+ channel counts,
+    // a hw device behind a plug plugin does.
+    TooManyChannels,
+    NoValidConfigurations,
+    TimedOut { seconds: u64 },
+    Cancelled,
+}
+
+impl Rejection {
+    // Whether probing again later might turn out differently.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Busy { .. } | Self::TimedOut { .. } | Self::Cancelled
+        )
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Busy { holders } if holders.is_empty() => write!(
+                f,
+                "In use by another process. Stop it, or run with --wait-busy to wait for it to be released."
+            ),
+            Self::Busy { holders } => write!(
+                f,
+                "In use by {}. Stop it, or run with --wait-busy to wait for it to be released.",
+                holders.iter().map(|holder| holder.to_string()).collect::<Vec<String>>().join(", ")
+            ),
+            Self::OpenFailed { errno } => write!(
+                f,
+                "Could not be opened: {}.",
+                io::Error::from_raw_os_error(*errno)
+            ),
+            Self::NoMixableFormats => write!(
+                f,
+                "Does not support any formats supported by dmix/dsnoop ({}), it is not software mixable.",
+                FORMATS.iter().map(|f| f.to_string()).collect::<Vec<String>>().join(", ")
+            ),
+            Self::TooManyChannels => write!(
+                f,
+                "Reports an unusually large number of supported Channel Counts (100+). It is more than likely not a real hardware device, but a hardware device behind a plug plugin, and it is not software mixable."
+            ),
+            Self::NoValidConfigurations => write!(
+                f,
+                "None of its combinations of Format, Sampling Rate and Channel Count are valid."
+            ),
+            Self::TimedOut { seconds } => write!(
+                f,
+                "Probing its card did not finish within {seconds} seconds."
+            ),
+            Self::Cancelled => write!(f, "Probing was cancelled."),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RejectedPcm {
+    pub name: String,
+    pub card_name: String,
+    pub direction: Direction,
+    pub rejection: Rejection,
+    // Only filled in with --explain.
+    pub tested: Vec<TestedCombination>,
+}
+
+// Every PCM that was found, whether it can be used and if not why not.
+pub fn show_summary(probed: &ProbedPcms) {
+    let mut rows: Vec<(&str, Direction, String, String)> = probed
+        .playback
+        .iter()
+        .chain(probed.capture.iter())
+        .map(|pcm| {
+            (
+                pcm.name.as_str(),
+                pcm.direction,
+                "Accepted".to_string(),
+                format!("Valid Configurations: {}", pcm.valid_configurations.len()),
+            )
+        })
+        .chain(probed.rejected.iter().map(|rejected| {
+            (
+                rejected.name.as_str(),
+                rejected.direction,
+                "Ignored".to_string(),
+                rejected.rejection.to_string(),
+            )
+        }))
+        .collect();
+
+    if rows.is_empty() {
+        return;
+    }
+
+    rows.sort_by(|a, b| a.0.cmp(b.0).then_with(|| (a.1 as u8).cmp(&(b.1 as u8))));
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(vec![
+            Cell::new("PCM").add_attribute(Attribute::Bold),
+            Cell::new("DIRECTION").add_attribute(Attribute::Bold),
+            Cell::new("STATUS").add_attribute(Attribute::Bold),
+            Cell::new("DETAILS").add_attribute(Attribute::Bold),
+        ]);
+
+    for (name, direction, status, details) in rows {
+        table.add_row(vec![
+            Cell::new(name),
+            Cell::new(format!("{direction:?}")),
+            Cell::new(status),
+            Cell::new(details),
+        ]);
+    }
+
+    println!("\n{table}");
+}
+
+// For every PCM which combinations of Format, Sampling Rate and Channel
+// Count are valid, and for the ones that aren't which step failed.
+pub fn show_explanation(probed: &ProbedPcms) {
+    let mut pcms: Vec<(&str, Direction, &[TestedCombination], &[ValidConfiguration])> = probed
+        .playback
+        .iter()
+        .chain(probed.capture.iter())
+        .map(|pcm| {
+            (
+                pcm.name.as_str(),
+                pcm.direction,
+                pcm.tested.as_slice(),
+                pcm.valid_configurations.as_slice(),
+            )
+        })
+        .chain(probed.rejected.iter().map(|rejected| {
+            (
+                rejected.name.as_str(),
+                rejected.direction,
+                rejected.tested.as_slice(),
+                &[][..],
+            )
+        }))
+        .filter(|(_, _, tested, _)| !tested.is_empty())
+        .collect();
+
+    pcms.sort_by(|a, b| a.0.cmp(b.0).then_with(|| (a.1 as u8).cmp(&(b.1 as u8))));
+
+    for (name, direction, tested, configs) in pcms {
+        println!("{}", format!("\n{name} ({direction:?})").bold());
+
+        show_matrix(tested);
+
+        // The same failure tends to take out whole rows and columns,
+        // so the failures are grouped by Format and reason.
+        let mut failures: BTreeMap<(String, String), (BTreeSet<u32>, BTreeSet<u32>)> =
+            BTreeMap::new();
+
+        for combination in tested {
+            if let Some(failure) = &combination.failure {
+                let (rates, channels) = failures
+                    .entry((combination.format.to_string(), failure.to_string()))
+                    .or_default();
+
+                rates.insert(combination.rate);
+                channels.insert(combination.channels);
+            }
+        }
+
+        for ((format, failure), (rates, channels)) in failures {
+            println!(
+                "{}",
+                format!(
+                    "{format}, {} Hz, {} channels: {failure}",
+                    rates
+                        .iter()
+                        .map(|r| r.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    format_ranges(&channels.into_iter().collect::<Vec<u32>>())
+                )
+                .yellow()
+            );
+        }
+
+        // And what limits the Buffer Times of the valid ones.
+        let mut limits: BTreeMap<Option<StepFailure>, usize> = BTreeMap::new();
+
+        for config in configs {
+            *limits
+                .entry(config.explain_buffer_time_limit())
+                .or_default() += 1;
+        }
+
+        for (limit, count) in limits {
+            let message = match limit {
+                None => format!(
+                    "The Buffer Times of {count} of {} Configurations go all the way up to the maximum.",
+                    configs.len()
+                ),
+                Some(failure) => format!(
+                    "The Buffer Times of {count} of {} Configurations end early: {failure}",
+                    configs.len()
+                ),
+            };
+
+            println!("{}", message.cyan());
+        }
+    }
+}
+
+// Rows are Sampling Rates, columns Formats and every
+// cell lists the Channel Counts that are valid.
+fn show_matrix(tested: &[TestedCombination]) {
+    let formats: Vec<Format> = tested.iter().map(|c| c.format).unique().collect();
+    let rates: Vec<u32> = tested.iter().map(|c| c.rate).unique().collect();
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(
+            std::iter::once(Cell::new("RATE").add_attribute(Attribute::Bold))
+                .chain(
+                    formats
+                        .iter()
+                        .map(|format| Cell::new(format).add_attribute(Attribute::Bold)),
+                )
+                .collect::<Vec<Cell>>(),
+        );
+
+    for rate in rates {
+        let cells = formats.iter().map(|format| {
+            let valid: Vec<u32> = tested
+                .iter()
+                .filter(|c| c.format == *format && c.rate == rate && c.failure.is_none())
+                .map(|c| c.channels)
+                .collect();
+
+            if valid.is_empty() {
+                Cell::new("✗")
+            } else {
+                Cell::new(format_ranges(&valid))
+            }
+        });
+
+        table.add_row(
+            std::iter::once(Cell::new(format!("{rate} Hz")))
+                .chain(cells)
+                .collect::<Vec<Cell>>(),
+        );
+    }
+
+    println!("{table}");
+}
+
+pub fn show_bench(pcms: &[AlsaPcm], probe_time: Duration) {
+    let mut table = Table::new();
+
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_width(100)
+        .set_header(vec![
+            Cell::new("PCM").add_attribute(Attribute::Bold),
+            Cell::new("DIRECTION").add_attribute(Attribute::Bold),
+            Cell::new("CONFIGURATIONS").add_attribute(Attribute::Bold),
+            Cell::new("PROBE").add_attribute(Attribute::Bold),
+            Cell::new("BUFFER TIMES").add_attribute(Attribute::Bold),
+        ]);
+
+    let mut buffer_time_total = Duration::ZERO;
+
+    for pcm in pcms {
+        // The wizard only asks for the Buffer Times of the chosen
+        // Configuration, this is the time it takes for all of them.
+        let start = Instant::now();
+
+        for config in &pcm.valid_configurations {
+            config.get_buffer_times_ms();
+        }
+
+        let buffer_time = start.elapsed();
+
+        buffer_time_total += buffer_time;
+
+        table.add_row(vec![
+            Cell::new(&pcm.name),
+            Cell::new(format!("{:?}", pcm.direction)),
+            Cell::new(pcm.valid_configurations.len()).set_alignment(CellAlignment::Right),
+            Cell::new(format_duration(pcm.probe_time)).set_alignment(CellAlignment::Right),
+            Cell::new(format_duration(buffer_time)).set_alignment(CellAlignment::Right),
+        ]);
+    }
+
+    println!("\n{table}");
+
+    println!(
+        "{}",
+        format!(
+            "\nProbing took {} (cards are probed in parallel), retrieving every Buffer Time took {}.",
+            format_duration(probe_time),
+            format_duration(buffer_time_total)
+        )
+        .cyan()
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: u32 = 1;
+    const MAX: u32 = 1000;
+
+    // What find_valid finds, how many values it tested and what testing every value finds.
+    fn compare(is_valid: impl Fn(u32) -> bool) -> (Vec<u32>, usize, Vec<u32>) {
+        let mut tests = 0;
+        let mut rejected = vec![];
+
+        let (found, limit) = find_valid(MIN, MAX, |v| {
+            tests += 1;
+
+            let valid = is_valid(v);
+
+            if !valid {
+                rejected.push(v);
+            }
+
+            valid
+        });
+
+        // The limit is always one that was actually tested.
+        if let Some(limit) = limit {
+            assert!(rejected.contains(&limit), "{limit} was never tested");
+            assert!(found.last().map_or(true, |last| *last < limit));
+        }
+
+        let walked = (MIN..=MAX).filter(|v| is_valid(*v)).collect();
+
+        (found, tests, walked)
+    }
+
+    #[test]
+    fn find_valid_contiguous() {
+        let (found, _, walked) = compare(|v| (5..=500).contains(&v));
+
+        assert_eq!(found, walked);
+    }
+
+    #[test]
+    fn find_valid_stepped() {
+        let (found, tests, walked) = compare(|v| v % 10 == 0 && v <= 700);
+
+        assert_eq!(found, walked);
+        assert!(tests < (MAX - MIN) as usize / 2);
+    }
+
+    #[test]
+    fn find_valid_powers_of_two() {
+        let (found, _, walked) = compare(|v| v.is_power_of_two());
+
+        assert_eq!(found, walked);
+    }
+
+    #[test]
+    fn find_valid_gapped() {
+        let (found, _, walked) = compare(|v| v != 4);
+
+        assert_eq!(found, walked);
+
+        let (found, _, walked) = compare(|v| (1..=8).contains(&v) && v != 4);
+
+        assert_eq!(found, walked);
+
+        let (found, _, walked) = compare(|v| v % 10 == 0 && !(300..400).contains(&v));
+
+        assert_eq!(found, walked);
+    }
+
+    #[test]
+    fn find_valid_off_the_grid() {
+        // Halfway between two on the grid.
+        let (found, _, walked) = compare(|v| [10, 20, 30, 35, 40].contains(&v));
+
+        assert_eq!(found, walked);
+        assert!(found.contains(&35));
+
+        // Right past the limit.
+        let (found, _, walked) = compare(|v| [10, 20, 30, 35].contains(&v));
+
+        assert_eq!(found, walked);
+
+        // Off the grid further past the limit.
+        let (found, _, walked) = compare(|v| v % 10 == 0 && v <= 300 || v == 615);
+
+        assert_eq!(found, walked);
+
+        // A second, evenly spaced run off the grid.
+        let (found, _, walked) = compare(|v| v % 10 == 0 || v % 10 == 5 && v > 500);
+
+        assert_eq!(found, walked);
+    }
+
+    #[test]
+    fn find_valid_limit() {
+        let limit = |is_valid: fn(u32) -> bool| find_valid(MIN, MAX, is_valid).1;
+
+        assert_eq!(limit(|v| v % 10 == 0 && v <= 700), Some(710));
+        assert_eq!(limit(|v| (5..=500).contains(&v)), Some(501));
+        assert_eq!(limit(|v| v >= 5), None);
+        assert_eq!(limit(|v| v.is_power_of_two()), Some(513));
+        assert_eq!(limit(|_| false), Some(MIN));
+        assert_eq!(limit(|v| v == 42), Some(43));
+        assert_eq!(limit(|v| v == MAX), None);
+    }
+
+    #[test]
+    fn find_valid_none_or_one() {
+        assert_eq!(compare(|_| false).0, Vec::<u32>::new());
+        assert_eq!(compare(|v| v == 42).0, vec![42]);
+        assert_eq!(compare(|v| v == MAX).0, vec![MAX]);
+    }
+}
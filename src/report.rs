@@ -0,0 +1,314 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use alsa::Direction;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::Backend,
+    cli::parse_format,
+    probe::{RejectedPcm, Rejection, StepFailure, TestedCombination},
+    AlsaPcm, ProbedPcms, ValidConfiguration,
+};
+
+// Bump this whenever a field is renamed or removed,
+// or the meaning of an existing field changes.
+// Adding fields is not a breaking change.
+pub const PROBE_REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeReport {
+    pub schema_version: u32,
+    pub awiz_version: String,
+    pub playback: Vec<PcmReport>,
+    pub capture: Vec<PcmReport>,
+    // Every PCM that was found but left out, and why.
+    #[serde(default)]
+    pub ignored: Vec<IgnoredReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PcmReport {
+    pub name: String,
+    pub description: String,
+    pub direction: String,
+    pub card: String,
+    pub device: u32,
+    pub subdevice: u32,
+    pub formats: Vec<String>,
+    pub rates: Vec<u32>,
+    // Only present if the PCM supports any rate in a continuous
+    // range, rates then only lists the standard rates within it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_range: Option<RateRangeReport>,
+    pub channels: Vec<u32>,
+    pub configurations: Vec<ConfigurationReport>,
+    // Every combination that was tested, only with --explain.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tested: Vec<TestedReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateRangeReport {
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IgnoredReport {
+    pub name: String,
+    pub direction: String,
+    pub card: String,
+    // reason, and whatever goes with it, for example the
+    // processes that are holding a busy PCM.
+    #[serde(flatten)]
+    pub rejection: Rejection,
+    // The reason in words.
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tested: Vec<TestedReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestedReport {
+    pub format: String,
+    pub rate: u32,
+    pub channels: u32,
+    pub valid: bool,
+    // The step that failed, what was asked for, what the
+    // driver made of it and the error it returned, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<StepFailure>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigurationReport {
+    pub format: String,
+    pub rate: u32,
+    pub channels: u32,
+    pub buffer_time_min_us: u32,
+    pub buffer_time_max_us: u32,
+    pub default_buffer_time_ms: u32,
+}
+
+impl ProbeReport {
+    pub fn new(probed: &ProbedPcms) -> Self {
+        Self {
+            schema_version: PROBE_REPORT_SCHEMA_VERSION,
+            awiz_version: env!("CARGO_PKG_VERSION").to_string(),
+            playback: probed.playback.iter().map(PcmReport::from).collect(),
+            capture: probed.capture.iter().map(PcmReport::from).collect(),
+            ignored: probed.rejected.iter().map(IgnoredReport::from).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Could not serialize the report: {e}"))
+    }
+
+    pub fn to_yaml(&self) -> Result<String, String> {
+        serde_yaml::to_string(self).map_err(|e| format!("Could not serialize the report: {e}"))
+    }
+
+    // The PCMs the report was made from, as if they were just probed.
+    // Anything that needs the hardware goes through backend.
+    pub fn to_probed(&self, backend: &Arc<dyn Backend>) -> Result<ProbedPcms, String> {
+        if self.schema_version != PROBE_REPORT_SCHEMA_VERSION {
+            return Err(format!(
+                "Unsupported report schema version {}",
+                self.schema_version
+            ));
+        }
+
+        let playback_pcms = self
+            .playback
+            .iter()
+            .map(|pcm| pcm.to_pcm(backend, Direction::Playback))
+            .collect::<Result<_, _>>()?;
+
+        let capture_pcms = self
+            .capture
+            .iter()
+            .map(|pcm| pcm.to_pcm(backend, Direction::Capture))
+            .collect::<Result<_, _>>()?;
+
+        let rejected = self
+            .ignored
+            .iter()
+            .map(|ignored| {
+                Ok(RejectedPcm {
+                    name: ignored.name.clone(),
+                    card_name: ignored.card.clone(),
+                    direction: parse_direction(&ignored.direction)
+                        .ok_or_else(|| format!("{} is not a valid direction", ignored.direction))?,
+                    rejection: ignored.rejection.clone(),
+                    tested: to_tested(&ignored.tested)?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(ProbedPcms {
+            playback: playback_pcms,
+            capture: capture_pcms,
+            rejected,
+            cancelled: false,
+        })
+    }
+}
+
+impl PcmReport {
+    fn to_pcm(&self, backend: &Arc<dyn Backend>, direction: Direction) -> Result<AlsaPcm, String> {
+        if self.direction != direction_name(direction) {
+            return Err(format!(
+                "{} is listed as {:?} but its direction is {}",
+                self.name, direction, self.direction
+            ));
+        }
+
+        let formats = self
+            .formats
+            .iter()
+            .map(|format| parse_format(format))
+            .collect::<Result<_, _>>()?;
+
+        let mut pcm = AlsaPcm {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            direction,
+            card_name: self.card.clone(),
+            device_number: self.device,
+            sub_device_number: self.subdevice,
+            formats,
+            rates: self.rates.clone(),
+            rate_range: self.rate_range.as_ref().map(|range| range.min..=range.max),
+            channels: self.channels.clone(),
+            valid_configurations: vec![],
+            tested: to_tested(&self.tested)?,
+            probe_time: Duration::ZERO,
+            backend: backend.clone(),
+        };
+
+        pcm.valid_configurations = self
+            .configurations
+            .iter()
+            .map(|config| config.to_configuration(&pcm))
+            .collect::<Result<_, _>>()?;
+
+        Ok(pcm)
+    }
+}
+
+impl ConfigurationReport {
+    fn to_configuration(&self, pcm: &AlsaPcm) -> Result<ValidConfiguration, String> {
+        Ok(ValidConfiguration {
+            name: pcm.name.clone(),
+            description: pcm.description.clone(),
+            direction: pcm.direction,
+            card_name: pcm.card_name.clone(),
+            device_number: pcm.device_number,
+            sub_device_number: pcm.sub_device_number,
+            format: parse_format(&self.format)?,
+            rate: self.rate,
+            channels: self.channels,
+            buffer_time_ms: self.default_buffer_time_ms,
+            buffer_time_range: self.buffer_time_min_us..=self.buffer_time_max_us,
+            buffer_times_cache: Arc::new(Mutex::new(None)),
+            backend: pcm.backend.clone(),
+        })
+    }
+}
+
+impl From<&AlsaPcm> for PcmReport {
+    fn from(pcm: &AlsaPcm) -> Self {
+        Self {
+            name: pcm.name.clone(),
+            description: pcm.description.clone(),
+            direction: direction_name(pcm.direction).to_string(),
+            card: pcm.card_name.clone(),
+            device: pcm.device_number,
+            subdevice: pcm.sub_device_number,
+            formats: pcm.formats.iter().map(|f| f.to_string()).collect(),
+            rates: pcm.rates.clone(),
+            rate_range: pcm.rate_range.as_ref().map(|range| RateRangeReport {
+                min: *range.start(),
+                max: *range.end(),
+            }),
+            channels: pcm.channels.clone(),
+            configurations: pcm
+                .valid_configurations
+                .iter()
+                .map(ConfigurationReport::from)
+                .collect(),
+            tested: pcm.tested.iter().map(TestedReport::from).collect(),
+        }
+    }
+}
+
+impl From<&RejectedPcm> for IgnoredReport {
+    fn from(rejected: &RejectedPcm) -> Self {
+        Self {
+            name: rejected.name.clone(),
+            direction: direction_name(rejected.direction).to_string(),
+            card: rejected.card_name.clone(),
+            rejection: rejected.rejection.clone(),
+            message: rejected.rejection.to_string(),
+            tested: rejected.tested.iter().map(TestedReport::from).collect(),
+        }
+    }
+}
+
+impl From<&TestedCombination> for TestedReport {
+    fn from(combination: &TestedCombination) -> Self {
+        Self {
+            format: combination.format.to_string(),
+            rate: combination.rate,
+            channels: combination.channels,
+            valid: combination.failure.is_none(),
+            failure: combination.failure.clone(),
+        }
+    }
+}
+
+fn to_tested(tested: &[TestedReport]) -> Result<Vec<TestedCombination>, String> {
+    tested
+        .iter()
+        .map(|combination| {
+            Ok(TestedCombination {
+                format: parse_format(&combination.format)?,
+                rate: combination.rate,
+                channels: combination.channels,
+                failure: combination.failure.clone(),
+            })
+        })
+        .collect()
+}
+
+impl From<&ValidConfiguration> for ConfigurationReport {
+    fn from(config: &ValidConfiguration) -> Self {
+        Self {
+            format: config.format.to_string(),
+            rate: config.rate,
+            channels: config.channels,
+            buffer_time_min_us: *config.buffer_time_range.start(),
+            buffer_time_max_us: *config.buffer_time_range.end(),
+            default_buffer_time_ms: config.buffer_time_ms,
+        }
+    }
+}
+
+pub fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Playback => "playback",
+        Direction::Capture => "capture",
+    }
+}
+
+pub fn parse_direction(direction: &str) -> Option<Direction> {
+    [Direction::Playback, Direction::Capture]
+        .into_iter()
+        .find(|d| direction_name(*d) == direction)
+}
@@ -0,0 +1,297 @@
+use std::{f64::consts::PI, time::Duration};
+
+// Long enough that the tone starts and stops without a click.
+const FADE: Duration = Duration::from_millis(10);
+
+// A sine at frequency Hz and amplitude (1.0 being full scale), faded in and out.
+pub fn sine(rate: u32, frequency: f64, duration: Duration, amplitude: f64) -> Vec<f32> {
+    let frames = frames(rate, duration);
+
+    let samples = (0..frames)
+        .map(|i| amplitude * (2.0 * PI * frequency * i as f64 / rate as f64).sin())
+        .collect();
+
+    fade(samples, rate)
+}
+
+// Pink noise, which has the same energy in every octave, at a peak of amplitude.
+pub fn pink_noise(rate: u32, duration: Duration, amplitude: f64) -> Vec<f32> {
+    let frames = frames(rate, duration);
+
+    // A fixed seed, the noise doesn't need to be any more random than that.
+    let mut state: u32 = 0x2545_f491;
+    let mut b = [0.0_f64; 7];
+
+    let noise: Vec<f64> = (0..frames)
+        .map(|_| {
+            // xorshift32 white noise between -1.0 and 1.0.
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+
+            let white = state as f64 / u32::MAX as f64 * 2.0 - 1.0;
+
+            // Paul Kellet's refined filter, -3 dB per octave.
+            b[0] = 0.99886 * b[0] + white * 0.0555179;
+            b[1] = 0.99332 * b[1] + white * 0.0750759;
+            b[2] = 0.96900 * b[2] + white * 0.1538520;
+            b[3] = 0.86650 * b[3] + white * 0.3104856;
+            b[4] = 0.55000 * b[4] + white * 0.5329522;
+            b[5] = -0.7616 * b[5] - white * 0.0168980;
+
+            let pink = b[..6].iter().sum::<f64>() + b[6] + white * 0.5362;
+
+            b[6] = white * 0.115926;
+
+            pink
+        })
+        .collect();
+
+    let peak = noise
+        .iter()
+        .fold(0.0_f64, |peak, sample| peak.max(sample.abs()));
+
+    let scale = if peak > 0.0 { amplitude / peak } else { 0.0 };
+
+    fade(
+        noise.into_iter().map(|sample| sample * scale).collect(),
+        rate,
+    )
+}
+
+// A mono signal on only channel (counting from 0) of channels, interleaved.
+pub fn on_channel(mono: &[f32], channels: u32, channel: u32) -> Vec<f32> {
+    mono.iter()
+        .flat_map(|sample| (0..channels).map(move |c| if c == channel { *sample } else { 0.0 }))
+        .collect()
+}
+
+// The same mono signal on every one of channels, interleaved.
+pub fn interleave(mono: &[f32], channels: u32) -> Vec<f32> {
+    mono.iter()
+        .flat_map(|sample| (0..channels).map(move |_| *sample))
+        .collect()
+}
+
+// How well reference matches recorded at every offset into recorded, from
+// 0 to recorded.len() - 1. Done with FFTs, a recording of a second or two
+// would take far too long to correlate sample by sample.
+pub fn cross_correlate(reference: &[f32], recorded: &[f32]) -> Vec<f64> {
+    let len = (reference.len() + recorded.len()).next_power_of_two();
+
+    let padded = |samples: &[f32]| {
+        let mut padded: Vec<(f64, f64)> = samples.iter().map(|s| (*s as f64, 0.0)).collect();
+
+        padded.resize(len, (0.0, 0.0));
+
+        padded
+    };
+
+    let mut recorded_spectrum = padded(recorded);
+    let mut reference_spectrum = padded(reference);
+
+    fft(&mut recorded_spectrum, false);
+    fft(&mut reference_spectrum, false);
+
+    // Multiplying by the complex conjugate correlates instead of convolves.
+    let mut product: Vec<(f64, f64)> = recorded_spectrum
+        .iter()
+        .zip(&reference_spectrum)
+        .map(|((a, b), (c, d))| (a * c + b * d, b * c - a * d))
+        .collect();
+
+    fft(&mut product, true);
+
+    product
+        .iter()
+        .take(recorded.len())
+        .map(|(re, _)| re / len as f64)
+        .collect()
+}
+
+// An in place radix-2 FFT, samples.len() has to be a power of two.
+fn fft(samples: &mut [(f64, f64)], inverse: bool) {
+    let len = samples.len();
+
+    let mut j = 0;
+
+    for i in 1..len {
+        let mut bit = len >> 1;
+
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+
+        j |= bit;
+
+        if i < j {
+            samples.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+
+    while size <= len {
+        let angle = 2.0 * PI / size as f64 * if inverse { 1.0 } else { -1.0 };
+        let step = (angle.cos(), angle.sin());
+
+        for start in (0..len).step_by(size) {
+            let mut w = (1.0, 0.0);
+
+            for k in 0..size / 2 {
+                let (a, b) = (samples[start + k], samples[start + k + size / 2]);
+                let t = (b.0 * w.0 - b.1 * w.1, b.0 * w.1 + b.1 * w.0);
+
+                samples[start + k] = (a.0 + t.0, a.1 + t.1);
+                samples[start + k + size / 2] = (a.0 - t.0, a.1 - t.1);
+
+                w = (w.0 * step.0 - w.1 * step.1, w.0 * step.1 + w.1 * step.0);
+            }
+        }
+
+        size <<= 1;
+    }
+}
+
+fn frames(rate: u32, duration: Duration) -> usize {
+    (rate as f64 * duration.as_secs_f64()) as usize
+}
+
+fn fade(samples: Vec<f64>, rate: u32) -> Vec<f32> {
+    let len = samples.len();
+    let fade_frames = frames(rate, FADE).min(len / 2).max(1);
+
+    samples
+        .into_iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            let gain = (i.min(len - 1 - i) as f64 / fade_frames as f64).min(1.0);
+
+            (sample * gain) as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    // The offset and value of the largest absolute correlation.
+    fn best_match(correlation: &[f64]) -> (usize, f64) {
+        correlation
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            .unwrap()
+    }
+
+    fn delayed(reference: &[f32], delay: usize, gain: f32) -> Vec<f32> {
+        let mut recorded = vec![0.0; delay];
+
+        recorded.extend(reference.iter().map(|sample| sample * gain));
+        recorded.resize(recorded.len() + 1000, 0.0);
+
+        recorded
+    }
+
+    #[test]
+    fn fft_round_trip() {
+        let original: Vec<(f64, f64)> = (0..64).map(|i| ((i as f64).sin(), 0.0)).collect();
+        let mut samples = original.clone();
+
+        fft(&mut samples, false);
+        fft(&mut samples, true);
+
+        for (a, b) in original.iter().zip(&samples) {
+            assert!((a.0 - b.0 / 64.0).abs() < 1e-9);
+            assert!((b.1 / 64.0).abs() < 1e-9);
+        }
+
+        // An impulse has a flat spectrum.
+        let mut impulse = vec![(0.0, 0.0); 16];
+
+        impulse[0] = (1.0, 0.0);
+
+        fft(&mut impulse, false);
+
+        assert!(impulse
+            .iter()
+            .all(|(re, im)| (re - 1.0).abs() < 1e-9 && im.abs() < 1e-9));
+    }
+
+    #[test]
+    fn delayed_copies_are_found() {
+        let reference = pink_noise(RATE, Duration::from_millis(100), 0.5);
+
+        for delay in [0, 1, 480, 12345] {
+            let correlation = cross_correlate(&reference, &delayed(&reference, delay, 0.25));
+            let (offset, value) = best_match(&correlation);
+
+            assert_eq!(offset, delay);
+            assert!(value > 0.0);
+        }
+
+        // Inverted, the match is just as good but negative.
+        let correlation = cross_correlate(&reference, &delayed(&reference, 480, -0.25));
+        let (offset, value) = best_match(&correlation);
+
+        assert_eq!(offset, 480);
+        assert!(value < 0.0);
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(
+            on_channel(&[1.0, 2.0], 3, 1),
+            [0.0, 1.0, 0.0, 0.0, 2.0, 0.0]
+        );
+        assert_eq!(on_channel(&[1.0, 2.0], 1, 0), [1.0, 2.0]);
+        assert_eq!(interleave(&[1.0, 2.0], 2), [1.0, 1.0, 2.0, 2.0]);
+        assert_eq!(interleave(&[1.0, 2.0], 1), [1.0, 2.0]);
+    }
+
+    #[test]
+    fn tones_fade_in_and_out() {
+        let tone = sine(RATE, 1000.0, Duration::from_millis(500), 0.25);
+
+        assert_eq!(tone.len(), RATE as usize / 2);
+        assert_eq!(tone[0], 0.0);
+        assert_eq!(tone[tone.len() - 1], 0.0);
+        assert!((peak(&tone) - 0.25).abs() < 1e-3);
+
+        // Half way through the fade in, half the gain.
+        let fade_frames = frames(RATE, FADE);
+        let faded = fade(vec![1.0; 4 * fade_frames], RATE);
+
+        assert_eq!(faded[fade_frames / 2], 0.5);
+        assert_eq!(faded[2 * fade_frames], 1.0);
+
+        // Too short to fade for the full FADE.
+        let faded = fade(vec![1.0; 5], RATE);
+
+        assert_eq!(faded, [0.0, 0.5, 1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn pink_noise_peaks_at_its_amplitude() {
+        let noise = pink_noise(RATE, Duration::from_secs(1), 0.5);
+
+        assert_eq!(noise.len(), RATE as usize);
+        assert_eq!(noise[0], 0.0);
+        assert_eq!(noise[noise.len() - 1], 0.0);
+        assert!((peak(&noise) - 0.5).abs() < 1e-6);
+
+        // The same every time.
+        assert_eq!(noise, pink_noise(RATE, Duration::from_secs(1), 0.5));
+    }
+}
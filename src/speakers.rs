@@ -0,0 +1,102 @@
+use std::{path::Path, time::Duration};
+
+use alsa::Direction;
+
+use colored::*;
+
+use crate::{
+    cli::TestSignal,
+    signal::{on_channel, pink_noise, sine},
+    stream::Stream,
+    TEST_TONE_AMPLITUDE, TEST_TONE_FREQUENCY,
+};
+
+// Play signal on every channel of the Playback PCM of the configuration at
+// path in turn, or only on channel, and say which channel is playing.
+// loops is how many times to go through the channels, 0 is forever.
+pub fn test_speakers(
+    path: &Path,
+    signal: TestSignal,
+    channel: Option<u32>,
+    duration: Duration,
+    loops: u32,
+) -> Result<(), String> {
+    if duration.is_zero() {
+        return Err("The duration has to be at least 1 second.".to_string());
+    }
+
+    let (mut stream, params) = Stream::open_installed(path, Direction::Playback)?;
+
+    let channels: Vec<u32> = match channel {
+        None => (0..params.channels).collect(),
+        Some(channel) if (1..=params.channels).contains(&channel) => vec![channel - 1],
+        Some(channel) => {
+            return Err(format!(
+                "There is no channel {channel}, the Playback PCM has {} channels.",
+                params.channels
+            ))
+        }
+    };
+
+    let names = stream.channel_names();
+
+    let mono = match signal {
+        TestSignal::Sine => sine(
+            params.rate,
+            TEST_TONE_FREQUENCY,
+            duration,
+            TEST_TONE_AMPLITUDE,
+        ),
+        TestSignal::PinkNoise => pink_noise(params.rate, duration, TEST_TONE_AMPLITUDE),
+    };
+
+    println!(
+        "{}",
+        format!(
+            "\nPlaying {} through the Playback PCM of {} ({} {} Hz {}ch).",
+            match signal {
+                TestSignal::Sine => format!("a {TEST_TONE_FREQUENCY} Hz sine"),
+                TestSignal::PinkNoise => "pink noise".to_string(),
+            },
+            path.display(),
+            params.format,
+            params.rate,
+            params.channels
+        )
+        .cyan()
+    );
+
+    if names.is_none() {
+        println!(
+            "{}",
+            "The driver has no channel map, the channels can only be told apart by number.".cyan()
+        );
+    }
+
+    if loops == 0 {
+        println!("{}", "Press Ctrl+C to stop.".cyan());
+    }
+
+    let mut round = 0;
+
+    while loops == 0 || round < loops {
+        for channel in &channels {
+            let name = names
+                .as_ref()
+                .and_then(|names| names.get(*channel as usize))
+                .map(|name| format!(": {name}"))
+                .unwrap_or_default();
+
+            println!(
+                "{}",
+                format!("\nChannel {} of {}{name}", channel + 1, params.channels).cyan()
+            );
+
+            stream.write(&on_channel(&mono, params.channels, *channel))?;
+        }
+
+        round += 1;
+    }
+
+    stream.drain()
+}
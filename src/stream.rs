@@ -0,0 +1,332 @@
+use std::{
+    ffi::{CStr, CString},
+    fs,
+    path::Path,
+    ptr,
+};
+
+use alsa::{pcm::Format, Direction};
+
+use alsa_sys::{
+    snd_pcm_chmap_long_name, snd_pcm_close, snd_pcm_drain, snd_pcm_format_t, snd_pcm_get_chmap,
+    snd_pcm_nonblock, snd_pcm_open_lconf, snd_pcm_readi, snd_pcm_recover, snd_pcm_set_params,
+    snd_pcm_t, snd_pcm_writei, SND_CHMAP_NA, SND_CHMAP_UNKNOWN, SND_PCM_ACCESS_RW_INTERLEAVED,
+    SND_PCM_STREAM_CAPTURE, SND_PCM_STREAM_PLAYBACK,
+};
+
+use crate::{
+    cli::parse_format,
+    conf::parse,
+    inspect::default_pcm,
+    validate::{check, ConfigTree, SND_NONBLOCK},
+    US_PER_MS,
+};
+
+// Used if the configuration doesn't say, the same as alsa-lib's own default.
+const DEFAULT_BUFFER_TIME_MS: u32 = 100;
+
+// The Format, Sampling Rate, Channel Count and Buffer
+// Time of the hardware behind a configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamParams {
+    pub format: Format,
+    pub rate: u32,
+    pub channels: u32,
+    pub buffer_time_ms: u32,
+}
+
+// A PCM opened with a private configuration tree, so that
+// a configuration can be used before it is installed.
+pub struct Stream {
+    pcm: *mut snd_pcm_t,
+    format: Format,
+    channels: usize,
+    // Kept around for as long as the PCM that was opened with it.
+    _tree: ConfigTree,
+}
+
+impl Stream {
+    pub fn open(
+        tree: ConfigTree,
+        name: &str,
+        direction: Direction,
+        format: Format,
+        rate: u32,
+        channels: u32,
+        latency_us: u32,
+    ) -> Result<Self, String> {
+        // Fail early rather than play noise.
+        bytes_per_sample(format)?;
+
+        let c_name = CString::new(name).map_err(|_| format!("{name} contains a NUL character"))?;
+
+        let stream = match direction {
+            Direction::Playback => SND_PCM_STREAM_PLAYBACK,
+            Direction::Capture => SND_PCM_STREAM_CAPTURE,
+        };
+
+        let mut pcm = ptr::null_mut();
+
+        check(unsafe {
+            snd_pcm_open_lconf(
+                &mut pcm,
+                c_name.as_ptr(),
+                stream,
+                SND_NONBLOCK,
+                tree.as_ptr(),
+            )
+        })
+        .map_err(|e| format!("Could not open {name}: {e}"))?;
+
+        // From here on pcm is closed when stream is dropped.
+        let stream = Self {
+            pcm,
+            format,
+            channels: channels as usize,
+            _tree: tree,
+        };
+
+        // Opening is non-blocking so a busy device fails instead of
+        // hanging, writing blocks so the samples are played in time.
+        check(unsafe { snd_pcm_nonblock(pcm, 0) })
+            .map_err(|e| format!("Could not set up {name}: {e}"))?;
+
+        check(unsafe {
+            snd_pcm_set_params(
+                pcm,
+                format as snd_pcm_format_t,
+                SND_PCM_ACCESS_RW_INTERLEAVED,
+                channels,
+                rate,
+                1,
+                latency_us,
+            )
+        })
+        .map_err(|e| format!("Could not set up {name} for {format} {rate} {channels}ch: {e}"))?;
+
+        Ok(stream)
+    }
+
+    // Open the default PCM of the configuration at path in direction with
+    // the params of the hardware it ends up at, so nothing is converted.
+    pub fn open_installed(
+        path: &Path,
+        direction: Direction,
+    ) -> Result<(Self, StreamParams), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+        let conf = parse(&contents)
+            .map_err(|e| format!("{} is not a valid ALSA configuration, {e}", path.display()))?;
+
+        let name = format!("{direction:?}");
+
+        let pcm = default_pcm(&conf, &name.to_lowercase()).ok_or_else(|| {
+            format!(
+                "{} does not configure a {name} PCM that awiz can use.",
+                path.display()
+            )
+        })?;
+
+        let missing = |param: &str| {
+            format!(
+                "{} does not set the {param} of its {name} PCM.",
+                path.display()
+            )
+        };
+
+        let params = StreamParams {
+            format: parse_format(&pcm.format.ok_or_else(|| missing("Format"))?)?,
+            rate: pcm.rate.ok_or_else(|| missing("Sampling Rate"))?,
+            channels: pcm.channels.ok_or_else(|| missing("Channel Count"))?,
+            buffer_time_ms: pcm.buffer_time_ms.unwrap_or(DEFAULT_BUFFER_TIME_MS),
+        };
+
+        let tree = ConfigTree::with_config(&contents)?;
+
+        let stream = Self::open(
+            tree,
+            "default",
+            direction,
+            params.format,
+            params.rate,
+            params.channels,
+            params.buffer_time_ms * US_PER_MS,
+        )?;
+
+        Ok((stream, params))
+    }
+
+    // The name of every channel as far as the driver knows them,
+    // None if it has no channel map.
+    pub fn channel_names(&self) -> Option<Vec<String>> {
+        let chmap = unsafe { snd_pcm_get_chmap(self.pcm) };
+
+        if chmap.is_null() {
+            return None;
+        }
+
+        let positions = unsafe {
+            let chmap = &*chmap;
+
+            chmap.pos.as_slice(chmap.channels as usize).to_vec()
+        };
+
+        unsafe { libc::free(chmap.cast()) };
+
+        if positions.len() != self.channels
+            || positions
+                .iter()
+                .all(|position| matches!(*position, SND_CHMAP_UNKNOWN | SND_CHMAP_NA))
+        {
+            return None;
+        }
+
+        Some(
+            positions
+                .into_iter()
+                .map(|position| {
+                    let name = unsafe { snd_pcm_chmap_long_name(position) };
+
+                    if name.is_null() {
+                        "Unknown".to_string()
+                    } else {
+                        unsafe { CStr::from_ptr(name) }
+                            .to_string_lossy()
+                            .to_string()
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    // samples are interleaved, between -1.0 and 1.0.
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let bytes = encode(samples, self.format)?;
+        let frame_size = bytes_per_sample(self.format)? * self.channels;
+
+        let mut offset = 0;
+
+        while offset < bytes.len() {
+            let frames = (bytes.len() - offset) / frame_size;
+
+            let written = unsafe {
+                snd_pcm_writei(
+                    self.pcm,
+                    bytes[offset..].as_ptr().cast(),
+                    frames as alsa_sys::snd_pcm_uframes_t,
+                )
+            };
+
+            if written < 0 {
+                // An underrun, or a suspend, is not the end of the world.
+                check(unsafe { snd_pcm_recover(self.pcm, written as i32, 1) })
+                    .map_err(|e| format!("Could not play: {e}"))?;
+            } else {
+                offset += written as usize * frame_size;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Up to frames interleaved frames, between -1.0 and 1.0.
+    pub fn read(&mut self, frames: usize) -> Result<Vec<f32>, String> {
+        let frame_size = bytes_per_sample(self.format)? * self.channels;
+        let mut bytes = vec![0_u8; frames * frame_size];
+
+        let read = loop {
+            let read = unsafe {
+                snd_pcm_readi(
+                    self.pcm,
+                    bytes.as_mut_ptr().cast(),
+                    frames as alsa_sys::snd_pcm_uframes_t,
+                )
+            };
+
+            if read >= 0 {
+                break read as usize;
+            }
+
+            // An overrun only means a few samples are missing.
+            check(unsafe { snd_pcm_recover(self.pcm, read as i32, 1) })
+                .map_err(|e| format!("Could not record: {e}"))?;
+        };
+
+        bytes.truncate(read * frame_size);
+
+        decode(&bytes, self.format)
+    }
+
+    // Wait for everything that was written to be played.
+    pub fn drain(&mut self) -> Result<(), String> {
+        check(unsafe { snd_pcm_drain(self.pcm) }).map_err(|e| format!("Could not play: {e}"))
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        unsafe { snd_pcm_close(self.pcm) };
+    }
+}
+
+fn bytes_per_sample(format: Format) -> Result<usize, String> {
+    match format {
+        Format::S16LE | Format::S16BE => Ok(2),
+        Format::S243LE | Format::S243BE => Ok(3),
+        Format::S24LE | Format::S24BE | Format::S32LE | Format::S32BE => Ok(4),
+        format => Err(format!("{format} is not supported")),
+    }
+}
+
+fn encode(samples: &[f32], format: Format) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(samples.len() * bytes_per_sample(format)?);
+
+    for sample in samples {
+        let sample = sample.clamp(-1.0, 1.0) as f64;
+
+        match format {
+            Format::S16LE => bytes.extend((to_integer(sample, 16) as i16).to_le_bytes()),
+            Format::S16BE => bytes.extend((to_integer(sample, 16) as i16).to_be_bytes()),
+            Format::S243LE => bytes.extend(&to_integer(sample, 24).to_le_bytes()[..3]),
+            Format::S243BE => bytes.extend(&to_integer(sample, 24).to_be_bytes()[1..]),
+            Format::S24LE => bytes.extend(to_integer(sample, 24).to_le_bytes()),
+            Format::S24BE => bytes.extend(to_integer(sample, 24).to_be_bytes()),
+            Format::S32LE => bytes.extend(to_integer(sample, 32).to_le_bytes()),
+            Format::S32BE => bytes.extend(to_integer(sample, 32).to_be_bytes()),
+            format => return Err(format!("{format} is not supported")),
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn to_integer(sample: f64, bits: u32) -> i32 {
+    let max = ((1_i64 << (bits - 1)) - 1) as f64;
+
+    (sample * max).round() as i32
+}
+
+fn decode(bytes: &[u8], format: Format) -> Result<Vec<f32>, String> {
+    let size = bytes_per_sample(format)?;
+
+    bytes
+        .chunks_exact(size)
+        .map(|b| {
+            let (value, bits) = match format {
+                Format::S16LE => (i16::from_le_bytes([b[0], b[1]]) as i32, 16),
+                Format::S16BE => (i16::from_be_bytes([b[0], b[1]]) as i32, 16),
+                // Shifting back and forth sign extends the 24 bits.
+                Format::S243LE => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8, 24),
+                Format::S243BE => (i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8, 24),
+                Format::S24LE => (i32::from_le_bytes([b[0], b[1], b[2], b[3]]) << 8 >> 8, 24),
+                Format::S24BE => (i32::from_be_bytes([b[0], b[1], b[2], b[3]]) << 8 >> 8, 24),
+                Format::S32LE => (i32::from_le_bytes([b[0], b[1], b[2], b[3]]), 32),
+                Format::S32BE => (i32::from_be_bytes([b[0], b[1], b[2], b[3]]), 32),
+                format => return Err(format!("{format} is not supported")),
+            };
+
+            Ok((value as f64 / (1_i64 << (bits - 1)) as f64) as f32)
+        })
+        .collect()
+}
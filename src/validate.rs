@@ -0,0 +1,264 @@
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::raw::c_int,
+    path::PathBuf,
+    ptr,
+};
+
+use alsa_sys::{
+    snd_config_delete, snd_config_load, snd_config_search_definition, snd_config_t, snd_config_top,
+    snd_config_topdir, snd_ctl_close, snd_ctl_open_lconf, snd_ctl_t, snd_input_buffer_open,
+    snd_input_close, snd_input_stdio_open, snd_input_t, snd_pcm_close, snd_pcm_open_lconf,
+    snd_pcm_stream_t, snd_pcm_t, SND_PCM_STREAM_CAPTURE, SND_PCM_STREAM_PLAYBACK,
+};
+
+use crate::conf::{parse, Value};
+
+// Not exported by alsa-sys, the same value for PCMs and controls.
+// Opening must fail rather than hang if a device is in use.
+pub const SND_NONBLOCK: c_int = 0x0001;
+
+const ALSA_CONF_FILE_NAME: &str = "alsa.conf";
+
+// A private alsa-lib configuration tree, the global
+// one that every other program uses is never touched.
+pub struct ConfigTree(*mut snd_config_t);
+
+impl ConfigTree {
+    // The system alsa.conf with config on top, as if config was installed.
+    pub fn with_config(config: &str) -> Result<Self, String> {
+        let tree = Self::new()?;
+
+        tree.load_file(&alsa_conf_path().to_string_lossy())?;
+
+        tree.load_str(config)
+            .map_err(|e| format!("alsa-lib could not load it: {e}"))?;
+
+        Ok(tree)
+    }
+
+    pub fn as_ptr(&self) -> *mut snd_config_t {
+        self.0
+    }
+
+    fn new() -> Result<Self, String> {
+        let mut top = ptr::null_mut();
+
+        check(unsafe { snd_config_top(&mut top) })
+            .map_err(|e| format!("Could not create a configuration tree: {e}"))?;
+
+        Ok(Self(top))
+    }
+
+    fn load_file(&self, path: &str) -> Result<(), String> {
+        let c_path = c_string(path)?;
+        let mut input = ptr::null_mut();
+
+        check(unsafe { snd_input_stdio_open(&mut input, c_path.as_ptr(), b"r\0".as_ptr().cast()) })
+            .map_err(|e| format!("Could not open {path}: {e}"))?;
+
+        self.load(input)
+            .map_err(|e| format!("Could not load {path}: {e}"))
+    }
+
+    fn load_str(&self, contents: &str) -> Result<(), String> {
+        let mut input = ptr::null_mut();
+
+        check(unsafe {
+            snd_input_buffer_open(
+                &mut input,
+                contents.as_ptr().cast(),
+                contents.len() as isize,
+            )
+        })?;
+
+        self.load(input)
+    }
+
+    fn load(&self, input: *mut snd_input_t) -> Result<(), String> {
+        let result = unsafe { snd_config_load(self.0, input) };
+
+        unsafe { snd_input_close(input) };
+
+        check(result)
+    }
+
+    // Resolve base.name the way alsa-lib does when it's opened,
+    // with every argument, function and reference expanded.
+    fn resolve(&self, base: &str, name: &str) -> Result<(), String> {
+        let (c_base, c_name) = (c_string(base)?, c_string(name)?);
+        let mut definition = ptr::null_mut();
+
+        check(unsafe {
+            snd_config_search_definition(self.0, c_base.as_ptr(), c_name.as_ptr(), &mut definition)
+        })?;
+
+        unsafe { snd_config_delete(definition) };
+
+        Ok(())
+    }
+
+    fn open_pcm(&self, name: &str, stream: snd_pcm_stream_t) -> Result<(), String> {
+        let c_name = c_string(name)?;
+        let mut pcm: *mut snd_pcm_t = ptr::null_mut();
+
+        check(unsafe {
+            snd_pcm_open_lconf(&mut pcm, c_name.as_ptr(), stream, SND_NONBLOCK, self.0)
+        })?;
+
+        unsafe { snd_pcm_close(pcm) };
+
+        Ok(())
+    }
+
+    fn open_ctl(&self, name: &str) -> Result<(), String> {
+        let c_name = c_string(name)?;
+        let mut ctl: *mut snd_ctl_t = ptr::null_mut();
+
+        check(unsafe { snd_ctl_open_lconf(&mut ctl, c_name.as_ptr(), SND_NONBLOCK, self.0) })?;
+
+        unsafe { snd_ctl_close(ctl) };
+
+        Ok(())
+    }
+}
+
+impl Drop for ConfigTree {
+    fn drop(&mut self) {
+        unsafe { snd_config_delete(self.0) };
+    }
+}
+
+// Load config on top of the system alsa.conf in a private tree and make sure
+// alsa-lib can resolve every PCM and control it defines, and if open_devices
+// is true actually open them. config is only ever written if this succeeds.
+pub fn validate_asound_conf(config: &str, open_devices: bool) -> Result<(), String> {
+    let conf = parse(config).map_err(|e| format!("It is not a valid configuration, {e}."))?;
+
+    let tree = ConfigTree::with_config(config)?;
+
+    let mut pcms = vec![
+        ("default", SND_PCM_STREAM_PLAYBACK),
+        ("default", SND_PCM_STREAM_CAPTURE),
+    ];
+
+    if conf.get("pcm.playback").is_some() {
+        pcms.push(("playback", SND_PCM_STREAM_PLAYBACK));
+    }
+
+    if conf.get("pcm.capture").is_some() {
+        pcms.push(("capture", SND_PCM_STREAM_CAPTURE));
+    }
+
+    for (name, stream) in pcms {
+        let direction = if stream == SND_PCM_STREAM_PLAYBACK {
+            "Playback"
+        } else {
+            "Capture"
+        };
+
+        tree.resolve("pcm", name)
+            .map_err(|e| format!("alsa-lib could not resolve pcm.{name}: {e}"))?;
+
+        if open_devices {
+            tree.open_pcm(name, stream)
+                .map_err(|e| format!("alsa-lib could not open pcm.{name} for {direction}: {e}"))?;
+        }
+    }
+
+    // The PCMs those use by name, so that a typo is caught even if the
+    // devices aren't opened. hw:1,0 and the like resolve to pcm.hw.
+    for id in conf.ids("pcm") {
+        for slot in ["slave.pcm", "playback.pcm", "capture.pcm"] {
+            if let Some(Value::String(name)) = conf.get(&format!("pcm.{id}.{slot}")) {
+                tree.resolve("pcm", &name).map_err(|e| {
+                    format!("alsa-lib could not resolve {name}, the {slot} of pcm.{id}: {e}")
+                })?;
+            }
+        }
+    }
+
+    if conf.get("ctl.default").is_some() {
+        tree.resolve("ctl", "default")
+            .map_err(|e| format!("alsa-lib could not resolve ctl.default: {e}"))?;
+
+        if open_devices {
+            tree.open_ctl("default")
+                .map_err(|e| format!("alsa-lib could not open ctl.default: {e}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+// The same alsa.conf alsa-lib itself starts from, ALSA_CONFIG_DIR is honoured.
+fn alsa_conf_path() -> PathBuf {
+    let dir = unsafe { CStr::from_ptr(snd_config_topdir()) };
+
+    PathBuf::from(dir.to_string_lossy().as_ref()).join(ALSA_CONF_FILE_NAME)
+}
+
+pub fn check(result: c_int) -> Result<(), String> {
+    if result < 0 {
+        Err(io::Error::from_raw_os_error(-result).to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn c_string(value: &str) -> Result<CString, String> {
+    CString::new(value).map_err(|_| format!("{value} contains a NUL character"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejection(config: &str, open_devices: bool) -> String {
+        validate_asound_conf(config, open_devices).unwrap_err()
+    }
+
+    #[test]
+    fn names_alsa_conf_defines_pass() {
+        // What build_asound_conf generates is tested along with it.
+        for name in ["hw:CARD=DAC,DEV=0", "plughw:1,0", "null"] {
+            let config = format!("pcm.!default {{ type plug slave.pcm \"{name}\" }}");
+
+            assert_eq!(validate_asound_conf(&config, false), Ok(()), "{name}");
+        }
+    }
+
+    #[test]
+    fn syntax_errors_are_rejected() {
+        assert_eq!(
+            rejection("pcm.!default { type plug", false),
+            "It is not a valid configuration, line 1, column 25: Expected } before the end."
+        );
+    }
+
+    #[test]
+    fn unknown_pcms_are_rejected() {
+        assert_eq!(
+            rejection("pcm.!default { type plug slave.pcm nosuchpcm }", false),
+            "alsa-lib could not resolve nosuchpcm, the slave.pcm of pcm.default: No such file or directory (os error 2)"
+        );
+
+        assert!(rejection(
+            "pcm.!default { type asym playback.pcm \"nosuchpcm\" }",
+            false
+        )
+        .starts_with("alsa-lib could not resolve nosuchpcm, the playback.pcm of pcm.default"));
+    }
+
+    #[test]
+    fn unknown_cards_are_rejected_when_opened() {
+        let config = "pcm.!default { type plug slave.pcm { type hw card NoSuchCard } }";
+
+        // Only opening it tells.
+        assert_eq!(validate_asound_conf(config, false), Ok(()));
+
+        assert!(rejection(config, true)
+            .starts_with("alsa-lib could not open pcm.default for Playback:"));
+    }
+}